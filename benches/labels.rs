@@ -0,0 +1,114 @@
+//! How long it takes to lay out the station names over dense Tokyo at a high zoom level, with and
+//! without the glyph cache. Run with `cargo bench --bench labels`.
+
+use std::time::{Duration, Instant};
+use the_sprawl::{
+    data::{load_network, Network, Strictness},
+    fonts::{FontManager, StationLabel},
+    map::{Degree, MapCoord, MapFrame, Viewport},
+    tile::{Tile, TileCounts},
+};
+
+/// Run `f` a number of times and return the average time per run
+fn bench<F: FnMut()>(name: &str, iterations: u32, mut f: F) -> Duration {
+    // Warm up
+    f();
+
+    let start = Instant::now();
+    for _ in 0..iterations {
+        f();
+    }
+    let average = start.elapsed() / iterations;
+
+    println!("{:<40} {:>10.4} ms", name, average.as_secs_f64() * 1000.0);
+    average
+}
+
+/// The names of the stations visible in `map_frame`, and their tiles
+fn station_labels<'a>(
+    network: &'a Network,
+    map_frame: &MapFrame,
+) -> (Vec<StationLabel<'a>>, Vec<Tile>) {
+    let stations: Vec<StationLabel> = network
+        .groups
+        .values()
+        .filter(|group| map_frame.is_visible(group.coord))
+        .map(|group| StationLabel {
+            tile: map_frame.get_tile(group.coord),
+            name: group.name.as_str().into(),
+            group_id: group.id,
+            priority: group.members.len(),
+        })
+        .collect();
+    let tiles = stations.iter().map(|station| station.tile).collect();
+    (stations, tiles)
+}
+
+fn main() {
+    let network = load_network(None, Strictness::Strict).unwrap().data;
+
+    let mut map_frame = MapFrame::default();
+    map_frame.resize(Viewport {
+        width: 800,
+        height: 600,
+    });
+    let tokyo = MapCoord {
+        long: 139.73.into(),
+        lat: 35.68.into(),
+    };
+    let map_frame = map_frame.centered_on(tokyo, Degree(0.03));
+    let (stations, station_tiles) = station_labels(&network, &map_frame);
+    println!("{} stations in view", stations.len());
+
+    // Names are placed as if there were no tracks in view, which only changes which side they go on
+    let no_tracks = TileCounts::default();
+
+    // Panning moves every station, but leaves the names the same size
+    let mut panned = map_frame.clone();
+    let shift = panned.get_degrees_per_pixel().0;
+    let mut direction = 1.0;
+    let mut pan = |font_manager: &mut FontManager| {
+        panned.upper_left.x += shift * Degree(direction);
+        panned.lower_right.x += shift * Degree(direction);
+        direction = -direction;
+        let (stations, station_tiles) = station_labels(&network, &panned);
+        font_manager.get_font_tiles(&panned, &stations, &station_tiles, &no_tracks);
+    };
+
+    // Zooming back and forth between two sizes goes through the same quantized sizes each time
+    let zoom_frames: Vec<MapFrame> = (0..30)
+        .map(|i| {
+            let height = 0.03 - 0.01 * (i as f32 / 30.0);
+            map_frame.centered_on(tokyo, Degree(height))
+        })
+        .collect();
+    let mut frame = 0;
+    let mut zoom = |font_manager: &mut FontManager| {
+        let zoomed = &zoom_frames[frame % zoom_frames.len()];
+        frame += 1;
+        font_manager.get_font_tiles(zoomed, &stations, &station_tiles, &no_tracks);
+    };
+
+    // Without the cache, every name is rasterized again each frame, the way it was before there
+    // was a cache
+    let mut uncached = FontManager::new().unwrap();
+    uncached.set_glyph_cache_size(0);
+    let panning_uncached = bench("tokyo 800x600 labels panning uncached", 20, || {
+        pan(&mut uncached)
+    });
+    let zooming_uncached = bench("tokyo 800x600 labels zooming uncached", 60, || {
+        zoom(&mut uncached)
+    });
+
+    let mut cached = FontManager::new().unwrap();
+    let panning = bench("tokyo 800x600 labels panning", 20, || pan(&mut cached));
+    let zooming = bench("tokyo 800x600 labels zooming", 60, || zoom(&mut cached));
+
+    println!(
+        "panning is {:.1}x and zooming {:.1}x faster with the cache, which holds {} names in {} kB",
+        panning_uncached.as_secs_f64() / panning.as_secs_f64(),
+        zooming_uncached.as_secs_f64() / zooming.as_secs_f64(),
+        cached.glyph_cache().len(),
+        cached.glyph_cache().bytes() / 1024
+    );
+}
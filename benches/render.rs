@@ -0,0 +1,86 @@
+//! Frame time for the main drawing paths, at the default zoom (all of Japan) and zoomed into
+//! central Tokyo, plus looking up the station under the mouse. Run with
+//! `cargo bench --bench render`.
+
+use std::time::{Duration, Instant};
+use the_sprawl::{
+    data::{load_network, Strictness},
+    map::{MapCoord, MapFrame, Viewport},
+    world::World,
+};
+
+/// Run `f` a number of times and print the average time per run
+fn bench<F: FnMut()>(name: &str, iterations: u32, mut f: F) {
+    // Warm up
+    f();
+
+    let start = Instant::now();
+    for _ in 0..iterations {
+        f();
+    }
+    let average = start.elapsed() / iterations;
+
+    println!("{:<36} {:>10.4} ms", name, average.as_secs_f64() * 1000.0);
+}
+
+fn main() {
+    let network = load_network(None, Strictness::Strict).unwrap().data;
+    let mut world = World::new(network, 0);
+
+    // Get some trains onto the map
+    world.step_frames(600);
+
+    let japan = MapFrame::default();
+    let tokyo = MapFrame::new(
+        MapCoord {
+            long: 139.60.into(),
+            lat: 35.75.into(),
+        },
+        MapCoord {
+            long: 139.85.into(),
+            lat: 35.60.into(),
+        },
+        Viewport::default(),
+    );
+
+    for (name, map_frame) in &[("japan", japan), ("tokyo", tokyo)] {
+        for &(width, height) in &[(200, 150), (800, 600)] {
+            let mut map_frame = map_frame.clone();
+            map_frame.viewport = Viewport { width, height };
+            world.set_map_frame(map_frame);
+
+            let mut buffer = vec![0; width as usize * height as usize * 4];
+            bench(&format!("{} {}x{} draw", name, width, height), 100, || {
+                world.draw(&mut buffer)
+            });
+
+            // Every pan recalculates the base map the next time it's drawn
+            let mut direction = 1;
+            bench(&format!("{} {}x{} pan", name, width, height), 50, || {
+                world.pan(direction, 0);
+                world.draw(&mut buffer);
+                direction = -direction;
+            });
+
+            // A frame of zooming in or out, which changes the size of everything
+            let middle = (width as isize / 2, height as isize / 2);
+            bench(&format!("{} {}x{} zoom", name, width, height), 50, || {
+                world.zoom(middle, direction as f32);
+                world.update(&Duration::from_millis(16));
+                world.draw(&mut buffer);
+                direction = -direction;
+            });
+
+            // What the mouse is pointing at, sweeping across the screen
+            let mut x = 0;
+            bench(
+                &format!("{} {}x{} nearest", name, width, height),
+                1000,
+                || {
+                    x = (x + 7) % width as isize;
+                    world.nearest_station((x, height as isize / 2));
+                },
+            );
+        }
+    }
+}
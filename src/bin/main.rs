@@ -2,30 +2,486 @@
 #![forbid(unsafe_code)]
 
 use pixels::{Error, Pixels, SurfaceTexture};
-use std::time::Instant;
+use std::{env, path::PathBuf, process, time::Instant};
+use the_sprawl::clock::{SimClock, MAX_SPEED, MIN_SPEED};
 use the_sprawl::constants::*;
-use the_sprawl::data;
+use the_sprawl::data::{self, DataError, Loaded, Strictness};
+use the_sprawl::effect::Routing;
+use the_sprawl::fonts::FontManager;
+use the_sprawl::geometry;
+use the_sprawl::gtfs::{self, ServiceChoice};
+use the_sprawl::map::{Degree, MapCoord, MapFrame, Viewport};
+use the_sprawl::names::Language;
+use the_sprawl::projection;
+use the_sprawl::schedule::{SimTime, Timetable};
+use the_sprawl::snapshot;
 use the_sprawl::world::World;
 use winit::dpi::{LogicalPosition, LogicalSize, PhysicalSize};
 use winit::event::{Event, VirtualKeyCode};
 use winit::event_loop::{ControlFlow, EventLoop};
-use winit_input_helper::WinitInputHelper;
+use winit_input_helper::{TextChar, WinitInputHelper};
+
+const TITLE: &str = "The Sprawl";
+
+/// How far, in pixels, the mouse can move while the button is held down and still count as a
+/// click rather than a drag
+const MAX_CLICK_DRAG: isize = 2;
+
+const USAGE: &str = "Usage: the-sprawl [OPTIONS]
+
+Options:
+    --data-dir DIR    Load stations.csv and join.csv (and line.csv and company.csv, if present)
+                      from DIR instead of the built-in data
+    --gtfs PATH       Load the network from a GTFS feed instead, either a zip file or a directory
+                      holding its files
+    --service ID      Run trains to the trips of the GTFS service with this service_id (default:
+                      the service with the most trips)
+    --date DATE       Run trains to the trips of the GTFS services running on DATE, like
+                      2024-04-01, going by the feed's calendar
+    --geometry FILE   Draw tracks along the paths in FILE, a GeoJSON FeatureCollection of
+                      LineStrings with station_cd1 and station_cd2 properties, instead of straight
+                      lines. Tracks in a GTFS feed with shapes follow those by default.
+    --names FILE      Load the stations' names in romaji from FILE, a CSV file with station_cd and
+                      station_name_r columns, like a full ekidata station list. Otherwise stations
+                      with a reading in kana are written in romaji from that.
+    --language NAME   Which names stations are shown by: `japanese`, `romaji` or `both`
+                      (default: japanese)
+    --font FILES      Draw text with the TrueType or OpenType fonts in FILES, a list separated like
+                      PATH (by `:`, or `;` on Windows). Each character is drawn with the first font
+                      that has it, and the built-in font is always tried last. Can be given more
+                      than once, adding to the end of the list.
+    --lenient         Skip rows in the data files that can't be loaded instead of exiting
+    --seed N          Seed for the simulation's random number generator. Runs with the same seed
+                      play out the same way. A random seed is used and printed if this isn't given.
+    --start-time TIME Simulated time of day to start at, like 7:30 (default: 07:00)
+    --speed N         How many times faster than real time the simulated clock runs, from 1 to
+                      3600 (default: 600)
+    --routing MODE    How trains without a timetable pick their route: `popularity` spreads them
+                      out over less visited stations, `distance` takes the shortest way along the
+                      tracks (default: popularity)
+    --projection NAME How the map is flattened onto the screen: `equirectangular`, `mercator` or
+                      `transverse-mercator` (default: equirectangular)
+    -h, --help        Print this message
+
+Timetable options:
+    --schedule        Run trains on every line to a generated timetable, instead of sending them
+                      between random stations. With --gtfs, the feed's own timetable is used.
+    --stop-times FILE Run trains to the timetable in FILE, a GTFS stop_times.txt whose stop_id
+                      column holds station IDs
+
+Snapshot options:
+    --snapshot FILE   Render a single frame to FILE as a PNG and exit, without opening a window
+    --size WxH        Size of the snapshot in pixels (default: 200x150)
+    --frame L,T,R,B   Longitudes and latitudes of the area to render, fitted to the snapshot
+                      without stretching (default: the starting view at its usual zoom, which
+                      shows all of Japan, or all of the feed with --gtfs)
+    --frames N        Run the simulation for N frames before rendering (default: 0)
+
+Mouse:
+    Drag              Pan the map. Letting go while it's moving flings it.
+    Wheel             Zoom in or out
+    Click             Show the details of a station, or hide them
+
+Keys:
+    Space             Pause or resume the clock
+    .                 Step forward one frame while paused
+    + / -             Speed the clock up or slow it down
+    P                 Switch to the next projection
+    L                 Show station names in Japanese, romaji or both
+    /                 Search for a station by name or reading. Up and Down pick a match, Enter
+                      goes there, and Escape closes the search.
+    O / D             Plan a route from or to the selected station
+    M                 Plan the route by the fewest stops or the shortest distance
+    T                 Send a train along the planned route
+    C                 Clear the planned route";
+
+/// Command-line options
+#[derive(Default)]
+struct Args {
+    /// Directory to load the station data from. The embedded data is used if this isn't given.
+    data_dir: Option<PathBuf>,
+
+    /// GTFS feed to load the network from instead
+    gtfs: Option<PathBuf>,
+
+    /// Which of the GTFS feed's services to run trains to
+    service: ServiceChoice,
+
+    /// GeoJSON file with the paths of the tracks
+    geometry: Option<PathBuf>,
+
+    /// CSV file with the stations' names in romaji
+    names: Option<PathBuf>,
+
+    /// Which names stations are shown by
+    language: Language,
+
+    /// Font files to draw text with, before the built-in font
+    fonts: Vec<PathBuf>,
+
+    /// Whether to skip bad rows in the data files
+    lenient: bool,
+
+    /// Seed for the simulation
+    seed: Option<u64>,
+
+    /// Whether to run trains to a generated timetable
+    schedule: bool,
+
+    /// Timetable to run trains to
+    stop_times: Option<PathBuf>,
+
+    /// Time of day to start at
+    start_time: Option<SimTime>,
+
+    /// How fast the simulated clock runs
+    speed: Option<u32>,
+
+    /// How trains pick their routes
+    routing: Routing,
+
+    /// How the map is flattened onto the screen, by name
+    projection: Option<String>,
+
+    /// Where to write a snapshot instead of opening a window
+    snapshot: Option<PathBuf>,
+
+    /// Snapshot size in pixels
+    size: Option<(u32, u32)>,
+
+    /// Upper left and lower right corners of the area to render in the snapshot
+    frame: Option<(MapCoord, MapCoord)>,
+
+    /// Number of simulation frames to run before taking the snapshot
+    frames: u32,
+}
+
+impl Args {
+    fn parse() -> Result<Self, String> {
+        let mut args = Args::default();
+        let mut iter = env::args().skip(1);
+
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "--data-dir" => {
+                    let dir = iter.next().ok_or("--data-dir requires a directory")?;
+                    args.data_dir = Some(PathBuf::from(dir));
+                }
+                "--gtfs" => {
+                    let path = iter.next().ok_or("--gtfs requires a file or directory")?;
+                    args.gtfs = Some(PathBuf::from(path));
+                }
+                "--service" => {
+                    let id = iter.next().ok_or("--service requires a service ID")?;
+                    if args.service != ServiceChoice::Busiest {
+                        return Err("--service and --date can't be used together".to_owned());
+                    }
+                    args.service = ServiceChoice::Id(id);
+                }
+                "--date" => {
+                    let date = iter.next().ok_or("--date requires a date")?;
+                    if args.service != ServiceChoice::Busiest {
+                        return Err("--service and --date can't be used together".to_owned());
+                    }
+                    args.service = ServiceChoice::Date(
+                        date.parse()
+                            .map_err(|_| format!("invalid date: {}", date))?,
+                    );
+                }
+                "--geometry" => {
+                    let file = iter.next().ok_or("--geometry requires a file name")?;
+                    args.geometry = Some(PathBuf::from(file));
+                }
+                "--names" => {
+                    let file = iter.next().ok_or("--names requires a file name")?;
+                    args.names = Some(PathBuf::from(file));
+                }
+                "--language" => {
+                    let language = iter.next().ok_or("--language requires a name")?;
+                    args.language = language
+                        .parse()
+                        .map_err(|_| format!("invalid language: {}", language))?;
+                }
+                "--font" => {
+                    let files = iter.next().ok_or("--font requires a file name")?;
+                    args.fonts.extend(env::split_paths(&files));
+                }
+                "--lenient" => args.lenient = true,
+                "--seed" => {
+                    let seed = iter.next().ok_or("--seed requires a number")?;
+                    args.seed = Some(
+                        seed.parse()
+                            .map_err(|_| format!("invalid seed: {}", seed))?,
+                    );
+                }
+                "--schedule" => args.schedule = true,
+                "--stop-times" => {
+                    let file = iter.next().ok_or("--stop-times requires a file name")?;
+                    args.stop_times = Some(PathBuf::from(file));
+                }
+                "--start-time" => {
+                    let time = iter.next().ok_or("--start-time requires a time")?;
+                    args.start_time = Some(
+                        time.parse()
+                            .map_err(|_| format!("invalid start time: {}", time))?,
+                    );
+                }
+                "--speed" => {
+                    let speed = iter.next().ok_or("--speed requires a number")?;
+                    args.speed = Some(
+                        speed
+                            .parse()
+                            .ok()
+                            .filter(|s| (MIN_SPEED..=MAX_SPEED).contains(s))
+                            .ok_or_else(|| format!("invalid speed: {}", speed))?,
+                    );
+                }
+                "--routing" => {
+                    let routing = iter.next().ok_or("--routing requires a mode")?;
+                    args.routing = routing
+                        .parse()
+                        .map_err(|_| format!("invalid routing mode: {}", routing))?;
+                }
+                "--projection" => {
+                    let name = iter.next().ok_or("--projection requires a name")?;
+                    if !projection::projection_names().any(|n| n == name) {
+                        return Err(format!(
+                            "invalid projection: {} (expected one of: {})",
+                            name,
+                            projection::projection_names()
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        ));
+                    }
+                    args.projection = Some(name);
+                }
+                "--snapshot" => {
+                    let file = iter.next().ok_or("--snapshot requires a file name")?;
+                    args.snapshot = Some(PathBuf::from(file));
+                }
+                "--size" => {
+                    let size = iter.next().ok_or("--size requires a size")?;
+                    args.size = Some(parse_size(&size)?);
+                }
+                "--frame" => {
+                    let frame = iter.next().ok_or("--frame requires four coordinates")?;
+                    args.frame = Some(parse_frame(&frame)?);
+                }
+                "--frames" => {
+                    let frames = iter.next().ok_or("--frames requires a number")?;
+                    args.frames = frames
+                        .parse()
+                        .map_err(|_| format!("invalid frame count: {}", frames))?;
+                }
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    process::exit(0);
+                }
+                other => return Err(format!("unrecognized argument: {}", other)),
+            }
+        }
+
+        if args.gtfs.is_some() && args.data_dir.is_some() {
+            return Err("--gtfs and --data-dir can't be used together".to_owned());
+        }
+        if args.gtfs.is_some() && args.stop_times.is_some() {
+            return Err("--gtfs and --stop-times can't be used together".to_owned());
+        }
+        if args.gtfs.is_none() && args.service != ServiceChoice::Busiest {
+            return Err("--service and --date can only be used with --gtfs".to_owned());
+        }
+
+        Ok(args)
+    }
+}
+
+/// Parse a size like `800x600`
+fn parse_size(size: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("invalid size: {}", size);
+    let mut parts = size.splitn(2, 'x');
+    let width: u32 = parts
+        .next()
+        .and_then(|w| w.parse().ok())
+        .ok_or_else(invalid)?;
+    let height: u32 = parts
+        .next()
+        .and_then(|h| h.parse().ok())
+        .ok_or_else(invalid)?;
+
+    if width == 0 || height == 0 {
+        return Err(invalid());
+    }
+
+    Ok((width, height))
+}
+
+/// Parse the corners of a map frame like `139.5,35.9,140.0,35.5` (left, top, right, bottom)
+fn parse_frame(frame: &str) -> Result<(MapCoord, MapCoord), String> {
+    let invalid = || format!("invalid frame: {}", frame);
+    let values = frame
+        .split(',')
+        .map(|v| v.trim().parse().map(Degree))
+        .collect::<Result<Vec<Degree>, _>>()
+        .map_err(|_| invalid())?;
+
+    match values.as_slice() {
+        [left, top, right, bottom] if left < right && bottom < top => Ok((
+            MapCoord {
+                long: *left,
+                lat: *top,
+            },
+            MapCoord {
+                long: *right,
+                lat: *bottom,
+            },
+        )),
+        _ => Err(invalid()),
+    }
+}
 
 fn main() -> Result<(), Error> {
+    let args = Args::parse().unwrap_or_else(|e| {
+        eprintln!("error: {}\n\n{}", e, USAGE);
+        process::exit(2);
+    });
+
+    // Load the data before opening the window, so that a bad path doesn't flash an empty window
+    let strictness = if args.lenient {
+        Strictness::Lenient
+    } else {
+        Strictness::Strict
+    };
+    let (mut network, feed_timetable) = match &args.gtfs {
+        Some(path) => {
+            let feed = report(gtfs::load_gtfs(path, &args.service, strictness));
+            if feed.services.iter().any(|id| !id.is_empty()) {
+                eprintln!("services: {}", feed.services.join(", "));
+            }
+            (feed.network, Some(feed.timetable))
+        }
+        None => (
+            report(data::load_network(args.data_dir.as_deref(), strictness)),
+            None,
+        ),
+    };
+
+    if let Some(path) = &args.geometry {
+        let geometry = report(geometry::load_geojson_from_path(path, &network, strictness));
+        network.add_geometry(geometry);
+    }
+
+    if let Some(path) = &args.names {
+        let names = report(data::load_romaji_from_path(path, strictness));
+        network.add_romaji_names(names);
+    }
+
+    let font_manager = FontManager::with_fonts(&args.fonts).unwrap_or_else(|e| {
+        eprintln!("error: couldn't load font {}", e);
+        process::exit(1);
+    });
+
+    // A feed from somewhere other than Japan should start out in view
+    let initial_frame = if args.gtfs.is_some() {
+        network
+            .bounds()
+            .map(|(upper_left, lower_right)| {
+                MapFrame::around(upper_left, lower_right, Viewport::default())
+            })
+            .unwrap_or_default()
+    } else {
+        MapFrame::default()
+    };
+
+    let seed = args.seed.unwrap_or_else(|| {
+        let seed = rand::random();
+        eprintln!("seed: {}", seed);
+        seed
+    });
+
+    let timetable = if let Some(path) = &args.stop_times {
+        Some(report(data::load_stop_times_from_path(
+            path, &network, strictness,
+        )))
+    } else if args.schedule {
+        Some(feed_timetable.unwrap_or_else(|| Timetable::generate(&network)))
+    } else {
+        None
+    };
+
+    // Without any names in romaji, stations keep going by their Japanese names
+    let has_romaji = network.has_romaji();
+    warn_without_romaji(has_romaji, args.language);
+
+    // Transverse Mercator is centered on the network, wherever in the world it is
+    let central_meridian = projection::central_meridian(network.bounds());
+    let projection = args
+        .projection
+        .as_deref()
+        .and_then(|name| projection::projection_by_name(name, central_meridian));
+
+    let mut world = World::with_font_manager(network, seed, font_manager);
+    world.set_map_frame(initial_frame.clone());
+    if let Some(projection) = &projection {
+        world.set_projection(projection.clone());
+    }
+    if let Some(time) = args.start_time {
+        world.clock_mut().set_time(time);
+    }
+    if let Some(speed) = args.speed {
+        world.clock_mut().set_speed(speed);
+    }
+    world.set_routing(args.routing);
+    world.set_language(args.language);
+    if let Some(timetable) = timetable {
+        world.set_timetable(timetable);
+    }
+
+    if let Some(path) = &args.snapshot {
+        let (width, height) = args
+            .size
+            .unwrap_or((DEFAULT_SCREEN_WIDTH, DEFAULT_SCREEN_HEIGHT));
+
+        // The snapshot shows the whole area asked for, or the starting view around its center at
+        // its usual zoom, without stretching either to the snapshot's shape
+        let viewport = Viewport { width, height };
+        let frame = match args.frame {
+            Some((upper_left, lower_right)) => MapFrame::around(upper_left, lower_right, viewport),
+            None => {
+                let mut frame = initial_frame;
+                frame.resize_around_center(viewport);
+                frame
+            }
+        };
+        world.set_map_frame(frame);
+        if let Some(projection) = projection {
+            world.set_projection(projection);
+        }
+        world.step_frames(args.frames);
+
+        if let Err(e) = snapshot::save_png(&mut world, width, height, path) {
+            eprintln!("error: couldn't write {}: {}", path.display(), e);
+            process::exit(1);
+        }
+        return Ok(());
+    }
+
     let event_loop = EventLoop::new();
     let mut input = WinitInputHelper::new();
-    let (window, p_width, p_height, mut _hidpi_factor) = create_window("The Sprawl", &event_loop);
+    let (window, p_width, p_height, mut _hidpi_factor) = create_window(TITLE, &event_loop);
+    let mut title = String::new();
+
+    // Where the left mouse button was last pressed, while it's held down
+    let mut click_cell: Option<(isize, isize)> = None;
 
     let surface_texture = SurfaceTexture::new(p_width, p_height, &window);
 
-    let all_stations = data::load_stations();
-    let connections = data::load_connections();
     let mut time = Instant::now();
-    let mut world = World::new(all_stations, connections);
 
-    world.init();
+    let mut pixels = Pixels::new(DEFAULT_SCREEN_WIDTH, DEFAULT_SCREEN_HEIGHT, surface_texture)?;
 
-    let mut pixels = Pixels::new(SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32, surface_texture)?;
+    // How many physical pixels each map pixel takes up on the screen. This stays fixed when the
+    // window is resized, so that resizing shows more or less of the map.
+    let pixel_scale = p_width as f64 / DEFAULT_SCREEN_WIDTH as f64;
 
     event_loop.run(move |event, _, control_flow| {
         // Draw the current frame
@@ -43,17 +499,108 @@ fn main() -> Result<(), Error> {
 
         window.request_redraw();
 
+        // Show the time in the title bar, only touching the window when it changes
+        let new_title = window_title(world.clock());
+        if new_title != title {
+            window.set_title(&new_title);
+            title = new_title;
+        }
+
         // Handle input events
         if input.update(&event) {
-            // Close events
-            if input.key_pressed(VirtualKeyCode::Escape) || input.quit() {
+            // Close events. Escape only closes the search box while it's open.
+            if input.quit()
+                || (input.key_pressed(VirtualKeyCode::Escape) && world.search().is_none())
+            {
                 *control_flow = ControlFlow::Exit;
                 return;
             }
 
-            // Resize the window
-            if let Some(size) = input.window_resized() {
-                pixels.resize(size.width, size.height);
+            if world.search().is_some() {
+                // The search box takes all the typing until it's confirmed or closed
+                handle_search_keys(&mut world, &input);
+            } else {
+                // Clock controls
+                if input.key_pressed(VirtualKeyCode::Space) {
+                    world.clock_mut().toggle_pause();
+                }
+                if input.key_pressed(VirtualKeyCode::Period) && world.clock().is_paused() {
+                    world.step_frames(1);
+                }
+                if input.key_pressed(VirtualKeyCode::Equals)
+                    || input.key_pressed(VirtualKeyCode::Add)
+                {
+                    world.clock_mut().faster();
+                }
+                if input.key_pressed(VirtualKeyCode::Minus)
+                    || input.key_pressed(VirtualKeyCode::Subtract)
+                {
+                    world.clock_mut().slower();
+                }
+
+                if input.key_pressed(VirtualKeyCode::P) {
+                    world.next_projection();
+                }
+
+                if input.key_pressed(VirtualKeyCode::L) {
+                    world.set_language(world.language().next());
+
+                    // Only warn once each time around, rather than for both romaji and both
+                    if world.language() == Language::Romaji {
+                        warn_without_romaji(has_romaji, world.language());
+                    }
+                }
+
+                if input.key_pressed(VirtualKeyCode::Slash) {
+                    world.open_search();
+                }
+
+                // Route planning, with the ends picked by selecting stations
+                if input.key_pressed(VirtualKeyCode::O) {
+                    world.set_route_origin(world.selected_station());
+                }
+                if input.key_pressed(VirtualKeyCode::D) {
+                    world.set_route_destination(world.selected_station());
+                }
+                if input.key_pressed(VirtualKeyCode::M) {
+                    world.set_route_metric(world.route_plan().metric.toggled());
+                }
+                if input.key_pressed(VirtualKeyCode::T) {
+                    world.send_train_along_route();
+                }
+                if input.key_pressed(VirtualKeyCode::C) {
+                    world.clear_route();
+                }
+            }
+
+            // Resize the window, along with the pixel buffer and the world's viewport. A size of
+            // 0 means the window was minimized, so there's nothing to draw.
+            if let Some(size) = input
+                .window_resized()
+                .filter(|s| s.width > 0 && s.height > 0)
+            {
+                let viewport = Viewport {
+                    width: ((size.width as f64 / pixel_scale).round() as u32).max(1),
+                    height: ((size.height as f64 / pixel_scale).round() as u32).max(1),
+                };
+
+                if viewport == world.map_frame().viewport {
+                    pixels.resize(size.width, size.height);
+                } else {
+                    // The pixel buffer can't change size, so it has to be recreated
+                    let surface_texture = SurfaceTexture::new(size.width, size.height, &window);
+                    match Pixels::new(viewport.width, viewport.height, surface_texture) {
+                        Ok(new_pixels) => {
+                            pixels = new_pixels;
+                            world.resize(viewport);
+                        }
+                        Err(e) => {
+                            eprintln!("couldn't resize the pixel buffer: {}", e);
+                            *control_flow = ControlFlow::Exit;
+                            return;
+                        }
+                    }
+                }
             }
 
             let (mouse_cell, mouse_prev_cell) = input
@@ -78,9 +625,25 @@ fn main() -> Result<(), Error> {
                 })
                 .unwrap_or_default();
 
-            // if input.mouse_pressed(0) {
-            //     world.inspect(mouse_cell);
-            // }
+            // A click that doesn't turn into a drag selects the station under the mouse, or
+            // closes the details of the selected one
+            if input.mouse_pressed(0) {
+                click_cell = Some(mouse_cell);
+            }
+            if input.mouse_released(0) {
+                if click_cell.is_some_and(|(x, y)| {
+                    (mouse_cell.0 - x).abs().max((mouse_cell.1 - y).abs()) <= MAX_CLICK_DRAG
+                }) {
+                    world.select(mouse_cell);
+                }
+                click_cell = None;
+
+                // Letting go mid-drag flings the map
+                world.end_pan();
+            }
+            if !input.mouse_held(0) {
+                world.hover(mouse_cell);
+            }
 
             if input.mouse_held(0) {
                 let diff_x = mouse_cell.0 - mouse_prev_cell.0;
@@ -102,6 +665,88 @@ fn main() -> Result<(), Error> {
     });
 }
 
+/// Edit, move through or confirm the search with this frame's keyboard input
+fn handle_search_keys(world: &mut World, input: &WinitInputHelper) {
+    if input.key_pressed(VirtualKeyCode::Escape) {
+        world.close_search();
+        return;
+    }
+    if input.key_pressed(VirtualKeyCode::Return) || input.key_pressed(VirtualKeyCode::NumpadEnter) {
+        world.confirm_search();
+        return;
+    }
+
+    if input.key_pressed(VirtualKeyCode::Down) {
+        world.move_search_choice(1);
+    }
+    if input.key_pressed(VirtualKeyCode::Up) {
+        world.move_search_choice(-1);
+    }
+
+    let text = input.text();
+    if !text.is_empty() {
+        let mut query = world
+            .search()
+            .map(|search| search.query().to_owned())
+            .unwrap_or_default();
+        for c in text {
+            match c {
+                // Enter and the like come through as characters too
+                TextChar::Char(c) if !c.is_control() => query.push(c),
+                TextChar::Back => {
+                    query.pop();
+                }
+                _ => {}
+            }
+        }
+        world.set_search_query(query);
+    }
+}
+
+/// The window title, with the simulated time of day and how fast it's passing
+fn window_title(clock: &SimClock) -> String {
+    let time = clock.time();
+    let state = if clock.is_paused() {
+        "paused".to_owned()
+    } else {
+        format!("{}x", clock.speed())
+    };
+
+    format!(
+        "{} - {:02}:{:02} ({})",
+        TITLE,
+        time.hours() % 24,
+        time.minutes(),
+        state
+    )
+}
+
+/// Print any warnings from loading a data file, or exit if it couldn't be loaded at all
+fn report<T>(loaded: Result<Loaded<T>, DataError>) -> T {
+    match loaded {
+        Ok(loaded) => {
+            for warning in &loaded.warnings {
+                eprintln!("warning: skipped {}", warning);
+            }
+            loaded.data
+        }
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Warn that names in romaji were asked for when no station has one
+fn warn_without_romaji(has_romaji: bool, language: Language) {
+    if !has_romaji && language != Language::Japanese {
+        eprintln!(
+            "warning: no station has a name in romaji or a reading in kana, so they're shown in \
+             Japanese; load names with --names, or use a full ekidata station list"
+        );
+    }
+}
+
 // This is taken from the Pixels example at https://github.com/parasyte/pixels/tree/master/examples/conway
 fn create_window(
     title: &str,
@@ -111,13 +756,13 @@ fn create_window(
     let window = winit::window::WindowBuilder::new()
         .with_visible(false)
         .with_title(title)
-        .build(&event_loop)
+        .build(event_loop)
         .unwrap();
     let hidpi_factor = window.scale_factor();
 
     // Get dimensions
-    let width = SCREEN_WIDTH as f64;
-    let height = SCREEN_HEIGHT as f64;
+    let width = DEFAULT_SCREEN_WIDTH as f64;
+    let height = DEFAULT_SCREEN_HEIGHT as f64;
     let (monitor_width, monitor_height) = {
         let size = window.current_monitor().size();
         (
@@ -0,0 +1,343 @@
+//! Moving the view smoothly: easing from one map frame to another, and drifting on for a moment
+//! after the map is flung
+
+use crate::map::MapFrame;
+use std::time::Duration;
+
+/// How long a turn of the mouse wheel takes to zoom all the way
+const ZOOM_DURATION: Duration = Duration::from_millis(250);
+
+/// How long `World::fly_to` takes to get there
+const FLIGHT_DURATION: Duration = Duration::from_millis(800);
+
+/// How long it takes a flung map to slow down, in seconds. Its speed drops by a factor of e every
+/// this many seconds.
+const FLING_TIME_CONSTANT: f32 = 0.325;
+
+/// How fast, in pixels per second, the map has to be moving when it's let go of to drift on.
+/// Anything slower was meant to stop where it is.
+const MIN_FLING_SPEED: f32 = 60.0;
+
+/// How slow, in pixels per second, a drifting map gets before it stops
+const STOP_SPEED: f32 = 8.0;
+
+/// How quickly the measured drag speed follows the mouse, in seconds. Smooths out frames where
+/// the mouse happened not to move.
+const DRAG_SMOOTHING: f32 = 0.04;
+
+/// Starts fast and slows down toward the end
+pub fn ease_out_cubic(t: f32) -> f32 {
+    1.0 - (1.0 - t).powi(3)
+}
+
+/// Starts slow, speeds up, and slows down again toward the end
+pub fn ease_in_out_cubic(t: f32) -> f32 {
+    if t < 0.5 {
+        4.0 * t.powi(3)
+    } else {
+        1.0 - (-2.0 * t + 2.0).powi(3) / 2.0
+    }
+}
+
+/// The kinds of animated moves, which each take their own time and ease in their own way
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Animation {
+    /// Zooming with the mouse wheel: quick, and slowing down toward the end
+    Zoom,
+
+    /// Going somewhere else on the map: slower, speeding up and then slowing down again
+    Flight,
+}
+
+impl Animation {
+    pub fn duration(self) -> Duration {
+        match self {
+            Animation::Zoom => ZOOM_DURATION,
+            Animation::Flight => FLIGHT_DURATION,
+        }
+    }
+
+    /// How far along the move is, from 0.0 to 1.0, given how far along the time is
+    pub fn ease(self, t: f32) -> f32 {
+        match self {
+            Animation::Zoom => ease_out_cubic(t),
+            Animation::Flight => ease_in_out_cubic(t),
+        }
+    }
+}
+
+/// An animated move from one map frame to another
+struct Tween {
+    animation: Animation,
+    from: MapFrame,
+    to: MapFrame,
+    elapsed: Duration,
+}
+
+/// The map drifting on after being flung
+struct Drift {
+    /// In pixels per second
+    velocity: (f32, f32),
+
+    /// The part of a pixel moved so far that hasn't been panned yet
+    remainder: (f32, f32),
+}
+
+/// How the camera moves the view
+#[derive(Clone)]
+pub enum CameraMove {
+    /// Show this frame instead
+    To(MapFrame),
+
+    /// Pan by this many pixels
+    Pan(isize, isize),
+}
+
+/// Moves the view on its own between frames: toward a target frame with easing, or onward after
+/// the map is let go of mid-drag
+#[derive(Default)]
+pub struct Camera {
+    tween: Option<Tween>,
+    drift: Option<Drift>,
+
+    /// Whether the map is being dragged
+    dragging: bool,
+
+    /// How far the map was dragged since the last update, in pixels
+    dragged: (f32, f32),
+
+    /// How fast the map's been dragged lately, in pixels per second
+    drag_velocity: (f32, f32),
+}
+
+impl Camera {
+    /// Move the view from `from` to `to`, replacing whatever move was under way
+    pub fn animate(&mut self, animation: Animation, from: MapFrame, to: MapFrame) {
+        self.drift = None;
+        self.tween = Some(Tween {
+            animation,
+            from,
+            to,
+            elapsed: Duration::default(),
+        });
+    }
+
+    /// Where the view is being moved to, if it's being moved by the given kind of animation
+    pub fn target(&self, animation: Animation) -> Option<&MapFrame> {
+        self.tween
+            .as_ref()
+            .filter(|tween| tween.animation == animation)
+            .map(|tween| &tween.to)
+    }
+
+    /// Whether the view is moving on its own
+    pub fn is_moving(&self) -> bool {
+        self.tween.is_some() || self.drift.is_some()
+    }
+
+    /// Stop moving the view, leaving it wherever it got to
+    pub fn stop(&mut self) {
+        self.tween = None;
+        self.drift = None;
+    }
+
+    /// The user dragged the map by this many pixels, which stops it moving on its own
+    pub fn drag(&mut self, diff_x: isize, diff_y: isize) {
+        self.stop();
+        self.dragging = true;
+        self.dragged.0 += diff_x as f32;
+        self.dragged.1 += diff_y as f32;
+    }
+
+    /// The user let go of the map. If it was being dragged fast enough, it drifts on in the same
+    /// direction and slows to a stop.
+    pub fn release(&mut self) {
+        let (velocity_x, velocity_y) = self.drag_velocity;
+        if self.dragging && velocity_x.hypot(velocity_y) >= MIN_FLING_SPEED {
+            self.drift = Some(Drift {
+                velocity: self.drag_velocity,
+                remainder: (0.0, 0.0),
+            });
+        }
+
+        self.dragging = false;
+        self.dragged = (0.0, 0.0);
+        self.drag_velocity = (0.0, 0.0);
+    }
+
+    /// Move on by `dt`. Returns how the view should change, if it should.
+    pub fn update(&mut self, dt: &Duration) -> Option<CameraMove> {
+        let seconds = dt.as_secs_f32();
+        if seconds <= 0.0 {
+            return None;
+        }
+
+        if self.dragging {
+            let weight = 1.0 - (-seconds / DRAG_SMOOTHING).exp();
+            let (dragged_x, dragged_y) = self.dragged;
+            let (velocity_x, velocity_y) = self.drag_velocity;
+            self.drag_velocity = (
+                velocity_x + (dragged_x / seconds - velocity_x) * weight,
+                velocity_y + (dragged_y / seconds - velocity_y) * weight,
+            );
+            self.dragged = (0.0, 0.0);
+        }
+
+        if let Some(tween) = &mut self.tween {
+            tween.elapsed += *dt;
+            let t = tween.elapsed.as_secs_f32() / tween.animation.duration().as_secs_f32();
+            return if t >= 1.0 {
+                self.tween.take().map(|tween| CameraMove::To(tween.to))
+            } else {
+                let frame = tween.from.interpolate(&tween.to, tween.animation.ease(t));
+                Some(CameraMove::To(frame))
+            };
+        }
+
+        let drift = self.drift.as_mut()?;
+        let friction = (-seconds / FLING_TIME_CONSTANT).exp();
+        drift.velocity.0 *= friction;
+        drift.velocity.1 *= friction;
+        if drift.velocity.0.hypot(drift.velocity.1) < STOP_SPEED {
+            self.drift = None;
+            return None;
+        }
+
+        // Only whole pixels are panned, so that the map isn't redrawn for moves too small to see
+        drift.remainder.0 += drift.velocity.0 * seconds;
+        drift.remainder.1 += drift.velocity.1 * seconds;
+        let (pan_x, pan_y) = (drift.remainder.0.trunc(), drift.remainder.1.trunc());
+        drift.remainder.0 -= pan_x;
+        drift.remainder.1 -= pan_y;
+
+        if pan_x == 0.0 && pan_y == 0.0 {
+            None
+        } else {
+            Some(CameraMove::Pan(pan_x as isize, pan_y as isize))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::{MapCoord, Viewport};
+
+    const FRAME: Duration = Duration::from_millis(16);
+
+    fn frame(long: f32, lat: f32, size: f32) -> MapFrame {
+        MapFrame::new(
+            MapCoord {
+                long: long.into(),
+                lat: (lat + size).into(),
+            },
+            MapCoord {
+                long: (long + size).into(),
+                lat: lat.into(),
+            },
+            Viewport {
+                width: 400,
+                height: 400,
+            },
+        )
+    }
+
+    /// A camera that's been dragged by `speed` pixels every frame for a while
+    fn dragged(speed: isize) -> Camera {
+        let mut camera = Camera::default();
+        for _ in 0..30 {
+            camera.drag(speed, 0);
+            assert!(camera.update(&FRAME).is_none());
+        }
+        camera
+    }
+
+    #[test]
+    fn tween_ends_exactly_on_its_target() {
+        let (from, to) = (frame(139.0, 35.0, 1.0), frame(140.0, 36.0, 0.5));
+        let mut camera = Camera::default();
+        camera.animate(Animation::Flight, from, to.clone());
+        assert!(camera.target(Animation::Flight).is_some());
+        assert!(camera.target(Animation::Zoom).is_none());
+
+        let half = Animation::Flight.duration() / 2;
+        match camera.update(&half) {
+            Some(CameraMove::To(frame)) => assert_ne!(frame.upper_left, to.upper_left),
+            _ => panic!("the view didn't move"),
+        }
+        assert!(camera.is_moving());
+
+        match camera.update(&half) {
+            Some(CameraMove::To(frame)) => {
+                assert_eq!(frame.upper_left, to.upper_left);
+                assert_eq!(frame.lower_right, to.lower_right);
+            }
+            _ => panic!("the view didn't move"),
+        }
+        assert!(!camera.is_moving());
+        assert!(camera.update(&FRAME).is_none());
+    }
+
+    #[test]
+    fn slow_release_stays_put() {
+        // 1 pixel every 16ms is 62.5 pixels per second, but only for the last frame
+        let mut camera = Camera::default();
+        camera.drag(1, 0);
+        assert!(camera.update(&FRAME).is_none());
+        camera.release();
+        assert!(!camera.is_moving());
+        assert!(camera.update(&FRAME).is_none());
+
+        // Much slower for a while
+        let mut camera = Camera::default();
+        for _ in 0..30 {
+            camera.drag(0, 0);
+            camera.update(&FRAME);
+        }
+        camera.drag(0, 0);
+        camera.release();
+        assert!(!camera.is_moving());
+    }
+
+    #[test]
+    fn drift_slows_to_a_stop() {
+        let mut camera = dragged(20);
+        camera.release();
+        assert!(camera.is_moving());
+
+        let mut pans = Vec::new();
+        for _ in 0..(5.0 / FRAME.as_secs_f32()) as usize {
+            match camera.update(&FRAME) {
+                Some(CameraMove::Pan(x, y)) => {
+                    assert_eq!(y, 0);
+                    pans.push(x);
+                }
+                Some(CameraMove::To(_)) => panic!("a drift moved to a frame"),
+                None => {}
+            }
+        }
+        assert!(!camera.is_moving());
+
+        // Onward in the direction it was flung, slower and slower
+        assert!(pans.iter().all(|x| *x > 0));
+        let (earlier, later) = pans.split_at(pans.len() / 2);
+        assert!(earlier.iter().sum::<isize>() > later.iter().sum::<isize>());
+        assert!(pans[0] > *pans.last().unwrap());
+    }
+
+    #[test]
+    fn drag_cancels_a_flight() {
+        let mut camera = Camera::default();
+        camera.animate(
+            Animation::Flight,
+            frame(139.0, 35.0, 1.0),
+            frame(140.0, 36.0, 0.5),
+        );
+        assert!(camera.update(&FRAME).is_some());
+
+        camera.drag(5, 5);
+        assert!(!camera.is_moving());
+        assert!(camera.target(Animation::Flight).is_none());
+        assert!(camera.update(&FRAME).is_none());
+    }
+}
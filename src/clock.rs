@@ -0,0 +1,239 @@
+use crate::schedule::{SimTime, ONE_DAY, SERVICE_DAY_START};
+
+/// The simulated time of day when the world is created
+pub const DEFAULT_START_TIME: SimTime = SimTime::from_hms(7, 0, 0);
+
+/// How many times faster than real time the clock runs, unless told otherwise
+pub const DEFAULT_SPEED: u32 = 600;
+
+pub const MIN_SPEED: u32 = 1;
+pub const MAX_SPEED: u32 = 3600;
+
+/// The speeds that `faster` and `slower` step through
+const SPEED_STEPS: [u32; 8] = [1, 10, 60, 300, 600, 1200, 1800, 3600];
+
+/// How many frames are simulated per second of real time
+const FRAMES_PER_SECOND: u32 = 60;
+
+/// The simulated time of day, and how fast it passes. Time moves on by one frame's worth every
+/// time the world steps, so the simulation stays deterministic no matter how fast frames are
+/// actually drawn.
+#[derive(Clone, Debug)]
+pub struct SimClock {
+    time: SimTime,
+    speed: u32,
+    paused: bool,
+
+    /// Leftover fractions of a millisecond from previous frames, in 1/60ths, so that slow speeds
+    /// don't drift
+    remainder: u32,
+}
+
+impl SimClock {
+    pub fn new(time: SimTime) -> Self {
+        Self {
+            time: time.in_service_day(),
+            speed: DEFAULT_SPEED,
+            paused: false,
+            remainder: 0,
+        }
+    }
+
+    /// The current time of day. This is always within the service day, so it runs from 4:00 up
+    /// to (but not including) 28:00.
+    pub fn time(&self) -> SimTime {
+        self.time
+    }
+
+    /// Jump to another time of day
+    pub fn set_time(&mut self, time: SimTime) {
+        self.time = time.in_service_day();
+        self.remainder = 0;
+    }
+
+    /// How many times faster than real time the clock runs
+    pub fn speed(&self) -> u32 {
+        self.speed
+    }
+
+    pub fn set_speed(&mut self, speed: u32) {
+        self.speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+    }
+
+    /// Change to the next faster of a handful of preset speeds
+    pub fn faster(&mut self) {
+        if let Some(speed) = SPEED_STEPS.iter().find(|s| **s > self.speed) {
+            self.speed = *speed;
+        }
+    }
+
+    /// Change to the next slower of a handful of preset speeds
+    pub fn slower(&mut self) {
+        if let Some(speed) = SPEED_STEPS.iter().rev().find(|s| **s < self.speed) {
+            self.speed = *speed;
+        }
+    }
+
+    /// How fast the clock is running compared to the default speed. Things that move a fixed
+    /// amount per frame instead of following the time of day can be scaled by this.
+    pub fn speed_ratio(&self) -> f32 {
+        self.speed as f32 / DEFAULT_SPEED as f32
+    }
+
+    /// How much simulated time passes with each frame, in seconds
+    pub fn seconds_per_frame(&self) -> f32 {
+        self.speed as f32 / FRAMES_PER_SECOND as f32
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    /// How busy the railways are at the current time of day, from near 0 in the middle of the
+    /// night to 1 at rush hour
+    pub fn activity(&self) -> f32 {
+        activity_at(self.time)
+    }
+
+    /// Move the time on by one frame at the current speed. This ignores `paused`, so that a
+    /// paused simulation can still be stepped through frame by frame.
+    pub fn tick(&mut self) {
+        let total = self.speed * 1000 + self.remainder;
+        self.time += SimTime(total / FRAMES_PER_SECOND);
+        self.remainder = total % FRAMES_PER_SECOND;
+
+        // Once the service day is over, start over with the next one
+        if self.time >= SERVICE_DAY_START + ONE_DAY {
+            self.time -= ONE_DAY;
+        }
+    }
+}
+
+/// How busy the railways are at the given time of day, from near 0 in the middle of the night to
+/// 1 at rush hour
+pub fn activity_at(time: SimTime) -> f32 {
+    let hours = time.0 as f32 / SimTime::from_hms(1, 0, 0).0 as f32;
+    match hours {
+        h if h < 5.0 => 0.02,
+        h if h < 7.0 => 0.3,
+        h if h < 9.5 => 1.0,
+        h if h < 17.0 => 0.6,
+        h if h < 19.5 => 1.0,
+        h if h < 22.0 => 0.5,
+        h if h < 25.0 => 0.25,
+        _ => 0.05,
+    }
+}
+
+impl Default for SimClock {
+    fn default() -> Self {
+        Self::new(DEFAULT_START_TIME)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ticks_carry_the_remainder_over() {
+        let start = SimTime::from_hms(8, 0, 0);
+        let mut clock = SimClock::new(start);
+        clock.set_speed(1);
+
+        // A frame at real time is 16 2/3 milliseconds, which only adds up over a second's worth
+        clock.tick();
+        assert_eq!(clock.time(), start + SimTime(16));
+        for _ in 1..FRAMES_PER_SECOND {
+            clock.tick();
+        }
+        assert_eq!(clock.time(), SimTime::from_hms(8, 0, 1));
+
+        // Jumping to another time starts the remainder over
+        clock.tick();
+        clock.set_time(start);
+        for _ in 0..FRAMES_PER_SECOND {
+            clock.tick();
+        }
+        assert_eq!(clock.time(), SimTime::from_hms(8, 0, 1));
+    }
+
+    #[test]
+    fn the_service_day_wraps_around() {
+        let mut clock = SimClock::new(SimTime::from_hms(27, 59, 50));
+        clock.set_speed(MAX_SPEED);
+        clock.tick();
+        assert_eq!(clock.time(), SimTime::from_hms(4, 0, 50));
+
+        // Times outside the service day are moved into it
+        clock.set_time(SimTime::from_hms(2, 0, 0));
+        assert_eq!(clock.time(), SimTime::from_hms(26, 0, 0));
+    }
+
+    #[test]
+    fn speed_steps_stop_at_the_ends() {
+        let mut clock = SimClock::default();
+        assert_eq!(clock.speed(), DEFAULT_SPEED);
+
+        for _ in 0..SPEED_STEPS.len() {
+            clock.faster();
+        }
+        assert_eq!(clock.speed(), MAX_SPEED);
+        clock.faster();
+        assert_eq!(clock.speed(), MAX_SPEED);
+
+        for _ in 0..SPEED_STEPS.len() {
+            clock.slower();
+        }
+        assert_eq!(clock.speed(), MIN_SPEED);
+        clock.slower();
+        assert_eq!(clock.speed(), MIN_SPEED);
+
+        // A speed between the steps goes to the nearest one on either side
+        clock.set_speed(100);
+        clock.faster();
+        assert_eq!(clock.speed(), 300);
+        clock.set_speed(100);
+        clock.slower();
+        assert_eq!(clock.speed(), 60);
+
+        clock.set_speed(0);
+        assert_eq!(clock.speed(), MIN_SPEED);
+        clock.set_speed(10_000);
+        assert_eq!(clock.speed(), MAX_SPEED);
+    }
+
+    #[test]
+    fn a_paused_clock_can_still_be_stepped() {
+        let start = SimTime::from_hms(8, 0, 0);
+        let mut clock = SimClock::new(start);
+        clock.toggle_pause();
+        assert!(clock.is_paused());
+
+        clock.tick();
+        assert_eq!(
+            clock.time(),
+            start + SimTime(DEFAULT_SPEED * 1000 / FRAMES_PER_SECOND)
+        );
+        assert!(clock.is_paused());
+
+        clock.toggle_pause();
+        assert!(!clock.is_paused());
+    }
+
+    #[test]
+    fn activity_follows_the_time_of_day() {
+        let activity = |h, m| SimClock::new(SimTime::from_hms(h, m, 0)).activity();
+
+        assert_eq!(activity(8, 0), 1.0);
+        assert_eq!(activity(18, 0), 1.0);
+        assert!(activity(12, 0) < activity(8, 0));
+        assert!(activity(4, 30) < activity(6, 0));
+        assert!(activity(26, 0) < activity(23, 0));
+        assert!(activity(27, 0) > 0.0);
+    }
+}
@@ -1,7 +1,9 @@
-use crate::map::Degree;
+use crate::map::{Degree, MapCoord, Meters};
 
-pub const SCREEN_WIDTH: u16 = 200;
-pub const SCREEN_HEIGHT: u16 = 150;
+/// The initial size of the map, in pixels. The viewport can be resized at runtime; this is also
+/// the size that the zoom-dependent station, track and font sizes were tuned for.
+pub const DEFAULT_SCREEN_WIDTH: u32 = 200;
+pub const DEFAULT_SCREEN_HEIGHT: u32 = 150;
 
 pub const JAPAN_LEFT: Degree = Degree(127.59);
 pub const JAPAN_RIGHT: Degree = Degree(145.77);
@@ -11,6 +13,10 @@ pub const JAPAN_BOTTOM: Degree = Degree(25.9);
 // Arbitrary coordinate for the (0, 0) tile
 pub const JAPAN_CENTER_LONG: Degree = Degree(137.710_62);
 pub const JAPAN_CENTER_LAT: Degree = Degree(36.035_645);
+pub const JAPAN_CENTER: MapCoord = MapCoord {
+    long: JAPAN_CENTER_LONG,
+    lat: JAPAN_CENTER_LAT,
+};
 
 /// Velocity limit for how fast you can zoom in/out
 pub const SCROLL_DIFF_MAX: f32 = 30.0;
@@ -19,12 +25,13 @@ pub const SCROLL_DIFF_MAX: f32 = 30.0;
 /// A tile is the smallest unit that can be marked as having a station or not
 /// (i.e. it's like a virtual "pixel", which itself is made up of actual pixels
 /// on the display). Currently this is set to 1, so one pixel is equal to one tile.
-pub const TILE_SIZE: u16 = 1;
+pub const TILE_SIZE: u32 = 1;
 
-pub const NUMBER_OF_TILES_X: u16 = SCREEN_WIDTH / TILE_SIZE;
-pub const NUMBER_OF_TILES_Y: u16 = SCREEN_HEIGHT / TILE_SIZE;
-
-/// The width of the current MapFrame cannot be less than this
+/// The width of the current MapFrame, scaled to the default screen width, cannot be less than this
 pub const MIN_ZOOM: Degree = Degree(0.01);
-/// The width of the current MapFrame cannot be greater than this
+/// The width of the current MapFrame, scaled to the default screen width, cannot be greater than
+/// this
 pub const MAX_ZOOM: Degree = Degree(80.0);
+
+/// Mean radius of the Earth
+pub const EARTH_RADIUS: Meters = Meters(6_371_008.8);
@@ -1,27 +1,173 @@
-use crate::map::{Degree, MapCoord};
+use crate::{
+    geometry::{path_length, TrackGeometry},
+    map::{Degree, MapCoord, Meters},
+    names::{kana_to_romaji, Language},
+    schedule::{StopTime, Timetable},
+};
 use ahash::RandomState;
-use csv::Reader;
+use csv::{Reader, StringRecord};
 use indexmap::IndexMap;
 use std::{
+    borrow::Cow,
     collections::{HashMap, HashSet},
+    error::Error,
     fmt::Display,
+    fs::File,
+    io::{self, Read},
+    path::Path,
+    str::FromStr,
 };
+use zip::result::ZipError;
+
+/// File name of the station list inside a data directory
+pub const STATIONS_FILE: &str = "stations.csv";
+
+/// File name of the station connection list inside a data directory
+pub const CONNECTIONS_FILE: &str = "join.csv";
+
+/// File name of the (optional) line list inside a data directory
+pub const LINES_FILE: &str = "line.csv";
+
+/// File name of the (optional) railway company list inside a data directory
+pub const COMPANIES_FILE: &str = "company.csv";
 
-#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+/// The data shipped with the binary, used when no other data source is given
+const EMBEDDED_STATIONS: &[u8] = include_bytes!("../data/stations.csv");
+const EMBEDDED_CONNECTIONS: &[u8] = include_bytes!("../data/join.csv");
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Copy, Clone)]
 pub struct StationId(pub u32);
 
+impl StationId {
+    /// ekidata station codes are the code of the station's line followed by a two-digit number
+    pub fn line_id(self) -> LineId {
+        LineId(self.0 / 100)
+    }
+}
+
+/// Identifies a set of stations that are really the same place, served by different lines (e.g.
+/// the dozen 新宿 entries). Corresponds to ekidata's `station_g_cd`, which is the ID of one of
+/// the stations in the group.
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, PartialOrd, Ord)]
+pub struct StationGroupId(pub u32);
+
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, PartialOrd, Ord)]
+pub struct LineId(pub u32);
+
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, PartialOrd, Ord)]
+pub struct CompanyId(pub u32);
+
+/// All stations, keyed by station ID
+pub type StationMap = IndexMap<StationId, Station, RandomState>;
+
+/// key: station ID
+/// value: set of station IDs connected to the key station
+pub type ConnectionMap = HashMap<StationId, HashSet<StationId, RandomState>, RandomState>;
+
+/// key: pair of connected station IDs, with the lower ID first
+/// value: the lines that run over the connection
+pub type ConnectionLineMap = HashMap<(StationId, StationId), Vec<LineId>, RandomState>;
+
+/// All station groups, keyed by group ID
+pub type StationGroupMap = IndexMap<StationGroupId, StationGroup, RandomState>;
+
+/// All lines, keyed by line ID
+pub type LineMap = IndexMap<LineId, Line, RandomState>;
+
+/// All railway companies, keyed by company ID
+pub type CompanyMap = IndexMap<CompanyId, Company, RandomState>;
+
+/// Station names in romaji, keyed by station ID
+pub type RomajiMap = HashMap<StationId, String, RandomState>;
+
 // Corresponds to entries in stations.csv
 #[derive(Debug, Clone)]
 pub struct Station {
     pub id: StationId,
     pub name: String,
+
+    /// How the name is read, in kana. Only full ekidata dumps have this.
+    pub kana: Option<String>,
+
+    /// The name written in romaji, from full ekidata dumps or a separate file of names
+    pub romaji: Option<String>,
+
+    pub coord: MapCoord,
+
+    /// The line this station is on. A station served by several lines appears once per line,
+    /// each time with a different ID.
+    pub line_id: LineId,
+
+    /// The group of stations that this one can be transferred to and from
+    pub group_id: StationGroupId,
+}
+
+/// All the stations sharing a `StationGroupId`. This is what gets drawn and labelled on the map,
+/// so that a transfer station shows up once instead of once per line.
+#[derive(Debug, Clone)]
+pub struct StationGroup {
+    pub id: StationGroupId,
+    pub name: String,
+
+    /// How the name is read, in kana, if known
+    pub kana: Option<String>,
+
+    /// The name written in romaji: either given for one of the member stations with the group's
+    /// name, or worked out from the reading. None if neither is known.
+    pub romaji: Option<String>,
+
+    /// The average position of the member stations
     pub coord: MapCoord,
+
+    /// The stations in this group, in ID order
+    pub members: Vec<StationId>,
+}
+
+impl StationGroup {
+    /// The group's name in the given language. Groups without a name in romaji go by their
+    /// Japanese one.
+    pub fn display_name(&self, language: Language) -> Cow<'_, str> {
+        match (language, &self.romaji) {
+            (Language::Romaji, Some(romaji)) => Cow::Borrowed(romaji),
+            (Language::Both, Some(romaji)) => Cow::Owned(format!("{} {}", self.name, romaji)),
+            _ => Cow::Borrowed(&self.name),
+        }
+    }
+}
+
+// Corresponds to entries in line.csv
+#[derive(Debug, Clone)]
+pub struct Line {
+    pub id: LineId,
+    pub name: String,
+    pub company_id: Option<CompanyId>,
+
+    /// The line's official color, if it has one
+    pub color: Option<[u8; 3]>,
+}
+
+// Corresponds to entries in company.csv
+#[derive(Debug, Clone)]
+pub struct Company {
+    pub id: CompanyId,
+    pub name: String,
 }
 
 // Corresponds to entries in join.csv
 pub struct Connection {
     station_id_1: StationId,
     station_id_2: StationId,
+
+    /// The line the connection is on, for files that say
+    line_id: Option<LineId>,
+}
+
+/// The connections loaded from a file like `data/join.csv`
+pub struct Connections {
+    pub connections: ConnectionMap,
+
+    /// The lines given for each connection. Empty when the file has no `line_cd` column.
+    pub lines: ConnectionLineMap,
 }
 
 impl Display for Station {
@@ -30,61 +176,1419 @@ impl Display for Station {
     }
 }
 
-pub fn load_stations() -> IndexMap<StationId, Station, RandomState> {
-    let bytes: &[u8] = include_bytes!("../data/stations.csv");
-    let mut reader = Reader::from_reader(bytes);
+/// The whole railway network: the station graph, plus the lines and companies running on it
+pub struct Network {
+    pub stations: StationMap,
+    pub connections: ConnectionMap,
+    pub groups: StationGroupMap,
+    pub lines: LineMap,
+    pub companies: CompanyMap,
 
-    let mut result: IndexMap<StationId, Station, RandomState> =
-        IndexMap::with_hasher(RandomState::new());
+    /// Which lines run over each connection
+    connection_lines: ConnectionLineMap,
+
+    /// The paths of the connections that don't run straight between their stations
+    geometry: TrackGeometry,
+}
+
+impl Network {
+    /// Put together a network from its parts. Any line that a station refers to but that isn't in
+    /// `lines` (for example when no line file was loaded) gets an unnamed, uncolored entry, so
+    /// every station's line can always be looked up.
+    pub fn new(
+        stations: StationMap,
+        connections: ConnectionMap,
+        mut lines: LineMap,
+        companies: CompanyMap,
+    ) -> Self {
+        for station in stations.values() {
+            lines.entry(station.line_id).or_insert_with(|| Line {
+                id: station.line_id,
+                name: String::new(),
+                company_id: None,
+                color: None,
+            });
+        }
+
+        let mut groups: StationGroupMap = IndexMap::with_hasher(RandomState::new());
+        for station in stations.values() {
+            groups
+                .entry(station.group_id)
+                .or_insert_with(|| StationGroup {
+                    id: station.group_id,
+                    name: station.name.clone(),
+                    kana: station.kana.clone(),
+                    romaji: None,
+                    coord: station.coord,
+                    members: Vec::new(),
+                })
+                .members
+                .push(station.id);
+        }
+        for group in groups.values_mut() {
+            group.members.sort_by_key(|id| id.0);
+
+            let count = Degree(group.members.len() as f32);
+            let (long_sum, lat_sum) = group
+                .members
+                .iter()
+                .map(|id| stations[id].coord)
+                .fold((Degree(0.0), Degree(0.0)), |(long, lat), coord| {
+                    (long + coord.long, lat + coord.lat)
+                });
+            group.coord = MapCoord {
+                long: long_sum / count,
+                lat: lat_sum / count,
+            };
+
+            // Prefer the name of the station the group is named after, if it's in the group
+            if let Some(station) = stations.get(&StationId(group.id.0)) {
+                group.name = station.name.clone();
+                group.kana = station.kana.clone();
+            }
+            group.romaji = group_romaji(group, &stations);
+        }
+
+        // Until told otherwise (see `add_connection_lines`), a connection belongs to a line when
+        // both of its stations are on that line
+        let mut connection_lines = HashMap::with_hasher(RandomState::new());
+        for (station_id, connected) in &connections {
+            for other_id in connected {
+                if let (Some(station), Some(other)) =
+                    (stations.get(station_id), stations.get(other_id))
+                {
+                    if station.line_id == other.line_id {
+                        connection_lines
+                            .entry(connection_key(*station_id, *other_id))
+                            .or_insert_with(|| vec![station.line_id]);
+                    }
+                }
+            }
+        }
+
+        Self {
+            stations,
+            connections,
+            groups,
+            lines,
+            companies,
+            connection_lines,
+            geometry: TrackGeometry::default(),
+        }
+    }
+
+    /// Use the given names in romaji for stations, instead of any they already had. Groups get
+    /// the name of whichever of their stations has the group's name. Stations that aren't in the
+    /// network are left out.
+    pub fn add_romaji_names(&mut self, names: RomajiMap) {
+        for (id, name) in names {
+            if let Some(station) = self.stations.get_mut(&id) {
+                station.romaji = Some(name);
+            }
+        }
+
+        let stations = &self.stations;
+        for group in self.groups.values_mut() {
+            group.romaji = group_romaji(group, stations);
+        }
+    }
+
+    /// Use the given lines for connections, instead of the ones worked out from their stations'
+    /// lines. Connections that aren't in `lines` keep the lines they had.
+    pub fn add_connection_lines(&mut self, lines: ConnectionLineMap) {
+        for (key, line_ids) in lines {
+            for line_id in &line_ids {
+                self.lines.entry(*line_id).or_insert_with(|| Line {
+                    id: *line_id,
+                    name: String::new(),
+                    company_id: None,
+                    color: None,
+                });
+            }
+            self.connection_lines.insert(key, line_ids);
+        }
+    }
+
+    /// Use the given paths for tracks, on top of any that were already set
+    pub fn add_geometry(&mut self, geometry: TrackGeometry) {
+        self.geometry.extend(geometry);
+    }
+
+    /// The path that trains take from one station to the other, starting and ending at where the
+    /// stations are drawn. Connections without a known path, and transfers, are a straight line.
+    pub fn track_path(
+        &self,
+        station_id_1: StationId,
+        station_id_2: StationId,
+    ) -> Option<Cow<'_, [MapCoord]>> {
+        match self.geometry.get(station_id_1, station_id_2) {
+            Some(path) => Some(Cow::Borrowed(path)),
+            None => Some(Cow::Owned(vec![
+                self.draw_coord(station_id_1)?,
+                self.draw_coord(station_id_2)?,
+            ])),
+        }
+    }
+
+    /// How far trains travel from one station to the other, following the track
+    pub fn track_length(&self, station_id_1: StationId, station_id_2: StationId) -> Meters {
+        self.track_path(station_id_1, station_id_2)
+            .map_or(Meters(0.0), |path| path_length(&path))
+    }
 
-    for record in reader.records() {
-        let record = record.unwrap();
-        let long: Degree = record.get(2).unwrap().parse().unwrap();
-        let lat: Degree = record.get(3).unwrap().parse().unwrap();
+    /// The group the given station belongs to
+    pub fn group_of(&self, station_id: StationId) -> Option<&StationGroup> {
+        self.stations
+            .get(&station_id)
+            .and_then(|s| self.groups.get(&s.group_id))
+    }
+
+    /// The stations that can be transferred to from the given station, without traveling on a
+    /// line. These are the other members of its group.
+    pub fn transfers(&self, station_id: StationId) -> impl Iterator<Item = StationId> + '_ {
+        self.group_of(station_id)
+            .into_iter()
+            .flat_map(|group| group.members.iter().copied())
+            .filter(move |id| *id != station_id)
+    }
+
+    /// Where to draw the given station: the position of its group, so that tracks on different
+    /// lines meet at the same point
+    pub fn draw_coord(&self, station_id: StationId) -> Option<MapCoord> {
+        self.group_of(station_id).map(|group| group.coord)
+    }
 
-        let station = Station {
-            id: StationId(record.get(0).unwrap().parse().unwrap()),
-            name: record.get(1).unwrap().to_owned(),
-            coord: MapCoord { long, lat },
-        };
+    /// Whether any station group has a name in romaji. The bundled data has neither names in
+    /// romaji nor readings to write them from.
+    pub fn has_romaji(&self) -> bool {
+        self.groups.values().any(|group| group.romaji.is_some())
+    }
+
+    /// The upper left and lower right corners of the smallest box around every station group, or
+    /// None if there aren't any stations
+    pub fn bounds(&self) -> Option<(MapCoord, MapCoord)> {
+        let mut coords = self.groups.values().map(|g| g.coord);
+        let first = coords.next()?;
+        Some(
+            coords.fold((first, first), |(upper_left, lower_right), coord| {
+                (
+                    MapCoord {
+                        long: Degree(upper_left.long.0.min(coord.long.0)),
+                        lat: Degree(upper_left.lat.0.max(coord.lat.0)),
+                    },
+                    MapCoord {
+                        long: Degree(lower_right.long.0.max(coord.long.0)),
+                        lat: Degree(lower_right.lat.0.min(coord.lat.0)),
+                    },
+                )
+            }),
+        )
+    }
+
+    /// The lines that run directly between the two stations. Empty if the stations aren't
+    /// connected, or if no line is known to run between them (such as for a transfer).
+    pub fn lines_between(&self, station_id_1: StationId, station_id_2: StationId) -> &[LineId] {
+        self.connection_lines
+            .get(&connection_key(station_id_1, station_id_2))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// The line the given station is on
+    pub fn station_line(&self, station_id: StationId) -> Option<&Line> {
+        self.stations
+            .get(&station_id)
+            .and_then(|s| self.lines.get(&s.line_id))
+    }
+
+    /// The company operating the given line, if known
+    pub fn line_company(&self, line_id: LineId) -> Option<&Company> {
+        self.lines
+            .get(&line_id)
+            .and_then(|l| l.company_id)
+            .and_then(|id| self.companies.get(&id))
+    }
+}
+
+/// The group's name in romaji: the one given for the first of its stations that has the group's
+/// name and a name in romaji, or else the group's reading written in romaji
+fn group_romaji(group: &StationGroup, stations: &StationMap) -> Option<String> {
+    group
+        .members
+        .iter()
+        .filter_map(|id| stations.get(id))
+        .filter(|station| station.name == group.name)
+        .find_map(|station| station.romaji.clone())
+        .or_else(|| group.kana.as_deref().map(kana_to_romaji))
+}
+
+fn connection_key(station_id_1: StationId, station_id_2: StationId) -> (StationId, StationId) {
+    if station_id_1.0 <= station_id_2.0 {
+        (station_id_1, station_id_2)
+    } else {
+        (station_id_2, station_id_1)
+    }
+}
+
+/// Something that was wrong with one of the data files. Every variant carries the name of the
+/// file, and where possible the line and column, so that a bad row can be found and fixed.
+#[derive(Debug)]
+pub enum DataError {
+    /// The file couldn't be opened or read at all
+    Io { file: String, source: io::Error },
+
+    /// The header row doesn't have one of the columns we need
+    MissingColumn { file: String, column: &'static str },
+
+    /// The CSV structure itself is broken on this line (e.g. the wrong number of fields)
+    Csv {
+        file: String,
+        line: u64,
+        source: csv::Error,
+    },
+
+    /// A required field on this line is empty
+    MissingField {
+        file: String,
+        line: u64,
+        column: &'static str,
+    },
+
+    /// A field on this line couldn't be parsed into the type we need
+    InvalidField {
+        file: String,
+        line: u64,
+        column: &'static str,
+        value: String,
+    },
+
+    /// A connection or stop time refers to a station ID that isn't in the station list
+    UnknownStation {
+        file: String,
+        line: u64,
+        column: &'static str,
+        station_id: StationId,
+    },
+
+    /// A GTFS file refers to an ID (of a stop, trip, etc.) that isn't in the file it should be in
+    UnknownId {
+        file: String,
+        line: u64,
+        column: &'static str,
+        id: String,
+    },
+
+    /// A GTFS service was asked for by ID, but none of the feed's trips with stop times are on it
+    UnknownService { file: String, service_id: String },
+
+    /// An ID that has to be unique (of a station, route, etc.) is used again on this line
+    DuplicateId {
+        file: String,
+        line: u64,
+        column: &'static str,
+        id: String,
+    },
+
+    /// A zip file couldn't be read
+    Archive { file: String, source: ZipError },
+
+    /// A JSON file couldn't be parsed
+    Json {
+        file: String,
+        source: serde_json::Error,
+    },
+
+    /// A GeoJSON feature (or the whole file, if `feature` is None) isn't what we need
+    InvalidFeature {
+        file: String,
+        feature: Option<usize>,
+        reason: String,
+    },
+}
+
+impl Display for DataError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DataError::Io { file, source } => write!(f, "{}: {}", file, source),
+            DataError::MissingColumn { file, column } => {
+                write!(f, "{}: no `{}` column in the header", file, column)
+            }
+            DataError::Csv { file, line, source } => write!(f, "{}:{}: {}", file, line, source),
+            DataError::MissingField { file, line, column } => {
+                write!(f, "{}:{}: `{}` is empty", file, line, column)
+            }
+            DataError::InvalidField {
+                file,
+                line,
+                column,
+                value,
+            } => write!(
+                f,
+                "{}:{}: invalid `{}` value {:?}",
+                file, line, column, value
+            ),
+            DataError::UnknownStation {
+                file,
+                line,
+                column,
+                station_id,
+            } => write!(
+                f,
+                "{}:{}: `{}` refers to unknown station {}",
+                file, line, column, station_id.0
+            ),
+            DataError::UnknownId {
+                file,
+                line,
+                column,
+                id,
+            } => write!(f, "{}:{}: unknown `{}` {:?}", file, line, column, id),
+            DataError::UnknownService { file, service_id } => {
+                write!(f, "{}: no trips are on service {:?}", file, service_id)
+            }
+            DataError::DuplicateId {
+                file,
+                line,
+                column,
+                id,
+            } => write!(
+                f,
+                "{}:{}: `{}` {:?} is already taken",
+                file, line, column, id
+            ),
+            DataError::Archive { file, source } => write!(f, "{}: {}", file, source),
+            DataError::Json { file, source } => write!(f, "{}: {}", file, source),
+            DataError::InvalidFeature {
+                file,
+                feature: Some(feature),
+                reason,
+            } => write!(f, "{}: feature {}: {}", file, feature, reason),
+            DataError::InvalidFeature {
+                file,
+                feature: None,
+                reason,
+            } => write!(f, "{}: {}", file, reason),
+        }
+    }
+}
 
-        result.insert(station.id, station);
+impl Error for DataError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DataError::Io { source, .. } => Some(source),
+            DataError::Csv { source, .. } => Some(source),
+            DataError::Archive { source, .. } => Some(source),
+            DataError::Json { source, .. } => Some(source),
+            _ => None,
+        }
     }
+}
+
+/// How to deal with rows that can't be loaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strictness {
+    /// Stop at the first bad row and return its error
+    Strict,
 
-    result
+    /// Skip bad rows, collecting their errors as warnings
+    Lenient,
 }
 
-pub fn load_connections() -> HashMap<StationId, HashSet<StationId, RandomState>, RandomState> {
-    let bytes: &[u8] = include_bytes!("../data/join.csv");
-    let mut reader = Reader::from_reader(bytes);
+/// The result of successfully loading a data file, along with the rows that were skipped in
+/// lenient mode. `warnings` is always empty in strict mode.
+pub struct Loaded<T> {
+    pub data: T,
+    pub warnings: Vec<DataError>,
+}
 
-    let vec: Vec<Connection> = reader
-        .records()
-        .map(|record| {
-            let record = record.unwrap();
-            let station_id_1: StationId = StationId(record.get(0).unwrap().parse().unwrap());
-            let station_id_2: StationId = StationId(record.get(1).unwrap().parse().unwrap());
+impl<T> Loaded<T> {
+    /// Move the warnings into `warnings` and return the data
+    pub(crate) fn take(self, warnings: &mut Vec<DataError>) -> T {
+        warnings.extend(self.warnings);
+        self.data
+    }
 
-            Connection {
-                station_id_1,
-                station_id_2,
+    /// Either return `error` (strict mode) or hold onto it as a warning (lenient mode)
+    pub(crate) fn reject(
+        &mut self,
+        strictness: Strictness,
+        error: DataError,
+    ) -> Result<(), DataError> {
+        match strictness {
+            Strictness::Strict => Err(error),
+            Strictness::Lenient => {
+                self.warnings.push(error);
+                Ok(())
             }
+        }
+    }
+}
+
+/// A CSV reader that looks up fields by their header name, so that both the trimmed files in
+/// `data/` and full ekidata dumps (which have many more columns) can be read.
+pub(crate) struct CsvFile<R> {
+    name: String,
+    reader: Reader<R>,
+    headers: StringRecord,
+}
+
+impl<R: Read> CsvFile<R> {
+    pub(crate) fn new(source: R, name: &str) -> Result<Self, DataError> {
+        let mut reader = Reader::from_reader(source);
+        let headers = reader
+            .headers()
+            .map_err(|source| DataError::Csv {
+                file: name.to_owned(),
+                line: 1,
+                source,
+            })?
+            .clone();
+
+        Ok(Self {
+            name: name.to_owned(),
+            reader,
+            headers,
         })
-        .collect();
+    }
+
+    /// Get the index of a column that has to be present
+    pub(crate) fn column(&self, column: &'static str) -> Result<Column, DataError> {
+        self.headers
+            .iter()
+            .position(|h| h.trim() == column)
+            .map(|index| Column {
+                name: column,
+                index,
+            })
+            .ok_or_else(|| DataError::MissingColumn {
+                file: self.name.clone(),
+                column,
+            })
+    }
+
+    /// Get the index of a column that only some versions of the file have
+    pub(crate) fn optional_column(&self, column: &'static str) -> Option<Column> {
+        self.column(column).ok()
+    }
+
+    /// Iterate over the rows, with the line number each one came from
+    pub(crate) fn rows(&mut self) -> impl Iterator<Item = Result<Row, DataError>> + '_ {
+        let name = &self.name;
+        self.reader.records().map(move |record| match record {
+            Ok(record) => Ok(Row {
+                file: name.clone(),
+                line: record.position().map(|p| p.line()).unwrap_or_default(),
+                record,
+            }),
+            Err(source) => Err(DataError::Csv {
+                file: name.clone(),
+                line: source.position().map(|p| p.line()).unwrap_or_default(),
+                source,
+            }),
+        })
+    }
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct Column {
+    pub(crate) name: &'static str,
+    index: usize,
+}
+
+pub(crate) struct Row {
+    pub(crate) file: String,
+    pub(crate) line: u64,
+    record: StringRecord,
+}
+
+impl Row {
+    pub(crate) fn get(&self, column: Column) -> Result<&str, DataError> {
+        match self.record.get(column.index).map(str::trim) {
+            Some(value) if !value.is_empty() => Ok(value),
+            _ => Err(DataError::MissingField {
+                file: self.file.clone(),
+                line: self.line,
+                column: column.name,
+            }),
+        }
+    }
+
+    /// Get a field from an optional column, treating an empty field the same as a missing column
+    pub(crate) fn get_optional(&self, column: Option<Column>) -> Option<&str> {
+        column.and_then(|c| self.get(c).ok())
+    }
+
+    pub(crate) fn parse<T: FromStr>(&self, column: Column) -> Result<T, DataError> {
+        let value = self.get(column)?;
+        value.parse().map_err(|_| DataError::InvalidField {
+            file: self.file.clone(),
+            line: self.line,
+            column: column.name,
+            value: value.to_owned(),
+        })
+    }
+}
+
+pub(crate) fn open<P: AsRef<Path>>(path: P) -> Result<File, DataError> {
+    File::open(path.as_ref()).map_err(|source| DataError::Io {
+        file: path.as_ref().display().to_string(),
+        source,
+    })
+}
 
-    let mut result: HashMap<StationId, HashSet<StationId, RandomState>, RandomState> =
+/// Load the stations that were embedded into the binary at compile time
+pub fn load_stations(strictness: Strictness) -> Result<Loaded<StationMap>, DataError> {
+    load_stations_from_reader(EMBEDDED_STATIONS, STATIONS_FILE, strictness)
+}
+
+/// Load stations from a CSV file in the same format as `data/stations.csv`
+pub fn load_stations_from_path<P: AsRef<Path>>(
+    path: P,
+    strictness: Strictness,
+) -> Result<Loaded<StationMap>, DataError> {
+    let file = open(&path)?;
+    load_stations_from_reader(file, &path.as_ref().display().to_string(), strictness)
+}
+
+/// Load stations from any source of CSV data in the same format as `data/stations.csv`.
+/// `name` is only used to identify the source in errors.
+pub fn load_stations_from_reader<R: Read>(
+    source: R,
+    name: &str,
+    strictness: Strictness,
+) -> Result<Loaded<StationMap>, DataError> {
+    let mut csv = CsvFile::new(source, name)?;
+    let id_column = csv.column("station_cd")?;
+    let name_column = csv.column("station_name")?;
+    let long_column = csv.column("lon")?;
+    let lat_column = csv.column("lat")?;
+    let line_column = csv.optional_column("line_cd");
+    let group_column = csv.optional_column("station_g_cd");
+    let kana_column = csv.optional_column("station_name_k");
+    let romaji_column = csv.optional_column("station_name_r");
+
+    let mut result = Loaded {
+        data: IndexMap::with_hasher(RandomState::new()),
+        warnings: Vec::new(),
+    };
+
+    for row in csv.rows() {
+        let station = row.and_then(|row| {
+            let long: Degree = row.parse(long_column)?;
+            let lat: Degree = row.parse(lat_column)?;
+
+            let id = StationId(row.parse(id_column)?);
+            if result.data.contains_key(&id) {
+                return Err(DataError::DuplicateId {
+                    file: row.file.clone(),
+                    line: row.line,
+                    column: "station_cd",
+                    id: id.0.to_string(),
+                });
+            }
+
+            // Full ekidata dumps have the line code as its own column; otherwise it comes from
+            // the station code
+            let line_id = match line_column {
+                Some(column) if row.get_optional(Some(column)).is_some() => {
+                    LineId(row.parse(column)?)
+                }
+                _ => id.line_id(),
+            };
+
+            // Without a group column, every station starts in its own group, and the groups are
+            // worked out below
+            let group_id = match group_column {
+                Some(column) if row.get_optional(Some(column)).is_some() => {
+                    StationGroupId(row.parse(column)?)
+                }
+                _ => StationGroupId(id.0),
+            };
+
+            Ok(Station {
+                id,
+                name: row.get(name_column)?.to_owned(),
+                kana: row.get_optional(kana_column).map(str::to_owned),
+                romaji: row.get_optional(romaji_column).map(str::to_owned),
+                coord: MapCoord { long, lat },
+                line_id,
+                group_id,
+            })
+        });
+
+        match station {
+            Ok(station) => {
+                result.data.insert(station.id, station);
+            }
+            Err(e) => result.reject(strictness, e)?,
+        }
+    }
+
+    if group_column.is_none() {
+        group_by_name(&mut result.data);
+    }
+
+    Ok(result)
+}
+
+/// Stations with the same name closer than this are taken to be the same place
+const SAME_STATION_DISTANCE: Meters = Meters(1000.0);
+
+/// Approximate ekidata's station groups for data that doesn't have them, by grouping stations
+/// with the same name that are close to each other, directly or through other stations with that
+/// name. Each group gets the lowest station ID in it, the same as ekidata usually does.
+fn group_by_name(stations: &mut StationMap) {
+    let mut by_name: HashMap<&str, Vec<&Station>, RandomState> =
         HashMap::with_hasher(RandomState::new());
+    for station in stations.values() {
+        by_name.entry(&station.name).or_default().push(station);
+    }
+
+    let mut group_ids: Vec<(StationId, StationGroupId)> = Vec::new();
+    for same_name in by_name.values() {
+        // Union-find over the stations with this name: each one points to another station in its
+        // group, and the station pointing to itself is the root, which has the lowest ID
+        let mut parents: Vec<usize> = (0..same_name.len()).collect();
+        fn root(parents: &mut [usize], mut i: usize) -> usize {
+            while parents[i] != i {
+                parents[i] = parents[parents[i]];
+                i = parents[i];
+            }
+            i
+        }
+
+        for i in 0..same_name.len() {
+            for j in i + 1..same_name.len() {
+                if same_name[i].coord.meters_to(&same_name[j].coord) < SAME_STATION_DISTANCE {
+                    let (a, b) = (root(&mut parents, i), root(&mut parents, j));
+                    if same_name[a].id.0 < same_name[b].id.0 {
+                        parents[b] = a;
+                    } else {
+                        parents[a] = b;
+                    }
+                }
+            }
+        }
+
+        for i in 0..same_name.len() {
+            let group_id = StationGroupId(same_name[root(&mut parents, i)].id.0);
+            group_ids.push((same_name[i].id, group_id));
+        }
+    }
+
+    for (station_id, group_id) in group_ids {
+        if let Some(station) = stations.get_mut(&station_id) {
+            station.group_id = group_id;
+        }
+    }
+}
+
+/// Load the connections that were embedded into the binary at compile time. `stations` is used
+/// to check that every connection is between two known stations.
+pub fn load_connections(
+    stations: &StationMap,
+    strictness: Strictness,
+) -> Result<Loaded<Connections>, DataError> {
+    load_connections_from_reader(EMBEDDED_CONNECTIONS, CONNECTIONS_FILE, stations, strictness)
+}
+
+/// Load connections from a CSV file in the same format as `data/join.csv`
+pub fn load_connections_from_path<P: AsRef<Path>>(
+    path: P,
+    stations: &StationMap,
+    strictness: Strictness,
+) -> Result<Loaded<Connections>, DataError> {
+    let file = open(&path)?;
+    load_connections_from_reader(
+        file,
+        &path.as_ref().display().to_string(),
+        stations,
+        strictness,
+    )
+}
+
+/// Load connections from any source of CSV data in the same format as `data/join.csv`.
+/// `name` is only used to identify the source in errors. Full ekidata dumps also say which line
+/// each connection is on, in a `line_cd` column.
+pub fn load_connections_from_reader<R: Read>(
+    source: R,
+    name: &str,
+    stations: &StationMap,
+    strictness: Strictness,
+) -> Result<Loaded<Connections>, DataError> {
+    let mut csv = CsvFile::new(source, name)?;
+    let columns = [csv.column("station_cd1")?, csv.column("station_cd2")?];
+    let line_column = csv.optional_column("line_cd");
+
+    let mut vec: Vec<Connection> = Vec::new();
+    let mut result: Loaded<Connections> = Loaded {
+        data: Connections {
+            connections: HashMap::with_hasher(RandomState::new()),
+            lines: HashMap::with_hasher(RandomState::new()),
+        },
+        warnings: Vec::new(),
+    };
+
+    for row in csv.rows() {
+        let connection = row.and_then(|row| {
+            let mut ids = [StationId(0); 2];
+            for (id, column) in ids.iter_mut().zip(columns.iter()) {
+                *id = StationId(row.parse(*column)?);
+
+                // A connection to a station that doesn't exist can't be drawn or traveled on
+                if !stations.contains_key(id) {
+                    return Err(DataError::UnknownStation {
+                        file: row.file.clone(),
+                        line: row.line,
+                        column: column.name,
+                        station_id: *id,
+                    });
+                }
+            }
+
+            let line_id = match line_column {
+                Some(column) if row.get_optional(Some(column)).is_some() => {
+                    Some(LineId(row.parse(column)?))
+                }
+                _ => None,
+            };
+
+            Ok(Connection {
+                station_id_1: ids[0],
+                station_id_2: ids[1],
+                line_id,
+            })
+        });
+
+        match connection {
+            Ok(connection) => vec.push(connection),
+            Err(e) => result.reject(strictness, e)?,
+        }
+    }
 
     for record in vec {
-        result
+        let connections = &mut result.data.connections;
+        connections
             .entry(record.station_id_1)
             .or_default()
             .insert(record.station_id_2);
-        result
+        connections
             .entry(record.station_id_2)
             .or_default()
             .insert(record.station_id_1);
+
+        if let Some(line_id) = record.line_id {
+            let lines = result
+                .data
+                .lines
+                .entry(connection_key(record.station_id_1, record.station_id_2))
+                .or_default();
+            if !lines.contains(&line_id) {
+                lines.push(line_id);
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+/// Load lines from a CSV file in ekidata's line format
+pub fn load_lines_from_path<P: AsRef<Path>>(
+    path: P,
+    strictness: Strictness,
+) -> Result<Loaded<LineMap>, DataError> {
+    let file = open(&path)?;
+    load_lines_from_reader(file, &path.as_ref().display().to_string(), strictness)
+}
+
+/// Load lines from any source of CSV data in ekidata's line format. Only the `line_cd` and
+/// `line_name` columns are required. `name` is only used to identify the source in errors.
+pub fn load_lines_from_reader<R: Read>(
+    source: R,
+    name: &str,
+    strictness: Strictness,
+) -> Result<Loaded<LineMap>, DataError> {
+    let mut csv = CsvFile::new(source, name)?;
+    let id_column = csv.column("line_cd")?;
+    let name_column = csv.column("line_name")?;
+    let company_column = csv.optional_column("company_cd");
+    let color_column = csv.optional_column("line_color_c");
+
+    let mut result = Loaded {
+        data: IndexMap::with_hasher(RandomState::new()),
+        warnings: Vec::new(),
+    };
+
+    for row in csv.rows() {
+        let line = row.and_then(|row| {
+            let id = LineId(row.parse(id_column)?);
+            if result.data.contains_key(&id) {
+                return Err(DataError::DuplicateId {
+                    file: row.file.clone(),
+                    line: row.line,
+                    column: "line_cd",
+                    id: id.0.to_string(),
+                });
+            }
+
+            let company_id = match company_column {
+                Some(column) if row.get_optional(Some(column)).is_some() => {
+                    Some(CompanyId(row.parse(column)?))
+                }
+                _ => None,
+            };
+
+            let color = match (color_column, row.get_optional(color_column)) {
+                (Some(column), Some(value)) => {
+                    Some(parse_color(value).ok_or_else(|| DataError::InvalidField {
+                        file: row.file.clone(),
+                        line: row.line,
+                        column: column.name,
+                        value: value.to_owned(),
+                    })?)
+                }
+                _ => None,
+            };
+
+            Ok(Line {
+                id,
+                name: row.get(name_column)?.to_owned(),
+                company_id,
+                color,
+            })
+        });
+
+        match line {
+            Ok(line) => {
+                result.data.insert(line.id, line);
+            }
+            Err(e) => result.reject(strictness, e)?,
+        }
+    }
+
+    Ok(result)
+}
+
+/// Parse a hex color like `80C241` or `#80C241`
+pub(crate) fn parse_color(value: &str) -> Option<[u8; 3]> {
+    let hex = value.trim_start_matches('#');
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+
+    let mut color = [0; 3];
+    for (i, channel) in color.iter_mut().enumerate() {
+        *channel = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(color)
+}
+
+/// Load railway companies from a CSV file in ekidata's company format
+pub fn load_companies_from_path<P: AsRef<Path>>(
+    path: P,
+    strictness: Strictness,
+) -> Result<Loaded<CompanyMap>, DataError> {
+    let file = open(&path)?;
+    load_companies_from_reader(file, &path.as_ref().display().to_string(), strictness)
+}
+
+/// Load railway companies from any source of CSV data in ekidata's company format. Only the
+/// `company_cd` and `company_name` columns are required. `name` is only used to identify the
+/// source in errors.
+pub fn load_companies_from_reader<R: Read>(
+    source: R,
+    name: &str,
+    strictness: Strictness,
+) -> Result<Loaded<CompanyMap>, DataError> {
+    let mut csv = CsvFile::new(source, name)?;
+    let id_column = csv.column("company_cd")?;
+    let name_column = csv.column("company_name")?;
+
+    let mut result = Loaded {
+        data: IndexMap::with_hasher(RandomState::new()),
+        warnings: Vec::new(),
+    };
+
+    for row in csv.rows() {
+        let company = row.and_then(|row| {
+            let id = CompanyId(row.parse(id_column)?);
+            if result.data.contains_key(&id) {
+                return Err(DataError::DuplicateId {
+                    file: row.file.clone(),
+                    line: row.line,
+                    column: "company_cd",
+                    id: id.0.to_string(),
+                });
+            }
+
+            Ok(Company {
+                id,
+                name: row.get(name_column)?.to_owned(),
+            })
+        });
+
+        match company {
+            Ok(company) => {
+                result.data.insert(company.id, company);
+            }
+            Err(e) => result.reject(strictness, e)?,
+        }
+    }
+
+    Ok(result)
+}
+
+/// Load station names in romaji from a CSV file with `station_cd` and `station_name_r` columns,
+/// like a full ekidata station list
+pub fn load_romaji_from_path<P: AsRef<Path>>(
+    path: P,
+    strictness: Strictness,
+) -> Result<Loaded<RomajiMap>, DataError> {
+    let file = open(&path)?;
+    load_romaji_from_reader(file, &path.as_ref().display().to_string(), strictness)
+}
+
+/// Load station names in romaji from any source of CSV data with `station_cd` and
+/// `station_name_r` columns. Rows with an empty name are skipped. `name` is only used to identify
+/// the source in errors.
+pub fn load_romaji_from_reader<R: Read>(
+    source: R,
+    name: &str,
+    strictness: Strictness,
+) -> Result<Loaded<RomajiMap>, DataError> {
+    let mut csv = CsvFile::new(source, name)?;
+    let id_column = csv.column("station_cd")?;
+    let romaji_column = csv.column("station_name_r")?;
+
+    let mut result = Loaded {
+        data: HashMap::with_hasher(RandomState::new()),
+        warnings: Vec::new(),
+    };
+
+    for row in csv.rows() {
+        let name = row.and_then(|row| {
+            let id = StationId(row.parse(id_column)?);
+            if result.data.contains_key(&id) {
+                return Err(DataError::DuplicateId {
+                    file: row.file.clone(),
+                    line: row.line,
+                    column: "station_cd",
+                    id: id.0.to_string(),
+                });
+            }
+
+            Ok(row
+                .get_optional(Some(romaji_column))
+                .map(|romaji| (id, romaji.to_owned())))
+        });
+
+        match name {
+            Ok(Some((id, romaji))) => {
+                result.data.insert(id, romaji);
+            }
+            Ok(None) => {}
+            Err(e) => result.reject(strictness, e)?,
+        }
+    }
+
+    Ok(result)
+}
+
+/// Load a timetable from a CSV file in the format of GTFS's `stop_times.txt`
+pub fn load_stop_times_from_path<P: AsRef<Path>>(
+    path: P,
+    network: &Network,
+    strictness: Strictness,
+) -> Result<Loaded<Timetable>, DataError> {
+    let file = open(&path)?;
+    load_stop_times_from_reader(
+        file,
+        &path.as_ref().display().to_string(),
+        network,
+        strictness,
+    )
+}
+
+/// Load a timetable from any source of CSV data in the format of GTFS's `stop_times.txt`. The
+/// `stop_id` column holds station IDs from `network`. Either of `arrival_time` and
+/// `departure_time` can be left empty, in which case it's taken to be the same as the other.
+/// `name` is only used to identify the source in errors.
+pub fn load_stop_times_from_reader<R: Read>(
+    source: R,
+    name: &str,
+    network: &Network,
+    strictness: Strictness,
+) -> Result<Loaded<Timetable>, DataError> {
+    let mut csv = CsvFile::new(source, name)?;
+    let trip_column = csv.column("trip_id")?;
+    let arrival_column = csv.column("arrival_time")?;
+    let departure_column = csv.column("departure_time")?;
+    let stop_column = csv.column("stop_id")?;
+    let sequence_column = csv.column("stop_sequence")?;
+
+    // Stops of each trip, in the order the trips first appear
+    let mut trips: IndexMap<String, Vec<(u32, StopTime)>, RandomState> =
+        IndexMap::with_hasher(RandomState::new());
+    let mut result: Loaded<Timetable> = Loaded {
+        data: Timetable::default(),
+        warnings: Vec::new(),
+    };
+
+    for row in csv.rows() {
+        let stop_time = row.and_then(|row| {
+            let station_id = StationId(row.parse(stop_column)?);
+            if !network.stations.contains_key(&station_id) {
+                return Err(DataError::UnknownStation {
+                    file: row.file.clone(),
+                    line: row.line,
+                    column: stop_column.name,
+                    station_id,
+                });
+            }
+
+            let arrival = row.get_optional(Some(arrival_column));
+            let departure = row.get_optional(Some(departure_column));
+            let (arrival, departure) = match (arrival, departure) {
+                (Some(_), Some(_)) => (row.parse(arrival_column)?, row.parse(departure_column)?),
+                (Some(_), None) => {
+                    let arrival = row.parse(arrival_column)?;
+                    (arrival, arrival)
+                }
+                (None, _) => {
+                    let departure = row.parse(departure_column)?;
+                    (departure, departure)
+                }
+            };
+
+            let stop_time = StopTime {
+                station_id,
+                arrival,
+                departure,
+            };
+            Ok((
+                row.get(trip_column)?.to_owned(),
+                (row.parse(sequence_column)?, stop_time),
+            ))
+        });
+
+        match stop_time {
+            Ok((trip_id, stop)) => trips.entry(trip_id).or_default().push(stop),
+            Err(e) => result.reject(strictness, e)?,
+        }
+    }
+
+    let trips = trips
+        .into_iter()
+        .map(|(_, mut stops)| {
+            stops.sort_by_key(|(sequence, _)| *sequence);
+            stops.into_iter().map(|(_, stop_time)| stop_time).collect()
+        })
+        .collect();
+
+    result.data = Timetable::from_stop_times(network, trips);
+    Ok(result)
+}
+
+/// Load a whole `Network`. With no data directory the embedded data is used. With one, the
+/// station and connection files have to be in it, while the line and company files are used
+/// only if present, since the embedded data doesn't have them either.
+pub fn load_network(
+    data_dir: Option<&Path>,
+    strictness: Strictness,
+) -> Result<Loaded<Network>, DataError> {
+    let mut warnings = Vec::new();
+
+    let (stations, connections, lines, companies) = match data_dir {
+        Some(dir) => {
+            let stations =
+                load_stations_from_path(dir.join(STATIONS_FILE), strictness)?.take(&mut warnings);
+            let connections =
+                load_connections_from_path(dir.join(CONNECTIONS_FILE), &stations, strictness)?
+                    .take(&mut warnings);
+
+            let lines_path = dir.join(LINES_FILE);
+            let lines = if lines_path.exists() {
+                load_lines_from_path(lines_path, strictness)?.take(&mut warnings)
+            } else {
+                IndexMap::with_hasher(RandomState::new())
+            };
+
+            let companies_path = dir.join(COMPANIES_FILE);
+            let companies = if companies_path.exists() {
+                load_companies_from_path(companies_path, strictness)?.take(&mut warnings)
+            } else {
+                IndexMap::with_hasher(RandomState::new())
+            };
+
+            (stations, connections, lines, companies)
+        }
+        None => {
+            let stations = load_stations(strictness)?.take(&mut warnings);
+            let connections = load_connections(&stations, strictness)?.take(&mut warnings);
+            (
+                stations,
+                connections,
+                IndexMap::with_hasher(RandomState::new()),
+                IndexMap::with_hasher(RandomState::new()),
+            )
+        }
+    };
+
+    let mut network = Network::new(stations, connections.connections, lines, companies);
+    network.add_connection_lines(connections.lines);
+
+    Ok(Loaded {
+        data: network,
+        warnings,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATIONS: &str = "station_cd,station_name,lon,lat
+1110101,函館,140.726413,41.773709
+1110102,五稜郭,140.733539,north
+1110103,桔梗,140.722952,41.846457
+";
+
+    #[test]
+    fn bad_row_fails_strict_loading() {
+        let error =
+            load_stations_from_reader(STATIONS.as_bytes(), "stations.csv", Strictness::Strict)
+                .err()
+                .unwrap();
+        match &error {
+            DataError::InvalidField {
+                file,
+                line,
+                column,
+                value,
+            } => {
+                assert_eq!(file, "stations.csv");
+                assert_eq!(*line, 3);
+                assert_eq!(*column, "lat");
+                assert_eq!(value, "north");
+            }
+            e => panic!("unexpected error {:?}", e),
+        }
+        assert_eq!(
+            error.to_string(),
+            "stations.csv:3: invalid `lat` value \"north\""
+        );
     }
 
-    result
+    #[test]
+    fn bad_row_is_skipped_by_lenient_loading() {
+        let loaded =
+            load_stations_from_reader(STATIONS.as_bytes(), "stations.csv", Strictness::Lenient)
+                .unwrap();
+        let ids: Vec<_> = loaded.data.keys().map(|id| id.0).collect();
+        assert_eq!(ids, vec![1110101, 1110103]);
+
+        assert_eq!(loaded.warnings.len(), 1);
+        assert_eq!(
+            loaded.warnings[0].to_string(),
+            "stations.csv:3: invalid `lat` value \"north\""
+        );
+    }
+
+    #[test]
+    fn empty_field_is_reported_by_column() {
+        let data = "station_cd,station_name,lon,lat\n1110101,,140.726413,41.773709\n";
+        let error = load_stations_from_reader(data.as_bytes(), "stations.csv", Strictness::Strict)
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "stations.csv:2: `station_name` is empty");
+    }
+
+    #[test]
+    fn missing_column_is_reported() {
+        let data = "station_cd,station_name,lon\n1110101,函館,140.726413\n";
+        let error = load_stations_from_reader(data.as_bytes(), "stations.csv", Strictness::Strict)
+            .err()
+            .unwrap();
+        assert!(matches!(
+            error,
+            DataError::MissingColumn { column: "lat", .. }
+        ));
+    }
+
+    #[test]
+    fn broken_csv_is_reported_by_line() {
+        let data = "station_cd,station_name,lon,lat\n1110101,函館,140.726413,41.773709\n1110102\n";
+        let error = load_stations_from_reader(data.as_bytes(), "stations.csv", Strictness::Strict)
+            .err()
+            .unwrap();
+        assert!(matches!(error, DataError::Csv { line: 3, .. }));
+    }
+
+    #[test]
+    fn duplicate_station_is_rejected() {
+        let data = "station_cd,station_name,lon,lat
+1110101,函館,140.726413,41.773709
+1110101,五稜郭,140.733539,41.803557
+";
+        let error = load_stations_from_reader(data.as_bytes(), "stations.csv", Strictness::Strict)
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "stations.csv:3: `station_cd` \"1110101\" is already taken"
+        );
+
+        // The first station with the ID is kept
+        let loaded =
+            load_stations_from_reader(data.as_bytes(), "stations.csv", Strictness::Lenient)
+                .unwrap();
+        assert_eq!(loaded.warnings.len(), 1);
+        assert_eq!(loaded.data.len(), 1);
+        assert_eq!(loaded.data[&StationId(1110101)].name, "函館");
+    }
+
+    #[test]
+    fn duplicate_line_is_rejected() {
+        let data = "line_cd,line_name
+11101,JR函館本線(函館～長万部)
+11101,JR函館本線(長万部～小樽)
+";
+        let error = load_lines_from_reader(data.as_bytes(), "line.csv", Strictness::Strict)
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "line.csv:3: `line_cd` \"11101\" is already taken"
+        );
+
+        // The first line with the ID is kept
+        let loaded =
+            load_lines_from_reader(data.as_bytes(), "line.csv", Strictness::Lenient).unwrap();
+        assert_eq!(loaded.warnings.len(), 1);
+        assert_eq!(loaded.data.len(), 1);
+        assert_eq!(loaded.data[&LineId(11101)].name, "JR函館本線(函館～長万部)");
+    }
+
+    #[test]
+    fn duplicate_company_is_rejected() {
+        let data = "company_cd,company_name
+1,JR北海道
+1,JR東日本
+";
+        let error = load_companies_from_reader(data.as_bytes(), "company.csv", Strictness::Strict)
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "company.csv:3: `company_cd` \"1\" is already taken"
+        );
+
+        // The first company with the ID is kept
+        let loaded =
+            load_companies_from_reader(data.as_bytes(), "company.csv", Strictness::Lenient)
+                .unwrap();
+        assert_eq!(loaded.warnings.len(), 1);
+        assert_eq!(loaded.data.len(), 1);
+        assert_eq!(loaded.data[&CompanyId(1)].name, "JR北海道");
+    }
+
+    #[test]
+    fn duplicate_romaji_is_rejected() {
+        let data = "station_cd,station_name_r
+1110101,Hakodate
+1110101,Goryokaku
+";
+        let error = load_romaji_from_reader(data.as_bytes(), "romaji.csv", Strictness::Strict)
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "romaji.csv:3: `station_cd` \"1110101\" is already taken"
+        );
+
+        // The first name for the station is kept
+        let loaded =
+            load_romaji_from_reader(data.as_bytes(), "romaji.csv", Strictness::Lenient).unwrap();
+        assert_eq!(loaded.warnings.len(), 1);
+        assert_eq!(loaded.data.len(), 1);
+        assert_eq!(loaded.data[&StationId(1110101)], "Hakodate");
+    }
+
+    #[test]
+    fn unknown_station_is_reported_by_column() {
+        let stations =
+            load_stations_from_reader(STATIONS.as_bytes(), "stations.csv", Strictness::Lenient)
+                .unwrap()
+                .data;
+        let connections = "station_cd1,station_cd2\n1110101,1110103\n1110103,1110104\n";
+
+        let error = load_connections_from_reader(
+            connections.as_bytes(),
+            "join.csv",
+            &stations,
+            Strictness::Strict,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "join.csv:3: `station_cd2` refers to unknown station 1110104"
+        );
+
+        let loaded = load_connections_from_reader(
+            connections.as_bytes(),
+            "join.csv",
+            &stations,
+            Strictness::Lenient,
+        )
+        .unwrap();
+        assert_eq!(loaded.warnings.len(), 1);
+        assert!(loaded.data.connections[&StationId(1110101)].contains(&StationId(1110103)));
+    }
+
+    #[test]
+    fn connection_lines_come_from_line_column() {
+        let stations =
+            load_stations_from_reader(STATIONS.as_bytes(), "stations.csv", Strictness::Lenient)
+                .unwrap()
+                .data;
+        let connections = "line_cd,station_cd1,station_cd2\n99999,1110101,1110103\n";
+        let connections = load_connections_from_reader(
+            connections.as_bytes(),
+            "join.csv",
+            &stations,
+            Strictness::Strict,
+        )
+        .unwrap()
+        .data;
+
+        let mut network = Network::new(
+            stations,
+            connections.connections,
+            IndexMap::with_hasher(RandomState::new()),
+            IndexMap::with_hasher(RandomState::new()),
+        );
+        network.add_connection_lines(connections.lines);
+        assert_eq!(
+            network.lines_between(StationId(1110103), StationId(1110101)),
+            [LineId(99999)]
+        );
+    }
+
+    #[test]
+    fn stations_are_grouped_through_each_other() {
+        // 2 is too far from 1 to be grouped with it directly, but 3 is close to both
+        let data = "station_cd,station_name,lon,lat
+1,新宿,139.7,35.0
+2,新宿,139.7,35.0144
+3,新宿,139.7,35.0072
+4,新宿,139.7,36.0
+";
+        let stations =
+            load_stations_from_reader(data.as_bytes(), "stations.csv", Strictness::Strict)
+                .unwrap()
+                .data;
+        let groups: Vec<_> = stations.values().map(|s| s.group_id.0).collect();
+        assert_eq!(groups, vec![1, 1, 1, 4]);
+    }
 }
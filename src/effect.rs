@@ -1,105 +1,244 @@
 use crate::{
-    data::{Station, StationId},
-    map::{Degree, MapCoord, MapFrame},
-    tile::{Tile, TilePos},
+    clock::{activity_at, SimClock},
+    data::{Network, StationId},
+    geometry::point_along,
+    map::{MapCoord, MapFrame, Meters},
+    route::{find_route, neighbors, RouteMetric},
+    schedule::{SimTime, Timetable, Trip},
+    tile::{Tile, TileIterator, TilePos},
 };
 use ahash::RandomState;
-use crossbeam_channel::{unbounded, Sender};
-use indexmap::IndexMap;
+use crossbeam_channel::{unbounded, Receiver, Sender};
 use line_drawing::Supercover;
 use pathfinding::directed::astar::astar;
-use rand::{thread_rng, Rng};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use rand_distr::{Distribution, Gamma};
-use std::{
-    collections::{HashMap, HashSet},
-    iter::FromIterator,
-    rc::Rc,
-    sync::{Arc, RwLock},
-    thread,
-};
+use std::{collections::HashMap, fmt, rc::Rc, str::FromStr};
 
 const MAX_STATION_POPULARITY: u32 = 20;
 const MAX_EFFECTS: usize = 500;
+
+/// The most trains that run to a timetable at once. The generated timetable has about 6,800
+/// trains out at rush hour, which all fit, but a big GTFS feed could have many more.
+const MAX_SCHEDULED_TRAINS: usize = 10_000;
 const STATION_BLINK_COLOR: [u8; 3] = [0xff, 0xFF, 0x00];
-const TRAIN_COLOR: [u8; 3] = [0x2A, 0xAF, 0xDB];
+
+/// Trains get brighter the busier the railways are, from late at night to rush hour
+const TRAIN_COLORS: [[u8; 3]; 5] = [
+    [0x1F, 0x5E, 0x7E],
+    [0x22, 0x72, 0x95],
+    [0x24, 0x86, 0xAD],
+    [0x27, 0x9B, 0xC4],
+    [0x2A, 0xAF, 0xDB],
+];
+
+/// Trains without a timetable run at a random speed between these, in km/h
+const MIN_TRAIN_SPEED: f32 = 18.0;
+const MAX_TRAIN_SPEED: f32 = 320.0;
+
+/// How trains without a timetable pick their route between two stations
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Routing {
+    /// Prefer stations that trains have visited less often recently, which spreads trains out
+    /// over the whole network
+    #[default]
+    Popularity,
+
+    /// Take the shortest route along the tracks
+    Distance,
+}
+
+impl FromStr for Routing {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "popularity" => Ok(Routing::Popularity),
+            "distance" => Ok(Routing::Distance),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Used to keep track of how often trains visit each station in order to adjust A* heuristics
+type StationPopularity = HashMap<StationId, u32, RandomState>;
 
 pub struct EffectManager {
     pub effects: Vec<Box<dyn Effect>>,
-    stations: Rc<IndexMap<StationId, Station, RandomState>>,
-    connections: Rc<HashMap<StationId, HashSet<StationId, RandomState>, RandomState>>,
+    network: Rc<Network>,
 
-    /// Used to keep track of how often trains visit each station in order to adjust A* heuristics
-    station_popularity: Arc<RwLock<HashMap<StationId, u32, RandomState>>>,
+    /// The only source of randomness in the simulation, so that running it again with the same
+    /// seed gives the same result
+    rng: StdRng,
 
-    // Channel to update the station_popularity map
+    station_popularity: StationPopularity,
+
+    /// The time of day as of the last update, to tell which trips have left since then
+    time: SimTime,
+
+    /// When set, trains run according to this instead of being sent between random stations
+    timetable: Option<Rc<Timetable>>,
+
+    /// How trains that aren't running to the timetable find their way
+    routing: Routing,
+
+    // Channel for the Trains to report the stations they reach. Handled once per update, so that
+    // the popularity changes at the same point in every run.
     write_sender: Sender<StationId>,
+    write_receiver: Receiver<StationId>,
 }
 
 impl EffectManager {
-    pub fn new(
-        stations: Rc<IndexMap<StationId, Station, RandomState>>,
-        connections: Rc<HashMap<StationId, HashSet<StationId, RandomState>, RandomState>>,
-    ) -> Self {
+    pub fn new(network: Rc<Network>, seed: u64) -> Self {
         let (write_sender, write_receiver) = unbounded();
 
-        let build_hasher = RandomState::new();
-        let station_popularity: Arc<RwLock<HashMap<StationId, u32, RandomState>>> =
-            Arc::new(RwLock::new(HashMap::with_hasher(build_hasher)));
-
-        let pop_clone = station_popularity.clone();
-
-        // Thread that will update the station_popularity map when messages come in from the Trains
-        thread::spawn(move || {
-            for msg in write_receiver {
-                if let Ok(mut guard) = pop_clone.write() {
-                    let current = guard.entry(msg).or_default();
-                    *current = current.saturating_add(1);
-                    if let Some(max) = guard.values().max() {
-                        if *max > MAX_STATION_POPULARITY {
-                            for val in guard.values_mut() {
-                                *val /= (MAX_STATION_POPULARITY / 10) as u32
-                            }
-                        }
-                    }
-                }
-            }
-        });
-
         Self {
             effects: Vec::new(),
-            stations,
-            connections,
-            station_popularity,
+            network,
+            rng: StdRng::seed_from_u64(seed),
+            station_popularity: HashMap::with_hasher(RandomState::new()),
+            time: SimTime::default(),
+            timetable: None,
+            routing: Routing::default(),
             write_sender,
+            write_receiver,
         }
     }
 
-    pub fn update(&mut self) {
+    /// Switch to running trains according to `timetable`. Effects that are already running are
+    /// removed, and all the trips that should be on their way at the given time of day are
+    /// started right away.
+    pub fn set_timetable(&mut self, timetable: Rc<Timetable>, time: SimTime) {
+        self.time = time;
+        self.timetable = Some(timetable);
+        self.effects.clear();
+        self.start_running_trips();
+    }
+
+    /// Change how trains pick their routes. Trains already on their way keep their route.
+    pub fn set_routing(&mut self, routing: Routing) {
+        self.routing = routing;
+    }
+
+    /// How often trains have reached the given station lately. Counts are scaled down now and
+    /// then, so they only mean something compared to each other.
+    pub fn station_popularity(&self, station_id: StationId) -> u32 {
+        self.station_popularity
+            .get(&station_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Send a train through the given stations, in order, alongside the others. Returns false,
+    /// without sending it, if there are already as many effects as there's room for.
+    pub fn spawn_train(&mut self, station_ids: &[StationId]) -> bool {
+        let max_effects = if self.timetable.is_some() {
+            MAX_SCHEDULED_TRAINS
+        } else {
+            MAX_EFFECTS
+        };
+        if self.effects.len() >= max_effects {
+            return false;
+        }
+
+        let train = Train::along(
+            self.network.clone(),
+            self.write_sender.clone(),
+            station_ids,
+            self.time,
+            &mut self.rng,
+        );
+        self.effects.push(Box::new(train));
+        true
+    }
+
+    /// Start a train for every trip in the timetable that's on its way at the current time, up to
+    /// MAX_SCHEDULED_TRAINS in all
+    fn start_running_trips(&mut self) {
+        if let Some(timetable) = &self.timetable {
+            let room = MAX_SCHEDULED_TRAINS.saturating_sub(self.effects.len());
+            for trip in timetable.trips_running(self.time).take(room) {
+                self.effects.push(Box::new(ScheduledTrain::new(
+                    self.network.clone(),
+                    timetable.clone(),
+                    timetable.trips()[trip],
+                    self.time,
+                )));
+            }
+        }
+    }
+
+    pub fn update(&mut self, clock: &SimClock) {
+        let previous_time = self.time;
+        self.time = clock.time();
+
         // Update the internal state of all effects
         for effect in self.effects.iter_mut() {
-            effect.update();
+            effect.update(clock);
         }
 
         // Remove any expired effects
         self.effects.retain(|effect| effect.is_valid());
 
-        // Add some new effects, maybe
-        if self.effects.len() < MAX_EFFECTS {
-            let mut rng = thread_rng();
+        // Count the stations the trains reached during this update
+        for station_id in self.write_receiver.try_iter() {
+            let popularity = &mut self.station_popularity;
+            let current = popularity.entry(station_id).or_default();
+            *current = current.saturating_add(1);
+            if let Some(max) = popularity.values().max() {
+                if *max > MAX_STATION_POPULARITY {
+                    for val in popularity.values_mut() {
+                        *val /= MAX_STATION_POPULARITY / 10
+                    }
+                }
+            }
+        }
+
+        if let Some(timetable) = &self.timetable {
+            // Send off the trains that are due to leave, as long as there's room for them. If the
+            // time went backwards, a new day started (or the clock was turned back), and the
+            // trains from before are gone. Trips running at both times are started again, so
+            // their old trains are dropped rather than left to run alongside the new ones.
+            if self.time < previous_time {
+                self.effects.retain(|effect| effect.trip().is_none());
+                self.start_running_trips();
+            } else {
+                for trip in timetable.trips_starting(previous_time, self.time) {
+                    let trip = timetable.trips()[trip];
+                    if self.effects.len() >= MAX_SCHEDULED_TRAINS {
+                        break;
+                    }
+                    if trip.end > self.time {
+                        self.effects.push(Box::new(ScheduledTrain::new(
+                            self.network.clone(),
+                            timetable.clone(),
+                            trip,
+                            self.time,
+                        )));
+                    }
+                }
+            }
+        } else if self.effects.len() < MAX_EFFECTS {
+            // Add some new effects, maybe
+            let roll: f32 = self.rng.gen();
 
-            let roll: f32 = rng.gen();
+            // Trains finish their journeys sooner when time passes faster, so send them off more
+            // often to keep about the same number on the map. There are fewer late at night.
+            let train_rate = 0.15 * clock.activity() * clock.speed_ratio();
 
             if roll < 0.001 {
-                let blink = StationBlink::new(self.stations.clone());
+                let blink = StationBlink::new(&self.network, &mut self.rng);
                 self.effects.push(Box::new(blink));
             }
 
-            if roll < 0.15 {
+            if roll < train_rate {
                 if let Some(train) = Train::new(
-                    self.stations.clone(),
-                    self.connections.clone(),
+                    self.network.clone(),
                     self.write_sender.clone(),
-                    self.station_popularity.clone(),
+                    &self.station_popularity,
+                    self.routing,
+                    self.time,
+                    &mut self.rng,
                 ) {
                     self.effects.push(Box::new(train));
                 }
@@ -108,8 +247,10 @@ impl EffectManager {
     }
 }
 
-pub trait Effect {
-    fn update(&mut self);
+pub trait Effect: fmt::Debug {
+    /// Move the effect on by one frame. The clock has already been moved on to the time after
+    /// the frame.
+    fn update(&mut self, clock: &SimClock);
 
     /// When this turns to false, this Effect will be removed on the next update cycle. Should start
     /// at true and only flip to false once.
@@ -119,20 +260,24 @@ pub trait Effect {
     fn get_colors(&self, map_frame: &MapFrame) -> Vec<(Tile, &[u8; 3])>;
 
     fn priority(&self) -> u8; // higher = more priority
+
+    /// The timetabled trip this effect is running, if it's a scheduled train
+    fn trip(&self) -> Option<Trip> {
+        None
+    }
 }
 
 /// An effect that represents a station that's blinking for a few frames
+#[derive(Debug)]
 pub struct StationBlink {
     coord: MapCoord,
     remaining_frames: u16,
 }
 
 impl StationBlink {
-    pub fn new(stations: Rc<IndexMap<StationId, Station, RandomState>>) -> Self {
-        let mut rng = thread_rng();
-
-        let random_station_index = rng.gen_range(0, stations.len());
-        let random_station = stations.get_index(random_station_index).unwrap().1;
+    pub fn new<R: Rng>(network: &Network, rng: &mut R) -> Self {
+        let random_station_index = rng.gen_range(0, network.stations.len());
+        let random_station = network.stations.get_index(random_station_index).unwrap().1;
 
         StationBlink {
             coord: random_station.coord,
@@ -142,7 +287,7 @@ impl StationBlink {
 }
 
 impl Effect for StationBlink {
-    fn update(&mut self) {
+    fn update(&mut self, _clock: &SimClock) {
         self.remaining_frames = self.remaining_frames.saturating_sub(1);
     }
 
@@ -173,13 +318,13 @@ impl Effect for StationBlink {
 pub struct TrackSection {
     start_station_id: StationId,
     end_station_id: StationId,
-    length: Degree,
+    length: Meters,
 }
 
 /// An effect that represents a train traveling, lighting up the track on the way
 pub struct Train {
     // Shared with the World struct; needed to calculate the path to take
-    stations: Rc<IndexMap<StationId, Station, RandomState>>,
+    network: Rc<Network>,
 
     /// Stations pairs to traverse in order
     track_sections: Vec<TrackSection>,
@@ -187,26 +332,31 @@ pub struct Train {
     /// Current index in the `track_sections` Vec. Basically what section of the line the train is on
     current_section_index: usize,
 
-    /// How much of the current section the train has traveled. Units are degrees
-    current_line_progress: Degree,
+    /// How much of the current section the train has traveled
+    current_line_progress: Meters,
+
+    /// How fast the train runs, in km/h
+    speed: f32,
 
-    /// How far to travel each move
-    degrees_per_move: Degree,
+    /// The color for the time of day
+    color: &'static [u8; 3],
 
     write_sender: Sender<StationId>,
 }
 
 impl Train {
-    pub fn new(
-        stations: Rc<IndexMap<StationId, Station, RandomState>>,
-        connections: Rc<HashMap<StationId, HashSet<StationId, RandomState>, RandomState>>,
+    pub fn new<R: Rng>(
+        network: Rc<Network>,
         write_sender: Sender<StationId>,
-        station_popularity: Arc<RwLock<HashMap<StationId, u32, RandomState>>>,
+        station_popularity: &StationPopularity,
+        routing: Routing,
+        now: SimTime,
+        rng: &mut R,
     ) -> Option<Self> {
-        // Chose a random start and end station. The graph of stations only has 2 connected
-        // components (Okinawa and everything else), so there's a good chance that there will be a
-        // path between them.
-        let mut rng = thread_rng();
+        // Chose a random start and end station. Counting transfers between the stations of a
+        // group, the graph of stations only has 2 connected components (Okinawa and everything
+        // else), so there's a good chance that there will be a path between them.
+        let stations = &network.stations;
         let start_index = rng.gen_range(0, stations.len());
         let end_index = rng.gen_range(0, stations.len());
 
@@ -216,124 +366,112 @@ impl Train {
         // For the A* heuristic, use the current "popularity" of a station. This should balance
         // things out so that the absolute shortest path isn't taken all the time, and promote
         // usage of less-traveled stations.
-        // For obtaining the heuristic throught he RwLock, we're using `try_read` instead of `read`
-        // so we don't block the thread. It's fine if occasionally we can't get the proper
-        // heuristic due to the RwLock being locked for writing.
-
-        let get_neighbors = |id: &StationId| -> Vec<(StationId, u32)> {
-            let neighbor_ids: Vec<&StationId> =
-                connections.get(id).map(Vec::from_iter).unwrap_or_default();
-
-            neighbor_ids
-                .iter()
-                .map(|i| {
-                    let score: u32 = if let Ok(guard) = station_popularity.try_read() {
-                        guard.get(i).copied().unwrap_or(1)
-                    } else {
-                        1
-                    };
-                    (**i, score)
-                })
-                .collect()
-        };
-
-        let heuristic = |id: &StationId| -> u32 {
-            if let Ok(guard) = station_popularity.try_read() {
-                guard.get(id).copied().unwrap_or(1)
-            } else {
-                1
+        let get_score =
+            |id: &StationId| -> u32 { station_popularity.get(id).copied().unwrap_or(1) };
+
+        // If there was no path to be found, just give up
+        let station_ids = match routing {
+            // Traveling along a connection, or transferring to another line at the same station,
+            // costs the popularity of the station it goes to
+            Routing::Popularity => {
+                astar(
+                    start_id,
+                    |id| neighbors(&network, *id, |i| get_score(&i), |i| get_score(&i)),
+                    get_score,
+                    |id| id == end_id,
+                )?
+                .0
+            }
+            // The same as planning a route by distance between the two stations' groups
+            Routing::Distance => {
+                find_route(
+                    &network,
+                    network.group_of(*start_id)?.id,
+                    network.group_of(*end_id)?.id,
+                    RouteMetric::Distance,
+                )?
+                .stations
             }
         };
+        Some(Self::along(network, write_sender, &station_ids, now, rng))
+    }
 
-        if let Some((station_ids, _)) = astar(start_id, get_neighbors, heuristic, |id| id == end_id)
-        {
-            let mut track_sections: Vec<TrackSection> = Vec::new();
-            for window in station_ids.windows(2) {
-                let start_station_id = window[0];
-                let end_station_id = window[1];
-                let start_coord = stations.get(&start_station_id).unwrap().coord;
-                let end_coord = stations.get(&end_station_id).unwrap().coord;
-
-                track_sections.push(TrackSection {
-                    start_station_id,
-                    end_station_id,
-                    length: start_coord.distance_to(&end_coord),
-                });
-            }
+    /// A train that runs through the given stations, in order, starting at the time `now`
+    pub fn along<R: Rng>(
+        network: Rc<Network>,
+        write_sender: Sender<StationId>,
+        station_ids: &[StationId],
+        now: SimTime,
+        rng: &mut R,
+    ) -> Self {
+        let mut track_sections: Vec<TrackSection> = Vec::new();
+        for window in station_ids.windows(2) {
+            let start_station_id = window[0];
+            let end_station_id = window[1];
+
+            // Stations are drawn at their group's position, so a transfer has no length
+            track_sections.push(TrackSection {
+                start_station_id,
+                end_station_id,
+                length: network.track_length(start_station_id, end_station_id),
+            });
+        }
 
-            // Just based on trying out various values, this distribution seems to give a good
-            // range of speeds
-            let gamma = Gamma::new(1.0, 0.002).unwrap();
-            let degrees_per_move = gamma.sample(&mut rng) + 0.0005;
-
-            Some(Self {
-                stations,
-                track_sections,
-                current_section_index: 0,
-                current_line_progress: 0.0.into(),
-                degrees_per_move: degrees_per_move.into(),
-                write_sender,
-            })
-        } else {
-            // If there was no path to be found, just give up
-            None
+        // Just based on trying out various values, this distribution seems to give a good
+        // range of speeds, mostly between local and limited express trains
+        let gamma = Gamma::new(1.0, 72.0).unwrap();
+        let speed = (gamma.sample(rng) + MIN_TRAIN_SPEED).min(MAX_TRAIN_SPEED);
+
+        Self {
+            network,
+            track_sections,
+            current_section_index: 0,
+            current_line_progress: 0.0.into(),
+            speed,
+            color: train_color(now),
+            write_sender,
         }
     }
+}
 
-    /// Get the tile-wise path (between two stations) that the train is currently traveling on
-    fn get_current_path(
-        &self,
-        current_track_section: &TrackSection,
-        map_frame: &MapFrame,
-    ) -> Vec<Tile> {
-        let start_station = self
-            .stations
-            .get(&current_track_section.start_station_id)
-            .unwrap();
-        let end_station = self
-            .stations
-            .get(&current_track_section.end_station_id)
-            .unwrap();
-
-        let start_tile = map_frame.get_tile(start_station.coord);
-        let end_tile = map_frame.get_tile(end_station.coord);
-
-        let tiles_in_path: Vec<(TilePos, TilePos)> = Supercover::new(
-            (start_tile.x.0, start_tile.y.0),
-            (end_tile.x.0, end_tile.y.0),
-        )
-        .map(|(x, y)| (TilePos(x), TilePos(y)))
-        .collect();
-
-        tiles_in_path
-            .into_iter()
-            .map(|(x, y)| Tile { x, y })
-            .collect()
+impl fmt::Debug for Train {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Train")
+            .field("track_sections", &self.track_sections)
+            .field("current_section_index", &self.current_section_index)
+            .field("current_line_progress", &self.current_line_progress)
+            .field("speed", &self.speed)
+            .finish()
     }
 }
 
 impl Effect for Train {
-    fn update(&mut self) {
-        // Travel a fixed amount of degrees per x ticks
-        self.current_line_progress += self.degrees_per_move;
+    fn update(&mut self, clock: &SimClock) {
+        self.color = train_color(clock.time());
 
-        // Seems like this should always be Some, but once it was None for some reason. Ignore
-        // that case, not sure why it's happening at the moment. It happened when I set all the
-        // trains to be really fast (0.007 degrees / tick).
-        if let Some(current_track_section) = self.track_sections.get(self.current_section_index) {
-            if self.current_line_progress >= current_track_section.length {
-                self.current_line_progress = 0.0.into();
-                self.current_section_index += 1;
-
-                // Reached a new station at the end of the current TrackSection; update the popularity map
-                if let Some(current_station) =
-                    self.stations.get(&current_track_section.end_station_id)
-                {
-                    // The channel is unbounded so this shouldn't error; regardless we can ignore
-                    // errors here. It's not vital that every message gets through, we're only
-                    // using this for rough heuristics
-                    let _ = self.write_sender.try_send(current_station.id);
-                }
+        // Travel as far as the train's speed takes it in the time that passes with each frame
+        self.current_line_progress += Meters(self.speed / 3.6 * clock.seconds_per_frame());
+
+        // A fast train can get through more than one section in a frame, and transfers have no
+        // length at all. Whatever's left over after a section carries on into the next one.
+        while let Some(current_track_section) = self.track_sections.get(self.current_section_index)
+        {
+            if self.current_line_progress < current_track_section.length {
+                break;
+            }
+            self.current_line_progress -= current_track_section.length;
+            self.current_section_index += 1;
+
+            // Reached a new station at the end of the current TrackSection; update the popularity map
+            if let Some(current_station) = self
+                .network
+                .stations
+                .get(&current_track_section.end_station_id)
+            {
+                // The channel is unbounded so this shouldn't error; regardless we can ignore
+                // errors here. It's not vital that every message gets through, we're only
+                // using this for rough heuristics
+                let _ = self.write_sender.try_send(current_station.id);
             }
         }
     }
@@ -348,20 +486,349 @@ impl Effect for Train {
 
     fn get_colors(&self, map_frame: &MapFrame) -> Vec<(Tile, &[u8; 3])> {
         if let Some(current_track_section) = self.track_sections.get(self.current_section_index) {
-            let path = self.get_current_path(&current_track_section, map_frame);
+            // A transfer has no length, and the train is at its start until the next update
+            let fraction = if current_track_section.length.0 > 0.0 {
+                (self.current_line_progress / current_track_section.length).0
+            } else {
+                0.0
+            };
+
+            train_box(
+                &self.network,
+                current_track_section.start_station_id,
+                current_track_section.end_station_id,
+                fraction,
+                map_frame,
+            )
+            .map(|tiles| tiles.map(|t| (t, self.color)).collect())
+            .unwrap_or_default()
+        } else {
+            // The train has finished its trip, and is removed on the next update
+            vec![]
+        }
+    }
+}
 
-            // Find the tile in the current track that the train is on
-            let tile_index = ((self.current_line_progress / current_track_section.length).0
-                * path.len() as f32) as usize;
-            let current_tile = path.get(tile_index).unwrap();
+/// A train running to a timetable. Where it is only depends on the time of day, so it can be
+/// placed correctly even when it's started partway through its trip.
+pub struct ScheduledTrain {
+    network: Rc<Network>,
+    timetable: Rc<Timetable>,
+    trip: Trip,
+    now: SimTime,
+}
 
-            Tile::get_box(*current_tile, map_frame.track_width())
-                .map(|t| (t, &TRAIN_COLOR))
-                .collect()
+impl ScheduledTrain {
+    pub fn new(network: Rc<Network>, timetable: Rc<Timetable>, trip: Trip, now: SimTime) -> Self {
+        Self {
+            network,
+            timetable,
+            trip,
+            now,
+        }
+    }
+
+    /// The stations the train is between, and how far along it is from the first to the second.
+    /// While it's stopped at a station, that station is both the start and the end.
+    fn position(&self) -> Option<(StationId, StationId, f32)> {
+        if !self.is_valid() {
+            return None;
+        }
+
+        let stops = &self.timetable.pattern(self.trip.pattern).stops;
+        let elapsed = self.now - self.trip.start;
+
+        // The first stop the train hasn't left yet
+        let next = stops.partition_point(|s| s.departure <= elapsed);
+        let previous = stops.get(next.checked_sub(1)?)?;
+        let next = stops.get(next)?;
+
+        if elapsed >= next.arrival {
+            Some((next.station_id, next.station_id, 0.0))
         } else {
-            // Only came across this case once, not sure exactly what causes it. It's so rare that
-            // let's just ignore it.
-            vec![]
+            let fraction = (elapsed - previous.departure).0 as f32
+                / (next.arrival - previous.departure).0 as f32;
+            Some((previous.station_id, next.station_id, fraction))
+        }
+    }
+}
+
+impl fmt::Debug for ScheduledTrain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ScheduledTrain")
+            .field("trip", &self.trip)
+            .field("now", &self.now)
+            .finish()
+    }
+}
+
+impl Effect for ScheduledTrain {
+    fn update(&mut self, clock: &SimClock) {
+        self.now = clock.time();
+    }
+
+    fn is_valid(&self) -> bool {
+        self.now >= self.trip.start && self.now < self.trip.end
+    }
+
+    fn priority(&self) -> u8 {
+        1
+    }
+
+    fn trip(&self) -> Option<Trip> {
+        Some(self.trip)
+    }
+
+    fn get_colors(&self, map_frame: &MapFrame) -> Vec<(Tile, &[u8; 3])> {
+        self.position()
+            .and_then(|(start_station_id, end_station_id, fraction)| {
+                train_box(
+                    &self.network,
+                    start_station_id,
+                    end_station_id,
+                    fraction,
+                    map_frame,
+                )
+            })
+            .map(|tiles| tiles.map(|t| (t, train_color(self.now))).collect())
+            .unwrap_or_default()
+    }
+}
+
+/// The color of trains at the given time of day
+fn train_color(time: SimTime) -> &'static [u8; 3] {
+    let brightest = TRAIN_COLORS.len() - 1;
+    let level = (activity_at(time) * brightest as f32).round() as usize;
+    &TRAIN_COLORS[level.min(brightest)]
+}
+
+/// The tiles a train covers when it's `fraction` of the way along the track between two
+/// stations, following the same tiles the track is drawn on. None if that part of the track isn't
+/// visible.
+fn train_box(
+    network: &Network,
+    start_station_id: StationId,
+    end_station_id: StationId,
+    fraction: f32,
+    map_frame: &MapFrame,
+) -> Option<TileIterator> {
+    // Only the straight piece of the track that the train is on needs to be walked
+    let path = network.track_path(start_station_id, end_station_id)?;
+    let (piece, fraction) = point_along(&path, fraction);
+    let start = map_frame.project(*path.get(piece)?);
+    let end = map_frame.project(*path.get(piece + 1)?);
+
+    // Don't bother working out the path of a track that's off screen
+    let (upper_left, lower_right) = map_frame.visible_bounds();
+    if start.x.0.max(end.x.0) < upper_left.x.0
+        || start.x.0.min(end.x.0) > lower_right.x.0
+        || start.y.0.min(end.y.0) > upper_left.y.0
+        || start.y.0.max(end.y.0) < lower_right.y.0
+    {
+        return None;
+    }
+
+    // Zoomed in, tracks are smoothed around the straight line between their ends, so the train
+    // is centered on that line too
+    let track_width = map_frame.track_width();
+    if track_width > 1 {
+        let start = map_frame.plane_tile_position(start);
+        let end = map_frame.plane_tile_position(end);
+        let center = (
+            start.0 + fraction * (end.0 - start.0),
+            start.1 + fraction * (end.1 - start.1),
+        );
+        return Some(Tile::box_around(center, track_width));
+    }
+
+    let start_tile = map_frame.plane_tile(start);
+    let end_tile = map_frame.plane_tile(end);
+
+    let path: Vec<(i32, i32)> = Supercover::new(
+        (start_tile.x.0, start_tile.y.0),
+        (end_tile.x.0, end_tile.y.0),
+    )
+    .collect();
+
+    // A fraction of exactly 1 would be past the end
+    let index = ((fraction * path.len() as f32) as usize).min(path.len().saturating_sub(1));
+    path.get(index).map(|(x, y)| {
+        let tile = Tile {
+            x: TilePos(*x),
+            y: TilePos(*y),
+        };
+        Tile::get_box(tile, track_width)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schedule::StopTime;
+    use indexmap::IndexMap;
+
+    fn empty_network() -> Rc<Network> {
+        Rc::new(Network::new(
+            IndexMap::with_hasher(RandomState::new()),
+            HashMap::with_hasher(RandomState::new()),
+            IndexMap::with_hasher(RandomState::new()),
+            IndexMap::with_hasher(RandomState::new()),
+        ))
+    }
+
+    #[test]
+    fn scheduled_train_is_valid_while_its_trip_runs() {
+        let network = empty_network();
+        let stop = |station_id, h, m| StopTime {
+            station_id: StationId(station_id),
+            arrival: SimTime::from_hms(h, m, 0),
+            departure: SimTime::from_hms(h, m, 0),
+        };
+        let timetable = Rc::new(Timetable::from_stop_times(
+            &network,
+            vec![vec![stop(1110101, 8, 0), stop(1110102, 8, 5)]],
+        ));
+        let trip = timetable.trips()[0];
+
+        let mut train = ScheduledTrain::new(network, timetable, trip, SimTime::from_hms(7, 59, 59));
+        assert!(!train.is_valid());
+
+        let mut clock = SimClock::new(SimTime::from_hms(8, 0, 0));
+        train.update(&clock);
+        assert!(train.is_valid());
+        assert_eq!(
+            train.position(),
+            Some((StationId(1110101), StationId(1110102), 0.0))
+        );
+
+        clock.set_time(SimTime::from_hms(8, 4, 59));
+        train.update(&clock);
+        assert!(train.is_valid());
+
+        clock.set_time(SimTime::from_hms(8, 5, 0));
+        train.update(&clock);
+        assert!(!train.is_valid());
+        assert_eq!(train.position(), None);
+    }
+
+    #[test]
+    fn turning_the_clock_back_keeps_one_train_per_trip() {
+        let network = empty_network();
+        let stop = |station_id, h, m| StopTime {
+            station_id: StationId(station_id),
+            arrival: SimTime::from_hms(h, m, 0),
+            departure: SimTime::from_hms(h, m, 0),
+        };
+        let timetable = Rc::new(Timetable::from_stop_times(
+            &network,
+            vec![vec![stop(1110101, 9, 0), stop(1110102, 11, 0)]],
+        ));
+        let scheduled_trains = |manager: &EffectManager| {
+            manager
+                .effects
+                .iter()
+                .filter(|effect| effect.trip().is_some())
+                .count()
+        };
+
+        let mut manager = EffectManager::new(network, 0);
+        let mut clock = SimClock::new(SimTime::from_hms(10, 0, 0));
+        manager.set_timetable(timetable, clock.time());
+        manager.update(&clock);
+        assert_eq!(scheduled_trains(&manager), 1);
+
+        clock.set_time(SimTime::from_hms(9, 30, 0));
+        manager.update(&clock);
+        assert_eq!(scheduled_trains(&manager), 1);
+
+        // Back to before the trip left
+        clock.set_time(SimTime::from_hms(8, 30, 0));
+        manager.update(&clock);
+        assert_eq!(scheduled_trains(&manager), 0);
+    }
+
+    #[test]
+    fn trains_are_brighter_when_the_railways_are_busier() {
+        let rush_hour = train_color(SimTime::from_hms(8, 0, 0));
+        let midday = train_color(SimTime::from_hms(12, 0, 0));
+        let night = train_color(SimTime::from_hms(26, 30, 0));
+        assert_eq!(rush_hour, TRAIN_COLORS.last().unwrap());
+        assert_eq!(night, &TRAIN_COLORS[0]);
+        assert!(night[2] < midday[2] && midday[2] < rush_hour[2]);
+
+        // A train already on its way changes color as the time of day does
+        let network = empty_network();
+        let (sender, _) = unbounded();
+        let station_ids = [StationId(1), StationId(2)];
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut train = Train::along(
+            network,
+            sender,
+            &station_ids,
+            SimTime::from_hms(26, 30, 0),
+            &mut rng,
+        );
+        assert_eq!(train.color, night);
+        train.update(&SimClock::new(SimTime::from_hms(8, 0, 0)));
+        assert_eq!(train.color, rush_hour);
+    }
+
+    #[test]
+    fn spawned_trains_are_capped() {
+        let mut manager = EffectManager::new(empty_network(), 0);
+        let station_ids = [StationId(1), StationId(2)];
+        for _ in 0..MAX_EFFECTS {
+            assert!(manager.spawn_train(&station_ids));
         }
+        assert!(!manager.spawn_train(&station_ids));
+        assert_eq!(manager.effects.len(), MAX_EFFECTS);
+    }
+
+    #[test]
+    fn train_carries_progress_over_into_the_next_sections() {
+        // Station 3 is a transfer at station 2, which has no length
+        let stations = "station_cd,station_name,lon,lat
+1,一,139.0,35.0
+2,二,139.0,35.01
+3,二,139.0,35.01
+4,四,139.0,35.02
+";
+        let stations = crate::data::load_stations_from_reader(
+            stations.as_bytes(),
+            "stations.csv",
+            crate::data::Strictness::Strict,
+        )
+        .unwrap()
+        .data;
+        let network = Rc::new(Network::new(
+            stations,
+            HashMap::with_hasher(RandomState::new()),
+            IndexMap::with_hasher(RandomState::new()),
+            IndexMap::with_hasher(RandomState::new()),
+        ));
+
+        let (sender, receiver) = unbounded();
+        let station_ids: Vec<StationId> = (1..=4).map(StationId).collect();
+        let mut train = Train::along(
+            network,
+            sender,
+            &station_ids,
+            SimTime::from_hms(12, 0, 0),
+            &mut StdRng::seed_from_u64(0),
+        );
+        let first_length = train.track_sections[0].length;
+        assert_eq!(train.track_sections[1].length, Meters(0.0));
+
+        // Go one and a half times the first section's length in one frame
+        let clock = SimClock::new(SimTime::from_hms(12, 0, 0));
+        train.speed = (first_length.0 * 1.5) * 3.6 / clock.seconds_per_frame();
+        train.update(&clock);
+
+        assert_eq!(train.current_section_index, 2);
+        assert!((train.current_line_progress.0 - first_length.0 * 0.5).abs() < 0.1);
+        assert_eq!(
+            receiver.try_iter().collect::<Vec<_>>(),
+            vec![StationId(2), StationId(3)]
+        );
     }
 }
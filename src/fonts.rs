@@ -1,26 +1,172 @@
-use crate::data::StationId;
+use crate::data::StationGroupId;
+use crate::glyph_cache::{quantize_height, quantized_height, Coverage, GlyphCache};
+use crate::labels::{Label, LabelPlacer};
 use crate::map::MapFrame;
-use crate::tile::Tile;
-use rusttype::{point, Font, Scale};
+use crate::tile::{Tile, TileCounts};
+use rusttype::{point, Font, GlyphId, PositionedGlyph, Scale};
+use std::{borrow::Cow, error::Error, fmt::Display, fs, io, path::Path};
+
+/// The font text falls back to when no other font has a glyph for a character, built into the
+/// binary
+const BUILTIN_FONT: &[u8] = include_bytes!("../data/Kosugi-Regular.ttf");
+const BUILTIN_FONT_NAME: &str = "Kosugi-Regular.ttf";
+
+/// A font that couldn't be loaded
+#[derive(Debug)]
+pub enum FontError {
+    /// The file couldn't be read
+    Io { file: String, source: io::Error },
+
+    /// The file isn't a font that can be drawn with
+    Invalid { file: String },
+}
+
+impl Display for FontError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FontError::Io { file, source } => write!(f, "{}: {}", file, source),
+            FontError::Invalid { file } => {
+                write!(f, "{}: not a TrueType or OpenType font", file)
+            }
+        }
+    }
+}
+
+impl Error for FontError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FontError::Io { source, .. } => Some(source),
+            FontError::Invalid { .. } => None,
+        }
+    }
+}
+
+/// Load a TrueType or OpenType font from a file
+pub fn load_font_from_path<P: AsRef<Path>>(path: P) -> Result<Font<'static>, FontError> {
+    let file = path.as_ref().display().to_string();
+    let data = fs::read(&path).map_err(|source| FontError::Io {
+        file: file.clone(),
+        source,
+    })?;
+    Font::try_from_vec(data).ok_or(FontError::Invalid { file })
+}
+
+/// Fonts to draw text with, in order of preference. Each character is drawn with the first font
+/// that has a glyph for it, so that a font without kanji can still be used for everything else.
+struct FontStack {
+    fonts: Vec<Font<'static>>,
+}
+
+impl FontStack {
+    /// Lay out a line of text at `scale`, starting at `x` = 0 with its baseline at `baseline`
+    fn layout(&self, text: &str, scale: Scale, baseline: f32) -> Vec<PositionedGlyph<'_>> {
+        let mut glyphs = Vec::new();
+        let mut x = 0.0;
+
+        // The font and glyph of the last character, for kerning between two glyphs of one font
+        let mut last: Option<(usize, GlyphId)> = None;
+
+        for c in text.chars() {
+            // Characters that no font has are drawn with the first font's placeholder glyph
+            let index = self
+                .fonts
+                .iter()
+                .position(|font| font.glyph(c).id() != GlyphId(0))
+                .unwrap_or(0);
+            let font = &self.fonts[index];
+            let glyph = font.glyph(c).scaled(scale);
+
+            if let Some((last_index, last_id)) = last {
+                if last_index == index {
+                    x += font.pair_kerning(scale, last_id, glyph.id());
+                }
+            }
+            last = Some((index, glyph.id()));
+
+            let advance_width = glyph.h_metrics().advance_width;
+            glyphs.push(glyph.positioned(point(x, baseline)));
+            x += advance_width;
+        }
+
+        glyphs
+    }
+
+    /// How far the baseline is below the top of a line of text at `scale`. Every font is drawn on
+    /// the first font's baseline.
+    fn ascent(&self, scale: Scale) -> f32 {
+        self.fonts[0].v_metrics(scale).ascent
+    }
+}
+
+/// How wide laid out text is, from the start of its first glyph to the end of its last one
+fn layout_width(glyphs: &[PositionedGlyph]) -> f32 {
+    glyphs.last().map_or(0.0, |glyph| {
+        glyph.position().x + glyph.unpositioned().h_metrics().advance_width
+    })
+}
+
+/// A station that could have its name drawn next to it
+#[derive(Clone, Debug)]
+pub struct StationLabel<'a> {
+    /// The station's center tile
+    pub tile: Tile,
+    pub name: Cow<'a, str>,
+    pub group_id: StationGroupId,
+
+    /// Names of stations with a higher priority are placed first
+    pub priority: usize,
+}
 
 pub struct FontManager {
-    font: Font<'static>,
+    fonts: FontStack,
+
+    /// Station names rasterized at the sizes they were drawn at lately, centered on their
+    /// station's tile. Panning leaves the size the same, so only the names of stations coming
+    /// into view have to be rasterized.
+    glyph_cache: GlyphCache,
+
+    label_placer: LabelPlacer,
 }
 
 impl FontManager {
-    pub fn new() -> Self {
-        let font_data = include_bytes!("../data/Kosugi-Regular.ttf");
-        let font = Font::try_from_bytes(font_data as &[u8]).unwrap();
-        Self { font }
+    /// Draw text with the built-in font
+    pub fn new() -> Result<Self, FontError> {
+        Self::with_fonts::<&Path>(&[])
     }
 
-    /// Get the tiles that should be colored in with fonts in the given MapFrame
+    /// Draw text with the fonts in the files at `paths`, in order of preference, falling back to
+    /// the built-in font for characters that none of them have
+    pub fn with_fonts<P: AsRef<Path>>(paths: &[P]) -> Result<Self, FontError> {
+        let mut fonts = paths
+            .iter()
+            .map(load_font_from_path)
+            .collect::<Result<Vec<_>, _>>()?;
+        fonts.push(
+            Font::try_from_bytes(BUILTIN_FONT).ok_or_else(|| FontError::Invalid {
+                file: BUILTIN_FONT_NAME.to_owned(),
+            })?,
+        );
+
+        Ok(Self {
+            fonts: FontStack { fonts },
+            glyph_cache: GlyphCache::default(),
+            label_placer: LabelPlacer::default(),
+        })
+    }
+
+    /// Get the tiles that should be colored in with fonts in the given MapFrame, along with the
+    /// index of the font color and how much of the tile the name covers, from 0.0 to 1.0. Each
+    /// name goes on whichever side of its station it fits on without covering another name, and
+    /// preferably without covering the stations at `station_tiles` or the tracks counted in
+    /// `track_tiles`. Names that don't fit anywhere are left out.
     pub fn get_font_tiles(
-        &self,
+        &mut self,
         map_frame: &MapFrame,
-        tiles_with_station: Vec<(&Tile, &String, &StationId)>,
-    ) -> Vec<(Tile, usize)> {
-        let mut result: Vec<(Tile, usize)> = Vec::new();
+        stations: &[StationLabel],
+        station_tiles: &[Tile],
+        track_tiles: &TileCounts,
+    ) -> Vec<(Tile, usize, f32)> {
+        let mut result: Vec<(Tile, usize, f32)> = Vec::new();
 
         // Height should scale based on map frame.
         const MAX_FONT_HEIGHT: f32 = 35.0;
@@ -29,56 +175,199 @@ impl FontManager {
         const START_FRAME_HEIGHT: f32 = 0.5;
         const END_FRAME_HEIGHT: f32 = 0.01;
 
-        if map_frame.height().0 > START_FRAME_HEIGHT {
+        if map_frame.reference_height().0 > START_FRAME_HEIGHT {
             return vec![];
         }
 
         // How much they're zoomed in past the minimum frame height, from 0.0 to 1.0
-        let zoom_factor: f32 =
-            (map_frame.height().0 - START_FRAME_HEIGHT) / (END_FRAME_HEIGHT - START_FRAME_HEIGHT);
+        let zoom_factor: f32 = (map_frame.reference_height().0 - START_FRAME_HEIGHT)
+            / (END_FRAME_HEIGHT - START_FRAME_HEIGHT);
 
-        let height: f32 = MAX_FONT_HEIGHT * zoom_factor;
-
-        let scale = Scale {
-            x: height,
-            y: height,
-        };
+        let height = quantize_height(MAX_FONT_HEIGHT * zoom_factor);
+        self.glyph_cache.next_frame();
 
-        let v_metrics = self.font.v_metrics(scale);
-        let offset = point(0.0, v_metrics.ascent);
-
-        for (tile, name, station_id) in tiles_with_station {
-            let glyphs: Vec<_> = self.font.layout(name, scale, offset).collect();
-            let width = scale.x;
-            for (i, g) in glyphs.iter().enumerate() {
-                if g.pixel_bounding_box().is_some() {
-                    g.draw(|x, y, v| {
-                        let x = x as i32;
-                        let y = y as i32;
-
-                        // (x, y) is the position to draw the glyph relative to its own bounding
-                        // box. We want to draw the name centered around the station itself. So the
-                        // x and y midpoint should be at `tile`.
-
-                        let font_start_x =
-                            tile.x.0 - ((glyphs.len() as f32 / 2.0) * width as f32) as i32;
-                        let font_start_y = tile.y.0 - (height / 2.0) as i32;
-                        let x_adjusted = x + font_start_x + ((width as i32) * i as i32);
-                        let y_adjusted = y + font_start_y;
-
-                        if v > 0.1 {
-                            let tile = Tile {
-                                x: x_adjusted.into(),
-                                y: y_adjusted.into(),
-                            };
-                            let font_index = station_id.0.rem_euclid(3) as usize;
-                            result.push((tile, font_index));
-                        }
-                    })
-                }
+        // The rasterized names of the labels, in the same order
+        let mut labels = Vec::new();
+        let mut coverages = Vec::new();
+        for station in stations {
+            let fonts = &self.fonts;
+            let coverage = self.glyph_cache.get(&station.name, height, || {
+                label_coverage(fonts, &station.name, quantized_height(height))
+            });
+            if let Some(bounds) = coverage.bounds {
+                labels.push(Label {
+                    tile: station.tile,
+                    group_id: station.group_id,
+                    priority: station.priority,
+                    bounds,
+                });
+                coverages.push(coverage);
             }
         }
 
+        let placements = self.label_placer.place(
+            &labels,
+            station_tiles,
+            map_frame.station_width(),
+            track_tiles,
+        );
+        for (i, (offset_x, offset_y)) in placements {
+            let label = &labels[i];
+            let font_index = label.group_id.0.rem_euclid(3) as usize;
+            result.extend(coverages[i].pixels().map(|(x, y, v)| {
+                let tile = Tile {
+                    x: (label.tile.x.0 + offset_x + x).into(),
+                    y: (label.tile.y.0 + offset_y + y).into(),
+                };
+                (tile, font_index, v)
+            }));
+        }
+
         result
     }
+
+    /// Forget every station name that's been rasterized, and keep the names that are rasterized
+    /// from now on until they take up more than `max_bytes`. With 0, names are rasterized again
+    /// every frame.
+    pub fn set_glyph_cache_size(&mut self, max_bytes: usize) {
+        self.glyph_cache = GlyphCache::new(max_bytes);
+    }
+
+    /// The station names that have been rasterized, for seeing how much the cache holds
+    pub fn glyph_cache(&self) -> &GlyphCache {
+        &self.glyph_cache
+    }
+
+    /// Lay out a line of text `height` pixels tall. Returns the pixels to fill in, relative to the
+    /// top left of the text, and how wide the text is.
+    pub fn text_pixels(&self, text: &str, height: f32) -> (Vec<(i32, i32)>, i32) {
+        let scale = Scale::uniform(height);
+        let mut pixels = Vec::new();
+
+        for glyph in self.fonts.layout(text, scale, self.fonts.ascent(scale)) {
+            if let Some(bounding_box) = glyph.pixel_bounding_box() {
+                glyph.draw(|x, y, v| {
+                    if v > 0.3 {
+                        pixels.push((x as i32 + bounding_box.min.x, y as i32 + bounding_box.min.y));
+                    }
+                });
+            }
+        }
+
+        (pixels, self.text_width(text, height))
+    }
+
+    /// How wide a line of text `height` pixels tall is
+    pub fn text_width(&self, text: &str, height: f32) -> i32 {
+        let glyphs = self.fonts.layout(text, Scale::uniform(height), 0.0);
+        layout_width(&glyphs).ceil() as i32
+    }
+}
+
+/// How much a station name `height` pixels tall covers the tiles around its station, when it's
+/// centered on the station's tile
+fn label_coverage(fonts: &FontStack, name: &str, height: f32) -> Coverage {
+    let mut pixels = Vec::new();
+    let scale = Scale::uniform(height);
+
+    // The name is laid out with the top of its line at 0, and moved left by half its width and up
+    // by half its height, so that its middle is at the station's tile
+    let glyphs = fonts.layout(name, scale, fonts.ascent(scale));
+    let width = layout_width(&glyphs);
+    let start_x = -(width / 2.0) as i32;
+    let start_y = -(height / 2.0) as i32;
+
+    for glyph in &glyphs {
+        if let Some(bounding_box) = glyph.pixel_bounding_box() {
+            glyph.draw(|x, y, v| {
+                // (x, y) is relative to the glyph's own bounding box
+                pixels.push((
+                    start_x + bounding_box.min.x + x as i32,
+                    start_y + bounding_box.min.y + y as i32,
+                    v,
+                ));
+            })
+        }
+    }
+
+    Coverage::new(&pixels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A TrueType font with only the tables a font can't do without, and no glyph for any
+    /// character
+    fn font_without_characters() -> Font<'static> {
+        let mut head = vec![0; 54];
+        head[0..4].copy_from_slice(&0x0001_0000u32.to_be_bytes());
+        head[18..20].copy_from_slice(&1000u16.to_be_bytes()); // units per em
+
+        let mut hhea = vec![0; 36];
+        hhea[0..4].copy_from_slice(&0x0001_0000u32.to_be_bytes());
+        hhea[4..6].copy_from_slice(&800i16.to_be_bytes()); // ascender
+        hhea[6..8].copy_from_slice(&(-200i16).to_be_bytes()); // descender
+        hhea[34..36].copy_from_slice(&1u16.to_be_bytes()); // horizontal metrics
+
+        let hmtx = [500u16.to_be_bytes(), 0u16.to_be_bytes()].concat();
+        let maxp = [
+            0x0000_5000u32.to_be_bytes().to_vec(),
+            1u16.to_be_bytes().to_vec(),
+        ]
+        .concat();
+
+        let tables: [(&[u8; 4], Vec<u8>); 4] = [
+            (b"head", head),
+            (b"hhea", hhea),
+            (b"hmtx", hmtx),
+            (b"maxp", maxp),
+        ];
+
+        // The version and the number of tables, then a record of where each table is, and then
+        // the tables themselves, each starting on a multiple of 4 bytes
+        let mut data = [0x0001_0000u32.to_be_bytes(), [0, 4, 0, 0], [0; 4]].concat();
+        let mut contents = Vec::new();
+        let start = data.len() + tables.len() * 16;
+        for (tag, table) in &tables {
+            data.extend_from_slice(*tag);
+            data.extend_from_slice(&[0; 4]); // checksum
+            data.extend_from_slice(&((start + contents.len()) as u32).to_be_bytes());
+            data.extend_from_slice(&(table.len() as u32).to_be_bytes());
+            contents.extend_from_slice(table);
+            contents.resize((contents.len() + 3) / 4 * 4, 0);
+        }
+        data.extend(contents);
+
+        Font::try_from_vec(data).unwrap()
+    }
+
+    #[test]
+    fn characters_fall_back_to_the_next_font() {
+        let builtin = Font::try_from_bytes(BUILTIN_FONT).unwrap();
+        let fonts = FontStack {
+            fonts: vec![font_without_characters(), builtin.clone()],
+        };
+
+        let text = "東京 Tokyo";
+        let glyphs = fonts.layout(text, Scale::uniform(20.0), 0.0);
+        assert_eq!(glyphs.len(), text.chars().count());
+        for (glyph, c) in glyphs.iter().zip(text.chars()) {
+            assert_ne!(glyph.id(), GlyphId(0));
+            assert_eq!(glyph.id(), builtin.glyph(c).id());
+        }
+        assert!(layout_width(&glyphs) > 0.0);
+    }
+
+    #[test]
+    fn fonts_that_cant_be_loaded_are_errors() {
+        let missing = FontManager::with_fonts(&["no/such/font.ttf"]);
+        assert!(matches!(missing, Err(FontError::Io { .. })));
+
+        let not_a_font = concat!(env!("CARGO_MANIFEST_DIR"), "/data/stations.csv");
+        match FontManager::with_fonts(&[not_a_font]) {
+            Err(FontError::Invalid { file }) => assert_eq!(file, not_a_font),
+            _ => panic!("a CSV file loaded as a font"),
+        }
+    }
 }
@@ -0,0 +1,498 @@
+//! The actual paths that tracks take between stations, for lines that don't run in a straight
+//! line. Paths come from the shapes in a GTFS feed or from a GeoJSON file; connections without
+//! one are drawn straight.
+
+use crate::{
+    data::{open, DataError, Loaded, Network, StationId, Strictness},
+    gtfs::{ShapeMap, ShapedTrip},
+    map::{Degree, MapCoord, Meters},
+};
+use ahash::RandomState;
+use serde_json::Value;
+use std::{collections::HashMap, convert::TryFrom, io::Read, path::Path};
+
+/// How far a station can be from a GTFS shape and still be placed on it. Further away than this,
+/// the shape probably doesn't really pass through the station.
+const MAX_SHAPE_DISTANCE: Meters = Meters(1000.0);
+
+/// GeoJSON feature properties holding the two stations a track runs between, named after the
+/// columns of join.csv
+const STATION_PROPERTIES: [&str; 2] = ["station_cd1", "station_cd2"];
+
+/// The path of the track between each pair of connected stations that has one. Every path starts
+/// exactly at the first station's drawn position and ends exactly at the second's, so tracks
+/// still meet at the stations.
+#[derive(Debug, Default, Clone)]
+pub struct TrackGeometry {
+    /// Each path is stored in both directions, so that trains going either way can follow it
+    /// without reversing it first
+    paths: HashMap<(StationId, StationId), Vec<MapCoord>, RandomState>,
+}
+
+impl TrackGeometry {
+    /// Set the path from one station to the other (and back)
+    pub fn insert(&mut self, from: StationId, to: StationId, path: Vec<MapCoord>) {
+        let mut reversed = path.clone();
+        reversed.reverse();
+        self.paths.insert((from, to), path);
+        self.paths.insert((to, from), reversed);
+    }
+
+    /// The path from one station to the other, if there is one
+    pub fn get(&self, from: StationId, to: StationId) -> Option<&[MapCoord]> {
+        self.paths.get(&(from, to)).map(Vec::as_slice)
+    }
+
+    pub fn contains(&self, from: StationId, to: StationId) -> bool {
+        self.paths.contains_key(&(from, to))
+    }
+
+    /// How many connections have a path
+    pub fn len(&self) -> usize {
+        self.paths.len() / 2
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    /// Add all the paths from `other`, replacing any that are already here
+    pub fn extend(&mut self, other: TrackGeometry) {
+        self.paths.extend(other.paths);
+    }
+
+    /// Cut GTFS shapes into paths between consecutive stops. Each station is placed at the point
+    /// of the shape closest to it, moving forward along the shape with every stop; stations that
+    /// aren't near the shape get a straight track instead.
+    pub fn from_shapes(network: &Network, shapes: &ShapeMap, trips: &[ShapedTrip]) -> Self {
+        let mut geometry = Self::default();
+
+        for trip in trips {
+            let shape = match shapes.get(&trip.shape_id) {
+                Some(shape) if shape.len() >= 2 => shape,
+                _ => continue,
+            };
+
+            let mut first_segment = 0;
+            let mut previous: Option<(StationId, MapCoord, usize)> = None;
+            for station_id in &trip.stations {
+                let coord = match network.draw_coord(*station_id) {
+                    Some(coord) => coord,
+                    None => continue,
+                };
+
+                let (segment, point) = project(shape, coord, first_segment);
+                if point.meters_to(&coord) > MAX_SHAPE_DISTANCE {
+                    previous = None;
+                    continue;
+                }
+
+                if let Some((previous_id, previous_coord, previous_segment)) = previous {
+                    if previous_id != *station_id && !geometry.contains(previous_id, *station_id) {
+                        // The shape's own points between where the two stations were placed
+                        let mut path = vec![previous_coord];
+                        path.extend_from_slice(&shape[previous_segment + 1..=segment]);
+                        path.push(coord);
+                        // A station right on one of the shape's points would be in it twice
+                        path.dedup_by(|a, b| a.long == b.long && a.lat == b.lat);
+                        geometry.insert(previous_id, *station_id, path);
+                    }
+                }
+
+                first_segment = segment;
+                previous = Some((*station_id, coord, segment));
+            }
+        }
+
+        geometry
+    }
+}
+
+/// The total length of a path
+pub fn path_length(path: &[MapCoord]) -> Meters {
+    path.windows(2)
+        .map(|pair| pair[0].meters_to(&pair[1]))
+        .fold(Meters(0.0), |total, length| total + length)
+}
+
+/// Where along a path `fraction` of its length is: the index of the line segment starting at
+/// `path[index]`, and how far along that segment it is. A path with no length is at its start.
+pub fn point_along(path: &[MapCoord], fraction: f32) -> (usize, f32) {
+    let segments = path.len().saturating_sub(1);
+    let target = path_length(path).0 * fraction;
+
+    let mut travelled = 0.0;
+    for (index, pair) in path.windows(2).enumerate() {
+        let length = pair[0].meters_to(&pair[1]).0;
+        if travelled + length >= target && length > 0.0 {
+            return (index, ((target - travelled) / length).clamp(0.0, 1.0));
+        }
+        travelled += length;
+    }
+
+    if travelled > 0.0 {
+        (segments.saturating_sub(1), 1.0)
+    } else {
+        (0, 0.0)
+    }
+}
+
+/// The point on `path` closest to `coord`, only looking at the segments from `first_segment`
+/// onwards: the segment it's on, and the point itself. Distances are measured with longitude
+/// scaled to the length of a degree at `coord`'s latitude, since away from the equator a degree
+/// of longitude is shorter on the ground than a degree of latitude.
+fn project(path: &[MapCoord], coord: MapCoord, first_segment: usize) -> (usize, MapCoord) {
+    let long_scale = coord.lat.0.to_radians().cos();
+    let mut closest = (first_segment, path[first_segment], f32::MAX);
+
+    for (index, pair) in path.windows(2).enumerate().skip(first_segment) {
+        let dx = (pair[1].long - pair[0].long).0;
+        let dy = (pair[1].lat - pair[0].lat).0;
+        let (scaled_dx, cx, cy) = (
+            dx * long_scale,
+            (coord.long - pair[0].long).0 * long_scale,
+            (coord.lat - pair[0].lat).0,
+        );
+        let length_squared = scaled_dx * scaled_dx + dy * dy;
+        let t = if length_squared > 0.0 {
+            ((cx * scaled_dx + cy * dy) / length_squared).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+
+        let point = MapCoord {
+            long: Degree(pair[0].long.0 + t * dx),
+            lat: Degree(pair[0].lat.0 + t * dy),
+        };
+        let (off_x, off_y) = (cx - t * scaled_dx, cy - t * dy);
+        let distance_squared = off_x * off_x + off_y * off_y;
+        if distance_squared < closest.2 {
+            closest = (index, point, distance_squared);
+        }
+    }
+
+    (closest.0, closest.1)
+}
+
+/// Load track paths from a GeoJSON file
+pub fn load_geojson_from_path<P: AsRef<Path>>(
+    path: P,
+    network: &Network,
+    strictness: Strictness,
+) -> Result<Loaded<TrackGeometry>, DataError> {
+    let path = path.as_ref();
+    let file = open(path)?;
+    load_geojson_from_reader(file, &path.display().to_string(), network, strictness)
+}
+
+/// Load track paths from GeoJSON: a FeatureCollection of LineStrings, each with `station_cd1` and
+/// `station_cd2` properties naming the connected stations that it runs between. `name` is only
+/// used in error messages.
+pub fn load_geojson_from_reader<R: Read>(
+    source: R,
+    name: &str,
+    network: &Network,
+    strictness: Strictness,
+) -> Result<Loaded<TrackGeometry>, DataError> {
+    let json: Value = serde_json::from_reader(source).map_err(|source| DataError::Json {
+        file: name.to_owned(),
+        source,
+    })?;
+
+    let features = json
+        .get("features")
+        .and_then(Value::as_array)
+        .ok_or_else(|| DataError::InvalidFeature {
+            file: name.to_owned(),
+            feature: None,
+            reason: "not a GeoJSON FeatureCollection".to_owned(),
+        })?;
+
+    let mut result = Loaded {
+        data: TrackGeometry::default(),
+        warnings: Vec::new(),
+    };
+
+    for (index, feature) in features.iter().enumerate() {
+        let invalid = |reason: String| DataError::InvalidFeature {
+            file: name.to_owned(),
+            feature: Some(index),
+            reason,
+        };
+
+        match load_feature(feature, network) {
+            Ok((from, to, path)) => result.data.insert(from, to, path),
+            Err(reason) => result.reject(strictness, invalid(reason))?,
+        }
+    }
+
+    Ok(result)
+}
+
+/// The stations a GeoJSON feature runs between, and its path, with the ends moved onto the
+/// stations. The error is the reason it couldn't be used.
+fn load_feature(
+    feature: &Value,
+    network: &Network,
+) -> Result<(StationId, StationId, Vec<MapCoord>), String> {
+    let mut station_ids = [StationId(0); 2];
+    for (station_id, property) in station_ids.iter_mut().zip(STATION_PROPERTIES.iter()) {
+        let value = feature
+            .get("properties")
+            .and_then(|p| p.get(property))
+            .ok_or_else(|| format!("no `{}` property", property))?;
+
+        // Accept IDs written either as numbers or as strings
+        let id = match value {
+            Value::Number(n) => n.as_u64(),
+            Value::String(s) => s.parse().ok(),
+            _ => None,
+        };
+        *station_id = id
+            .and_then(|id| u32::try_from(id).ok())
+            .map(StationId)
+            .ok_or_else(|| format!("invalid `{}` value {}", property, value))?;
+    }
+
+    let [from, to] = station_ids;
+    let (from_coord, to_coord) = match (network.draw_coord(from), network.draw_coord(to)) {
+        (Some(from_coord), Some(to_coord)) => (from_coord, to_coord),
+        (None, _) => return Err(format!("unknown station {}", from.0)),
+        (_, None) => return Err(format!("unknown station {}", to.0)),
+    };
+    if !network
+        .connections
+        .get(&from)
+        .is_some_and(|c| c.contains(&to))
+    {
+        return Err(format!("stations {} and {} aren't connected", from.0, to.0));
+    }
+
+    let geometry = feature
+        .get("geometry")
+        .ok_or_else(|| "no geometry".to_owned())?;
+    if geometry.get("type").and_then(Value::as_str) != Some("LineString") {
+        return Err("geometry isn't a LineString".to_owned());
+    }
+
+    let mut path = geometry
+        .get("coordinates")
+        .and_then(Value::as_array)
+        .ok_or_else(|| "no coordinates".to_owned())?
+        .iter()
+        .map(|position| match position.as_array().map(Vec::as_slice) {
+            Some([long, lat, ..]) => Some(MapCoord {
+                long: Degree(long.as_f64()? as f32),
+                lat: Degree(lat.as_f64()? as f32),
+            }),
+            _ => None,
+        })
+        .collect::<Option<Vec<MapCoord>>>()
+        .ok_or_else(|| "invalid coordinates".to_owned())?;
+    if path.len() < 2 {
+        return Err("fewer than 2 coordinates".to_owned());
+    }
+
+    // The line may have been drawn in either direction
+    if path[0].meters_to(&to_coord) < path[0].meters_to(&from_coord) {
+        path.reverse();
+    }
+    let last = path.len() - 1;
+    path[0] = from_coord;
+    path[last] = to_coord;
+
+    Ok((from, to, path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{load_connections_from_reader, load_stations_from_reader};
+    use indexmap::IndexMap;
+
+    /// Three stations in a row going east, and a fourth off to the side that isn't connected
+    const STATIONS: &str = "station_cd,station_name,lon,lat
+1,一,139.0,35.0
+2,二,139.1,35.0
+3,三,139.2,35.0
+4,四,139.1,35.5
+";
+
+    const CONNECTIONS: &str = "station_cd1,station_cd2
+1,2
+2,3
+";
+
+    fn network() -> Network {
+        let stations =
+            load_stations_from_reader(STATIONS.as_bytes(), "stations.csv", Strictness::Strict)
+                .unwrap()
+                .data;
+        let connections = load_connections_from_reader(
+            CONNECTIONS.as_bytes(),
+            "join.csv",
+            &stations,
+            Strictness::Strict,
+        )
+        .unwrap()
+        .data;
+        Network::new(
+            stations,
+            connections.connections,
+            IndexMap::with_hasher(RandomState::new()),
+            IndexMap::with_hasher(RandomState::new()),
+        )
+    }
+
+    fn coord(long: f32, lat: f32) -> MapCoord {
+        MapCoord {
+            long: Degree(long),
+            lat: Degree(lat),
+        }
+    }
+
+    fn assert_close(a: MapCoord, b: MapCoord) {
+        assert!(a.distance_to(&b).0 < 1e-4, "{} isn't close to {}", a, b);
+    }
+
+    fn load(json: &str, strictness: Strictness) -> Result<Loaded<TrackGeometry>, DataError> {
+        load_geojson_from_reader(json.as_bytes(), "tracks.geojson", &network(), strictness)
+    }
+
+    fn feature(from: u32, to: u32, geometry: &str) -> String {
+        format!(
+            r#"{{"type": "Feature", "properties": {{"station_cd1": {}, "station_cd2": "{}"}},
+                "geometry": {}}}"#,
+            from, to, geometry
+        )
+    }
+
+    fn collection(features: &[String]) -> String {
+        format!(
+            r#"{{"type": "FeatureCollection", "features": [{}]}}"#,
+            features.join(",")
+        )
+    }
+
+    #[test]
+    fn shapes_are_cut_at_their_stops() {
+        let network = network();
+        // A shape that bends north between each pair of stations
+        let mut shapes = ShapeMap::with_hasher(RandomState::new());
+        shapes.insert(
+            "shape".to_owned(),
+            vec![
+                coord(139.0, 35.0),
+                coord(139.05, 35.002),
+                coord(139.1, 35.0),
+                coord(139.15, 35.002),
+                coord(139.2, 35.0),
+            ],
+        );
+        let trips = [ShapedTrip {
+            shape_id: "shape".to_owned(),
+            stations: vec![StationId(1), StationId(2), StationId(3)],
+        }];
+
+        let geometry = TrackGeometry::from_shapes(&network, &shapes, &trips);
+        assert_eq!(geometry.len(), 2);
+
+        let path = geometry.get(StationId(1), StationId(2)).unwrap();
+        assert_eq!(path.len(), 3);
+        assert_close(path[0], coord(139.0, 35.0));
+        assert_close(path[1], coord(139.05, 35.002));
+        assert_close(path[2], coord(139.1, 35.0));
+
+        let path = geometry.get(StationId(3), StationId(2)).unwrap();
+        assert_eq!(path.len(), 3);
+        assert_close(path[0], coord(139.2, 35.0));
+        assert_close(path[1], coord(139.15, 35.002));
+
+        assert!(geometry.get(StationId(1), StationId(3)).is_none());
+    }
+
+    #[test]
+    fn stops_are_placed_by_distance_on_the_ground() {
+        // 0.3 degrees north of the point is a longer way than 0.5 degrees east, at 60 degrees
+        // north, where a degree of longitude is half as long as one of latitude
+        let path = [coord(9.5, 60.3), coord(10.5, 60.3), coord(10.5, 59.5)];
+        let (segment, point) = project(&path, coord(10.0, 60.0), 0);
+        assert_eq!(segment, 1);
+        assert_close(point, coord(10.5, 60.0));
+    }
+
+    #[test]
+    fn points_along_a_path() {
+        let path = [coord(139.0, 35.0), coord(139.1, 35.0), coord(139.2, 35.0)];
+        assert_eq!(point_along(&path, 0.0), (0, 0.0));
+        assert_eq!(point_along(&path, 1.0), (1, 1.0));
+
+        let (segment, along) = point_along(&path, 0.5);
+        assert!(
+            (segment == 0 && (along - 1.0).abs() < 1e-3) || (segment == 1 && along.abs() < 1e-3)
+        );
+
+        let (segment, along) = point_along(&path, 0.25);
+        assert_eq!(segment, 0);
+        assert!((along - 0.5).abs() < 1e-3);
+
+        let nowhere = [coord(139.0, 35.0), coord(139.0, 35.0)];
+        assert_eq!(point_along(&nowhere, 0.5), (0, 0.0));
+        assert_eq!(point_along(&nowhere[..1], 0.5), (0, 0.0));
+    }
+
+    #[test]
+    fn geojson_lines_drawn_backwards_are_turned_around() {
+        let json = collection(&[feature(
+            1,
+            2,
+            r#"{"type": "LineString",
+                "coordinates": [[139.1001, 35.0], [139.05, 35.01], [139.0001, 35.0]]}"#,
+        )]);
+        let geometry = load(&json, Strictness::Strict).unwrap().data;
+
+        let path = geometry.get(StationId(1), StationId(2)).unwrap();
+        assert_eq!(path.len(), 3);
+        assert_close(path[0], coord(139.0, 35.0));
+        assert_close(path[1], coord(139.05, 35.01));
+        assert_close(path[2], coord(139.1, 35.0));
+    }
+
+    #[test]
+    fn geojson_features_between_unconnected_stations_are_rejected() {
+        let line = r#"{"type": "LineString", "coordinates": [[139.1, 35.5], [139.1, 35.0]]}"#;
+        let json = collection(&[feature(4, 2, line), feature(2, 3, line)]);
+
+        match load(&json, Strictness::Strict) {
+            Err(DataError::InvalidFeature {
+                feature: Some(0),
+                reason,
+                ..
+            }) => assert!(reason.contains("aren't connected")),
+            other => panic!("unexpected result {:?}", other.map(|l| l.data)),
+        }
+
+        let loaded = load(&json, Strictness::Lenient).unwrap();
+        assert_eq!(loaded.warnings.len(), 1);
+        assert_eq!(loaded.data.len(), 1);
+        assert!(loaded.data.contains(StationId(2), StationId(3)));
+    }
+
+    #[test]
+    fn geojson_geometry_has_to_be_a_line() {
+        let json = collection(&[feature(
+            1,
+            2,
+            r#"{"type": "Point", "coordinates": [139.0, 35.0]}"#,
+        )]);
+
+        match load(&json, Strictness::Strict) {
+            Err(DataError::InvalidFeature {
+                feature: Some(0),
+                reason,
+                ..
+            }) => assert!(reason.contains("LineString")),
+            other => panic!("unexpected result {:?}", other.map(|l| l.data)),
+        }
+    }
+}
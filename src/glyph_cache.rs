@@ -0,0 +1,286 @@
+//! Station names rasterized once and kept around for drawing again
+
+use crate::labels::TileRect;
+use ahash::RandomState;
+use std::{collections::HashMap, rc::Rc};
+
+/// Text heights are rounded to a multiple of this many pixels before rasterizing, so that zooming
+/// a little doesn't rasterize every name again. Too small a difference in size to see.
+const HEIGHT_STEP: f32 = 0.25;
+
+/// How much memory the rasterized names can take up, in bytes, before the ones that haven't been
+/// drawn for the longest are evicted
+const MAX_CACHE_BYTES: usize = 32 * 1024 * 1024;
+
+/// The height to rasterize text at instead of `height`, as a whole number of steps
+pub fn quantize_height(height: f32) -> u32 {
+    (height / HEIGHT_STEP).round().max(0.0) as u32
+}
+
+/// The height in pixels of a quantized height
+pub fn quantized_height(steps: u32) -> f32 {
+    steps as f32 * HEIGHT_STEP
+}
+
+/// How much of each pixel in a box some text covers, from 0 (not at all) to 255 (entirely)
+pub struct Coverage {
+    /// The box around every pixel the text covers at all. None if it doesn't cover any.
+    pub bounds: Option<TileRect>,
+    width: usize,
+    values: Vec<u8>,
+}
+
+impl Coverage {
+    /// Collect the coverage of the given pixels, from 0.0 to 1.0. Pixels that are listed more
+    /// than once, where glyphs overlap, keep the highest coverage.
+    pub fn new(pixels: &[(i32, i32, f32)]) -> Self {
+        let bounds = TileRect::around(
+            pixels
+                .iter()
+                .filter(|(_, _, v)| *v > 0.0)
+                .map(|(x, y, _)| (*x, *y)),
+        );
+        let (width, height) = bounds.map_or((0, 0), |b| {
+            (
+                (b.right - b.left + 1) as usize,
+                (b.bottom - b.top + 1) as usize,
+            )
+        });
+
+        let mut values = vec![0; width * height];
+        if let Some(b) = bounds {
+            for (x, y, v) in pixels {
+                if *v > 0.0 {
+                    let i = (y - b.top) as usize * width + (x - b.left) as usize;
+                    values[i] = values[i].max((v.min(1.0) * 255.0).round() as u8);
+                }
+            }
+        }
+
+        Self {
+            bounds,
+            width,
+            values,
+        }
+    }
+
+    /// Every pixel that's covered at all, with its coverage from 0.0 to 1.0
+    pub fn pixels(&self) -> impl Iterator<Item = (i32, i32, f32)> + '_ {
+        let (left, top) = self.bounds.map_or((0, 0), |b| (b.left, b.top));
+        let width = self.width.max(1);
+        self.values
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| **v > 0)
+            .map(move |(i, v)| {
+                let x = left + (i % width) as i32;
+                let y = top + (i / width) as i32;
+                (x, y, *v as f32 / 255.0)
+            })
+    }
+
+    /// Roughly how much memory this takes up, in bytes
+    fn size(&self) -> usize {
+        std::mem::size_of::<Self>() + self.values.len()
+    }
+}
+
+struct Entry {
+    coverage: Rc<Coverage>,
+
+    /// The last frame this was drawn in
+    last_used: u64,
+}
+
+/// Rasterized text, keyed by the text and its quantized height. Once it takes up too much memory,
+/// the entries that haven't been drawn for the longest are evicted.
+pub struct GlyphCache {
+    /// The heights each piece of text was rasterized at, and the result
+    entries: HashMap<String, HashMap<u32, Entry, RandomState>, RandomState>,
+    bytes: usize,
+    max_bytes: usize,
+
+    /// Counts up with every frame, to tell which entries were drawn most recently
+    frame: u64,
+}
+
+impl Default for GlyphCache {
+    fn default() -> Self {
+        Self::new(MAX_CACHE_BYTES)
+    }
+}
+
+impl GlyphCache {
+    /// A cache that starts evicting once it takes up more than `max_bytes`. With no room at all,
+    /// nothing is kept and text is rasterized every time it's drawn.
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            entries: HashMap::default(),
+            bytes: 0,
+            max_bytes,
+            frame: 0,
+        }
+    }
+
+    /// Get the coverage of `text` at a quantized height, calling `rasterize` for it if it isn't
+    /// cached yet
+    pub fn get<F: FnOnce() -> Coverage>(
+        &mut self,
+        text: &str,
+        height: u32,
+        rasterize: F,
+    ) -> Rc<Coverage> {
+        if self.max_bytes == 0 {
+            return Rc::new(rasterize());
+        }
+
+        let frame = self.frame;
+        if let Some(entry) = self
+            .entries
+            .get_mut(text)
+            .and_then(|heights| heights.get_mut(&height))
+        {
+            entry.last_used = frame;
+            return entry.coverage.clone();
+        }
+
+        let coverage = Rc::new(rasterize());
+        self.bytes += coverage.size();
+        self.entries.entry(text.to_owned()).or_default().insert(
+            height,
+            Entry {
+                coverage: coverage.clone(),
+                last_used: frame,
+            },
+        );
+        coverage
+    }
+
+    /// Start a new frame. If the cache has grown too big, the entries that haven't been used
+    /// for the longest are evicted first, until it's down to half its maximum size.
+    pub fn next_frame(&mut self) {
+        self.frame += 1;
+        if self.bytes <= self.max_bytes {
+            return;
+        }
+
+        let mut by_age: Vec<(u64, String, u32)> = self
+            .entries
+            .iter()
+            .flat_map(|(text, heights)| {
+                heights
+                    .iter()
+                    .map(move |(height, entry)| (entry.last_used, text.clone(), *height))
+            })
+            .collect();
+        by_age.sort_unstable();
+
+        for (_, text, height) in by_age {
+            if self.bytes <= self.max_bytes / 2 {
+                break;
+            }
+            if let Some(heights) = self.entries.get_mut(&text) {
+                if let Some(entry) = heights.remove(&height) {
+                    self.bytes -= entry.coverage.size();
+                }
+                if heights.is_empty() {
+                    self.entries.remove(&text);
+                }
+            }
+        }
+    }
+
+    /// Evict everything
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.bytes = 0;
+    }
+
+    /// How many pieces of text at some height are cached
+    pub fn len(&self) -> usize {
+        self.entries.values().map(|heights| heights.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Roughly how much memory the cache takes up, in bytes
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A square of text `side` pixels across
+    fn square(side: i32) -> Coverage {
+        let pixels: Vec<(i32, i32, f32)> = (0..side * side)
+            .map(|i| (i % side, i / side, 1.0))
+            .collect();
+        Coverage::new(&pixels)
+    }
+
+    /// Get `text` from the cache, and whether it had to be rasterized
+    fn get(cache: &mut GlyphCache, text: &str) -> bool {
+        let mut rasterized = false;
+        cache.get(text, 40, || {
+            rasterized = true;
+            square(10)
+        });
+        rasterized
+    }
+
+    #[test]
+    fn least_recently_used_entries_are_evicted_first() {
+        let entry_size = square(10).size();
+        let mut cache = GlyphCache::new(entry_size * 4);
+        for text in ["a", "b", "c", "d"] {
+            cache.next_frame();
+            assert!(get(&mut cache, text));
+        }
+
+        // Still within the limit
+        cache.next_frame();
+        assert_eq!(cache.len(), 4);
+
+        // "a" is used again in the frame that goes over the limit, so it's the newest
+        assert!(!get(&mut cache, "a"));
+        assert!(get(&mut cache, "e"));
+        assert_eq!(cache.bytes(), entry_size * 5);
+
+        // Evicting goes down to half the limit, starting with the oldest
+        cache.next_frame();
+        assert_eq!(cache.bytes(), entry_size * 2);
+        assert_eq!(cache.len(), 2);
+        assert!(!get(&mut cache, "a"));
+        assert!(!get(&mut cache, "e"));
+        assert!(get(&mut cache, "b"));
+    }
+
+    #[test]
+    fn clearing_forgets_everything() {
+        let mut cache = GlyphCache::default();
+        get(&mut cache, "a");
+        get(&mut cache, "b");
+        assert_eq!(cache.bytes(), square(10).size() * 2);
+
+        cache.clear();
+        assert_eq!(cache.bytes(), 0);
+        assert!(cache.is_empty());
+        assert!(get(&mut cache, "a"));
+        assert_eq!(cache.bytes(), square(10).size());
+    }
+
+    #[test]
+    fn a_cache_with_no_room_keeps_nothing() {
+        let mut cache = GlyphCache::new(0);
+        assert!(get(&mut cache, "a"));
+        assert!(get(&mut cache, "a"));
+        cache.next_frame();
+        assert!(cache.is_empty());
+        assert_eq!(cache.bytes(), 0);
+    }
+}
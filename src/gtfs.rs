@@ -0,0 +1,1599 @@
+//! Importer for GTFS static feeds (https://gtfs.org/schedule/), as an alternative to the ekidata
+//! files. Each combination of a stop and a route that serves it becomes a `Station`, the same way
+//! ekidata lists a station once for every line, and stops are grouped by their parent station.
+//! Only the trips of one day's services go in the timetable, since a feed usually has a service
+//! for weekdays, one for weekends and holidays, and so on.
+
+use crate::{
+    data::{
+        open, parse_color, Company, CompanyId, CompanyMap, ConnectionMap, CsvFile, DataError, Line,
+        LineId, LineMap, Loaded, Network, Station, StationGroupId, StationId, StationMap,
+        Strictness,
+    },
+    geometry::TrackGeometry,
+    map::{Degree, MapCoord},
+    schedule::{SimTime, StopTime, Timetable},
+};
+use ahash::RandomState;
+use indexmap::IndexMap;
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{Cursor, Read},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use zip::ZipArchive;
+
+const AGENCY_FILE: &str = "agency.txt";
+const STOPS_FILE: &str = "stops.txt";
+const ROUTES_FILE: &str = "routes.txt";
+const TRIPS_FILE: &str = "trips.txt";
+const STOP_TIMES_FILE: &str = "stop_times.txt";
+const SHAPES_FILE: &str = "shapes.txt";
+const CALENDAR_FILE: &str = "calendar.txt";
+const CALENDAR_DATES_FILE: &str = "calendar_dates.txt";
+
+/// The columns of calendar.txt saying whether a service runs on each day of the week, starting
+/// with Monday
+const WEEKDAY_COLUMNS: [&str; 7] = [
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+    "saturday",
+    "sunday",
+];
+
+/// Shape IDs mapped to the points of each shape, in order
+pub type ShapeMap = IndexMap<String, Vec<MapCoord>, RandomState>;
+
+/// The stations a trip stops at, and the shape of the route it takes between them. Trips that
+/// run the same way are only listed once.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ShapedTrip {
+    pub shape_id: String,
+    pub stations: Vec<StationId>,
+}
+
+/// A day in a feed's calendar
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ServiceDate {
+    pub year: u32,
+    pub month: u32,
+    pub day: u32,
+}
+
+impl ServiceDate {
+    /// The day of the week, from 0 for Monday to 6 for Sunday
+    pub fn weekday(self) -> usize {
+        // Sakamoto's method, which counts from Sunday
+        const MONTH_OFFSETS: [u32; 12] = [0, 3, 2, 5, 0, 3, 5, 1, 4, 6, 2, 4];
+        let year = if self.month < 3 {
+            self.year - 1
+        } else {
+            self.year
+        };
+        let from_sunday = (year + year / 4 - year / 100
+            + year / 400
+            + MONTH_OFFSETS[self.month as usize - 1]
+            + self.day)
+            % 7;
+        (from_sunday as usize + 6) % 7
+    }
+}
+
+/// Parses dates written the way GTFS does, like 20240401, or like 2024-04-01
+impl FromStr for ServiceDate {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits = match (s.len(), s.get(4..5), s.get(7..8)) {
+            (8, _, _) => s.to_owned(),
+            (10, Some("-"), Some("-")) => s.replace('-', ""),
+            _ => return Err(()),
+        };
+        if digits.len() != 8 || !digits.chars().all(|c| c.is_ascii_digit()) {
+            return Err(());
+        }
+
+        let date = ServiceDate {
+            year: digits[..4].parse().map_err(|_| ())?,
+            month: digits[4..6].parse().map_err(|_| ())?,
+            day: digits[6..].parse().map_err(|_| ())?,
+        };
+        if (1..=12).contains(&date.month) && (1..=31).contains(&date.day) {
+            Ok(date)
+        } else {
+            Err(())
+        }
+    }
+}
+
+/// Which of a feed's services trains run to
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ServiceChoice {
+    /// The service with the most trips, which is usually the weekday one
+    #[default]
+    Busiest,
+
+    /// The service with this `service_id`
+    Id(String),
+
+    /// The services running on this date, going by calendar.txt and calendar_dates.txt
+    Date(ServiceDate),
+}
+
+/// Everything loaded from a GTFS feed
+pub struct GtfsFeed {
+    /// The stops, routes and agencies as stations, lines and companies. Tracks follow the
+    /// feed's shapes where trips have one.
+    pub network: Network,
+
+    /// When each trip of the chosen services stops at each station
+    pub timetable: Timetable,
+
+    /// The IDs of the services whose trips are in the timetable
+    pub services: Vec<String>,
+
+    pub shapes: ShapeMap,
+    pub shaped_trips: Vec<ShapedTrip>,
+}
+
+/// Where the feed's files come from
+enum Source {
+    Directory(PathBuf),
+    Zip {
+        name: String,
+        archive: ZipArchive<File>,
+    },
+}
+
+impl Source {
+    fn new(path: &Path) -> Result<Self, DataError> {
+        if path.is_dir() {
+            return Ok(Source::Directory(path.to_owned()));
+        }
+
+        let name = path.display().to_string();
+        let archive = ZipArchive::new(open(path)?).map_err(|source| DataError::Archive {
+            file: name.clone(),
+            source,
+        })?;
+        Ok(Source::Zip { name, archive })
+    }
+
+    /// Where a file in the feed would be, for error messages
+    fn file_name(&self, file: &str) -> String {
+        match self {
+            Source::Directory(dir) => dir.join(file).display().to_string(),
+            Source::Zip { name, .. } => format!("{}/{}", name, file),
+        }
+    }
+
+    /// Open one of the feed's files, or None if the feed doesn't have it
+    fn open(&mut self, file: &str) -> Result<Option<Box<dyn Read>>, DataError> {
+        match self {
+            Source::Directory(dir) => {
+                let path = dir.join(file);
+                if path.exists() {
+                    Ok(Some(Box::new(open(path)?)))
+                } else {
+                    Ok(None)
+                }
+            }
+            Source::Zip { name, archive } => {
+                // Some feeds put their files in a folder inside the zip
+                let entry_name = archive
+                    .file_names()
+                    .find(|n| *n == file || n.ends_with(&format!("/{}", file)))
+                    .map(str::to_owned);
+                let entry_name = match entry_name {
+                    Some(entry_name) => entry_name,
+                    None => return Ok(None),
+                };
+
+                let archive_error = |source| DataError::Archive {
+                    file: name.clone(),
+                    source,
+                };
+                let mut entry = archive.by_name(&entry_name).map_err(archive_error)?;
+                let mut contents = Vec::new();
+                entry
+                    .read_to_end(&mut contents)
+                    .map_err(|source| DataError::Io {
+                        file: format!("{}/{}", name, entry_name),
+                        source,
+                    })?;
+                Ok(Some(Box::new(Cursor::new(contents))))
+            }
+        }
+    }
+
+    /// Open one of the feed's files, which it has to have
+    fn open_required(&mut self, file: &str) -> Result<Box<dyn Read>, DataError> {
+        let name = self.file_name(file);
+        self.open(file)?.ok_or_else(|| DataError::Io {
+            file: name,
+            source: std::io::Error::new(std::io::ErrorKind::NotFound, "not found in the feed"),
+        })
+    }
+}
+
+/// A stop from stops.txt
+struct Stop {
+    name: String,
+    coord: MapCoord,
+
+    /// The ID of the stop's parent station, or its own ID if it doesn't have one
+    group_key: String,
+}
+
+/// A trip from trips.txt
+struct TripInfo {
+    route: usize,
+
+    /// The days the trip runs on. A feed that leaves `service_id` out has a single service, with
+    /// the empty string as its ID.
+    service_id: String,
+
+    shape_id: Option<String>,
+}
+
+/// The days of the week a service runs on, and the dates it runs between, from calendar.txt
+struct Calendar {
+    weekdays: [bool; 7],
+    start: ServiceDate,
+    end: ServiceDate,
+}
+
+/// A date a service is added to or removed from, from calendar_dates.txt
+struct CalendarException {
+    service_id: String,
+    date: ServiceDate,
+
+    /// Whether the service runs on the date (exception type 1) or doesn't (exception type 2)
+    added: bool,
+}
+
+/// Load a GTFS feed from a directory or a zip file. agency.txt, shapes.txt, calendar.txt and
+/// calendar_dates.txt are optional; stops.txt, routes.txt, trips.txt and stop_times.txt have to
+/// be there. Every trip is part of the network, but only the trips of the services in `services`
+/// are put in the timetable; when none of the feed's trips are in them, the busiest service is
+/// used instead. A service chosen by an ID that no trips are on is an error in strict mode.
+pub fn load_gtfs<P: AsRef<Path>>(
+    path: P,
+    services: &ServiceChoice,
+    strictness: Strictness,
+) -> Result<Loaded<GtfsFeed>, DataError> {
+    let mut source = Source::new(path.as_ref())?;
+    let mut warnings = Vec::new();
+
+    let agencies = match source.open(AGENCY_FILE)? {
+        Some(file) => {
+            load_agencies(file, &source.file_name(AGENCY_FILE), strictness)?.take(&mut warnings)
+        }
+        None => IndexMap::with_hasher(RandomState::new()),
+    };
+
+    let file = source.open_required(STOPS_FILE)?;
+    let stops = load_stops(file, &source.file_name(STOPS_FILE), strictness)?.take(&mut warnings);
+
+    let file = source.open_required(ROUTES_FILE)?;
+    let routes = load_routes(file, &source.file_name(ROUTES_FILE), &agencies, strictness)?
+        .take(&mut warnings);
+
+    let file = source.open_required(TRIPS_FILE)?;
+    let trips =
+        load_trips(file, &source.file_name(TRIPS_FILE), &routes, strictness)?.take(&mut warnings);
+
+    let file = source.open_required(STOP_TIMES_FILE)?;
+    let stop_times = load_stop_times(
+        file,
+        &source.file_name(STOP_TIMES_FILE),
+        &stops,
+        &trips,
+        strictness,
+    )?
+    .take(&mut warnings);
+
+    let shapes = match source.open(SHAPES_FILE)? {
+        Some(file) => {
+            load_shapes(file, &source.file_name(SHAPES_FILE), strictness)?.take(&mut warnings)
+        }
+        None => IndexMap::with_hasher(RandomState::new()),
+    };
+
+    // The calendar is only needed to find the services running on a date
+    let (calendar, exceptions) = match services {
+        ServiceChoice::Date(_) => {
+            let calendar = match source.open(CALENDAR_FILE)? {
+                Some(file) => load_calendar(file, &source.file_name(CALENDAR_FILE), strictness)?
+                    .take(&mut warnings),
+                None => IndexMap::with_hasher(RandomState::new()),
+            };
+            let exceptions = match source.open(CALENDAR_DATES_FILE)? {
+                Some(file) => {
+                    load_calendar_dates(file, &source.file_name(CALENDAR_DATES_FILE), strictness)?
+                        .take(&mut warnings)
+                }
+                None => Vec::new(),
+            };
+            (calendar, exceptions)
+        }
+        _ => (IndexMap::with_hasher(RandomState::new()), Vec::new()),
+    };
+    let service_ids = choose_services(
+        services,
+        &source.file_name(TRIPS_FILE),
+        &trips,
+        &stop_times,
+        &calendar,
+        &exceptions,
+        strictness,
+    )?
+    .take(&mut warnings);
+    let chosen: HashSet<&str, RandomState> = service_ids.iter().map(String::as_str).collect();
+
+    // A station for every stop on every route that stops there, numbered in the order the
+    // routes are listed, then the order the stops are listed
+    let mut served: Vec<(usize, usize)> = stop_times
+        .iter()
+        .flat_map(|(trip, stops)| {
+            let route = trips[trip].route;
+            stops.iter().map(move |(stop, _, _)| (route, *stop))
+        })
+        .collect();
+    served.sort_unstable();
+    served.dedup();
+
+    let station_ids: HashMap<(usize, usize), StationId, RandomState> = served
+        .iter()
+        .enumerate()
+        .map(|(i, key)| (*key, StationId(i as u32 + 1)))
+        .collect();
+
+    // Each group takes the lowest station ID in it, the same as ekidata
+    let mut group_ids: HashMap<&str, StationGroupId, RandomState> =
+        HashMap::with_hasher(RandomState::new());
+    let mut stations: StationMap = IndexMap::with_hasher(RandomState::new());
+    for (route, stop_index) in &served {
+        let id = station_ids[&(*route, *stop_index)];
+        let (_, stop) = stops.get_index(*stop_index).unwrap();
+        let group_id = *group_ids
+            .entry(&stop.group_key)
+            .or_insert(StationGroupId(id.0));
+
+        stations.insert(
+            id,
+            Station {
+                id,
+                name: stop.name.clone(),
+                kana: None,
+                romaji: None,
+                coord: stop.coord,
+                line_id: routes[*route].id,
+                group_id,
+            },
+        );
+    }
+
+    // Trains run between each pair of stops that follow each other in a trip
+    let mut connections: ConnectionMap = HashMap::with_hasher(RandomState::new());
+    let mut timetable_trips: Vec<Vec<StopTime>> = Vec::new();
+    let mut shaped_trips: IndexMap<ShapedTrip, (), RandomState> =
+        IndexMap::with_hasher(RandomState::new());
+    for (trip, trip_stops) in &stop_times {
+        let route = trips[trip].route;
+        let trip_stations: Vec<StationId> = trip_stops
+            .iter()
+            .map(|(stop, _, _)| station_ids[&(route, *stop)])
+            .collect();
+
+        for pair in trip_stations.windows(2) {
+            if pair[0] != pair[1] {
+                connections.entry(pair[0]).or_default().insert(pair[1]);
+                connections.entry(pair[1]).or_default().insert(pair[0]);
+            }
+        }
+
+        if chosen.contains(trips[trip].service_id.as_str()) {
+            timetable_trips.push(
+                trip_stations
+                    .iter()
+                    .zip(trip_stops.iter())
+                    .map(|(station_id, (_, arrival, departure))| StopTime {
+                        station_id: *station_id,
+                        arrival: *arrival,
+                        departure: *departure,
+                    })
+                    .collect(),
+            );
+        }
+
+        if let Some(shape_id) = &trips[trip].shape_id {
+            if shapes.contains_key(shape_id) {
+                shaped_trips.insert(
+                    ShapedTrip {
+                        shape_id: shape_id.clone(),
+                        stations: trip_stations,
+                    },
+                    (),
+                );
+            }
+        }
+    }
+
+    let lines: LineMap = routes
+        .into_iter()
+        .map(|(_, line)| (line.id, line))
+        .collect();
+    let companies: CompanyMap = agencies.into_iter().map(|(_, c)| (c.id, c)).collect();
+    let shaped_trips: Vec<ShapedTrip> = shaped_trips.into_iter().map(|(t, _)| t).collect();
+    let mut network = Network::new(stations, connections, lines, companies);
+    network.add_geometry(TrackGeometry::from_shapes(&network, &shapes, &shaped_trips));
+    let timetable = Timetable::from_stop_times(&network, timetable_trips);
+
+    Ok(Loaded {
+        data: GtfsFeed {
+            network,
+            timetable,
+            services: service_ids,
+            shapes,
+            shaped_trips,
+        },
+        warnings,
+    })
+}
+
+/// The IDs of the services in `choice` that any of the trips with stop times are on, in the
+/// order they're first used in trips.txt. The busiest service is used if there are none, which
+/// for a service chosen by ID means it's been mistyped, so that's rejected. `name` is the name of
+/// trips.txt, for errors.
+fn choose_services(
+    choice: &ServiceChoice,
+    name: &str,
+    trips: &IndexMap<String, TripInfo, RandomState>,
+    stop_times: &IndexMap<String, Vec<(usize, SimTime, SimTime)>, RandomState>,
+    calendar: &IndexMap<String, Calendar, RandomState>,
+    exceptions: &[CalendarException],
+    strictness: Strictness,
+) -> Result<Loaded<Vec<String>>, DataError> {
+    let mut trip_counts: IndexMap<&str, usize, RandomState> =
+        IndexMap::with_hasher(RandomState::new());
+    for (id, trip) in trips {
+        if stop_times.contains_key(id) {
+            *trip_counts.entry(trip.service_id.as_str()).or_default() += 1;
+        }
+    }
+
+    let chosen: Vec<String> = match choice {
+        ServiceChoice::Busiest => Vec::new(),
+        ServiceChoice::Id(id) => vec![id.clone()],
+        ServiceChoice::Date(date) => {
+            let mut running: Vec<&str> = calendar
+                .iter()
+                .filter(|(_, c)| c.weekdays[date.weekday()] && c.start <= *date && *date <= c.end)
+                .map(|(id, _)| id.as_str())
+                .collect();
+            for exception in exceptions.iter().filter(|e| e.date == *date) {
+                if exception.added {
+                    running.push(&exception.service_id);
+                } else {
+                    running.retain(|id| *id != exception.service_id);
+                }
+            }
+            running.into_iter().map(str::to_owned).collect()
+        }
+    };
+
+    let used: Vec<String> = trip_counts
+        .keys()
+        .filter(|id| chosen.iter().any(|c| c == *id))
+        .map(|id| (*id).to_owned())
+        .collect();
+    let mut result = Loaded {
+        data: used,
+        warnings: Vec::new(),
+    };
+    if !result.data.is_empty() {
+        return Ok(result);
+    }
+    if let ServiceChoice::Id(id) = choice {
+        let error = DataError::UnknownService {
+            file: name.to_owned(),
+            service_id: id.clone(),
+        };
+        result.reject(strictness, error)?;
+    }
+
+    // The first of the services with the most trips
+    let busiest =
+        trip_counts.iter().fold(
+            None,
+            |busiest: Option<(&str, usize)>, (id, count)| match busiest {
+                Some((_, most)) if most >= *count => busiest,
+                _ => Some((id, *count)),
+            },
+        );
+    result.data = busiest.map(|(id, _)| id.to_owned()).into_iter().collect();
+    Ok(result)
+}
+
+/// Agency IDs mapped to companies. A feed with a single agency can leave out `agency_id`, in
+/// which case it's stored under the empty string.
+fn load_agencies<R: Read>(
+    source: R,
+    name: &str,
+    strictness: Strictness,
+) -> Result<Loaded<IndexMap<String, Company, RandomState>>, DataError> {
+    let mut csv = CsvFile::new(source, name)?;
+    let id_column = csv.optional_column("agency_id");
+    let name_column = csv.column("agency_name")?;
+
+    let mut result = Loaded {
+        data: IndexMap::with_hasher(RandomState::new()),
+        warnings: Vec::new(),
+    };
+
+    for row in csv.rows() {
+        let agency = row.and_then(|row| {
+            let id = row.get_optional(id_column).unwrap_or_default().to_owned();
+            if result.data.contains_key(&id) {
+                return Err(DataError::DuplicateId {
+                    file: row.file.clone(),
+                    line: row.line,
+                    column: "agency_id",
+                    id,
+                });
+            }
+            Ok((id, row.get(name_column)?.to_owned()))
+        });
+
+        match agency {
+            Ok((id, name)) => {
+                let company_id = CompanyId(result.data.len() as u32 + 1);
+                result.data.insert(
+                    id,
+                    Company {
+                        id: company_id,
+                        name,
+                    },
+                );
+            }
+            Err(e) => result.reject(strictness, e)?,
+        }
+    }
+
+    Ok(result)
+}
+
+/// Stop IDs mapped to stops. Only actual stops and platforms (`location_type` 0) are kept;
+/// parent stations are only used to group their stops. A stop ID used again is rejected, and the
+/// first stop with it is kept.
+fn load_stops<R: Read>(
+    source: R,
+    name: &str,
+    strictness: Strictness,
+) -> Result<Loaded<IndexMap<String, Stop, RandomState>>, DataError> {
+    let mut csv = CsvFile::new(source, name)?;
+    let id_column = csv.column("stop_id")?;
+    let name_column = csv.column("stop_name")?;
+    let lat_column = csv.column("stop_lat")?;
+    let lon_column = csv.column("stop_lon")?;
+    let type_column = csv.optional_column("location_type");
+    let parent_column = csv.optional_column("parent_station");
+
+    let mut result = Loaded {
+        data: IndexMap::with_hasher(RandomState::new()),
+        warnings: Vec::new(),
+    };
+
+    for row in csv.rows() {
+        let stop = row.and_then(|row| {
+            let location_type = row.get_optional(type_column).unwrap_or("0");
+            if location_type != "0" {
+                return Ok(None);
+            }
+
+            let id = row.get(id_column)?.to_owned();
+            if result.data.contains_key(&id) {
+                return Err(DataError::DuplicateId {
+                    file: row.file.clone(),
+                    line: row.line,
+                    column: "stop_id",
+                    id,
+                });
+            }
+            let group_key = row.get_optional(parent_column).unwrap_or(&id).to_owned();
+            Ok(Some((
+                id,
+                Stop {
+                    name: row.get(name_column)?.to_owned(),
+                    coord: MapCoord {
+                        long: Degree(row.parse(lon_column)?),
+                        lat: Degree(row.parse(lat_column)?),
+                    },
+                    group_key,
+                },
+            )))
+        });
+
+        match stop {
+            Ok(Some((id, stop))) => {
+                result.data.insert(id, stop);
+            }
+            Ok(None) => {}
+            Err(e) => result.reject(strictness, e)?,
+        }
+    }
+
+    Ok(result)
+}
+
+/// The routes as lines, in file order, along with each one's route ID. Each route ID has to be
+/// used only once, since lines are numbered by where they are in the file.
+fn load_routes<R: Read>(
+    source: R,
+    name: &str,
+    agencies: &IndexMap<String, Company, RandomState>,
+    strictness: Strictness,
+) -> Result<Loaded<IndexMap<String, Line, RandomState>>, DataError> {
+    let mut csv = CsvFile::new(source, name)?;
+    let id_column = csv.column("route_id")?;
+    let agency_column = csv.optional_column("agency_id");
+    let short_name_column = csv.optional_column("route_short_name");
+    let long_name_column = csv.optional_column("route_long_name");
+    let color_column = csv.optional_column("route_color");
+
+    let mut result = Loaded {
+        data: IndexMap::with_hasher(RandomState::new()),
+        warnings: Vec::new(),
+    };
+
+    for row in csv.rows() {
+        let route = row.and_then(|row| {
+            let id = row.get(id_column)?.to_owned();
+            if result.data.contains_key(&id) {
+                return Err(DataError::DuplicateId {
+                    file: row.file.clone(),
+                    line: row.line,
+                    column: "route_id",
+                    id,
+                });
+            }
+
+            let agency = row.get_optional(agency_column).unwrap_or_default();
+            let company_id = match agencies.get(agency) {
+                Some(company) => Some(company.id),
+                // With only one agency, routes don't have to say which one they belong to
+                None if agency.is_empty() && agencies.len() == 1 => Some(agencies[0].id),
+                None => None,
+            };
+
+            let name = row
+                .get_optional(long_name_column)
+                .or_else(|| row.get_optional(short_name_column))
+                .unwrap_or(&id)
+                .to_owned();
+
+            let color = match row.get_optional(color_column) {
+                Some(color) => Some(parse_color(color).ok_or_else(|| DataError::InvalidField {
+                    file: row.file.clone(),
+                    line: row.line,
+                    column: "route_color",
+                    value: color.to_owned(),
+                })?),
+                None => None,
+            };
+
+            Ok((id, name, company_id, color))
+        });
+
+        match route {
+            Ok((id, name, company_id, color)) => {
+                let line_id = LineId(result.data.len() as u32 + 1);
+                result.data.insert(
+                    id,
+                    Line {
+                        id: line_id,
+                        name,
+                        company_id,
+                        color,
+                    },
+                );
+            }
+            Err(e) => result.reject(strictness, e)?,
+        }
+    }
+
+    Ok(result)
+}
+
+/// Trip IDs mapped to the route (as an index into `routes`) and shape of each trip. A trip ID
+/// used again is rejected, and the first trip with it is kept.
+fn load_trips<R: Read>(
+    source: R,
+    name: &str,
+    routes: &IndexMap<String, Line, RandomState>,
+    strictness: Strictness,
+) -> Result<Loaded<IndexMap<String, TripInfo, RandomState>>, DataError> {
+    let mut csv = CsvFile::new(source, name)?;
+    let route_column = csv.column("route_id")?;
+    let id_column = csv.column("trip_id")?;
+    let service_column = csv.optional_column("service_id");
+    let shape_column = csv.optional_column("shape_id");
+
+    let mut result = Loaded {
+        data: IndexMap::with_hasher(RandomState::new()),
+        warnings: Vec::new(),
+    };
+
+    for row in csv.rows() {
+        let trip = row.and_then(|row| {
+            let route_id = row.get(route_column)?;
+            let route = routes
+                .get_index_of(route_id)
+                .ok_or_else(|| DataError::UnknownId {
+                    file: row.file.clone(),
+                    line: row.line,
+                    column: route_column.name,
+                    id: route_id.to_owned(),
+                })?;
+
+            let id = row.get(id_column)?.to_owned();
+            if result.data.contains_key(&id) {
+                return Err(DataError::DuplicateId {
+                    file: row.file.clone(),
+                    line: row.line,
+                    column: "trip_id",
+                    id,
+                });
+            }
+
+            Ok((
+                id,
+                TripInfo {
+                    route,
+                    service_id: row
+                        .get_optional(service_column)
+                        .unwrap_or_default()
+                        .to_owned(),
+                    shape_id: row.get_optional(shape_column).map(str::to_owned),
+                },
+            ))
+        });
+
+        match trip {
+            Ok((id, trip)) => {
+                result.data.insert(id, trip);
+            }
+            Err(e) => result.reject(strictness, e)?,
+        }
+    }
+
+    Ok(result)
+}
+
+/// Service IDs mapped to when they run. A service ID used again is rejected, and the first row
+/// with it is kept.
+fn load_calendar<R: Read>(
+    source: R,
+    name: &str,
+    strictness: Strictness,
+) -> Result<Loaded<IndexMap<String, Calendar, RandomState>>, DataError> {
+    let mut csv = CsvFile::new(source, name)?;
+    let id_column = csv.column("service_id")?;
+    let mut weekday_columns = Vec::with_capacity(WEEKDAY_COLUMNS.len());
+    for column in WEEKDAY_COLUMNS.iter() {
+        weekday_columns.push(csv.column(column)?);
+    }
+    let start_column = csv.column("start_date")?;
+    let end_column = csv.column("end_date")?;
+
+    let mut result = Loaded {
+        data: IndexMap::with_hasher(RandomState::new()),
+        warnings: Vec::new(),
+    };
+
+    for row in csv.rows() {
+        let calendar = row.and_then(|row| {
+            let id = row.get(id_column)?.to_owned();
+            if result.data.contains_key(&id) {
+                return Err(DataError::DuplicateId {
+                    file: row.file.clone(),
+                    line: row.line,
+                    column: "service_id",
+                    id,
+                });
+            }
+
+            let mut weekdays = [false; 7];
+            for (runs, column) in weekdays.iter_mut().zip(&weekday_columns) {
+                *runs = match row.get(*column)? {
+                    "0" => false,
+                    "1" => true,
+                    value => {
+                        return Err(DataError::InvalidField {
+                            file: row.file.clone(),
+                            line: row.line,
+                            column: column.name,
+                            value: value.to_owned(),
+                        })
+                    }
+                };
+            }
+
+            Ok((
+                id,
+                Calendar {
+                    weekdays,
+                    start: row.parse(start_column)?,
+                    end: row.parse(end_column)?,
+                },
+            ))
+        });
+
+        match calendar {
+            Ok((id, calendar)) => {
+                result.data.insert(id, calendar);
+            }
+            Err(e) => result.reject(strictness, e)?,
+        }
+    }
+
+    Ok(result)
+}
+
+/// The dates that services are added to or removed from, in file order
+fn load_calendar_dates<R: Read>(
+    source: R,
+    name: &str,
+    strictness: Strictness,
+) -> Result<Loaded<Vec<CalendarException>>, DataError> {
+    let mut csv = CsvFile::new(source, name)?;
+    let id_column = csv.column("service_id")?;
+    let date_column = csv.column("date")?;
+    let type_column = csv.column("exception_type")?;
+
+    let mut result = Loaded {
+        data: Vec::new(),
+        warnings: Vec::new(),
+    };
+
+    for row in csv.rows() {
+        let exception = row.and_then(|row| {
+            let added = match row.get(type_column)? {
+                "1" => true,
+                "2" => false,
+                value => {
+                    return Err(DataError::InvalidField {
+                        file: row.file.clone(),
+                        line: row.line,
+                        column: type_column.name,
+                        value: value.to_owned(),
+                    })
+                }
+            };
+
+            Ok(CalendarException {
+                service_id: row.get(id_column)?.to_owned(),
+                date: row.parse(date_column)?,
+                added,
+            })
+        });
+
+        match exception {
+            Ok(exception) => result.data.push(exception),
+            Err(e) => result.reject(strictness, e)?,
+        }
+    }
+
+    Ok(result)
+}
+
+/// The stops of each trip, in order, as indices into `stops` along with the arrival and
+/// departure times
+#[allow(clippy::type_complexity)]
+fn load_stop_times<R: Read>(
+    source: R,
+    name: &str,
+    stops: &IndexMap<String, Stop, RandomState>,
+    trips: &IndexMap<String, TripInfo, RandomState>,
+    strictness: Strictness,
+) -> Result<Loaded<IndexMap<String, Vec<(usize, SimTime, SimTime)>, RandomState>>, DataError> {
+    let mut csv = CsvFile::new(source, name)?;
+    let trip_column = csv.column("trip_id")?;
+    let arrival_column = csv.column("arrival_time")?;
+    let departure_column = csv.column("departure_time")?;
+    let stop_column = csv.column("stop_id")?;
+    let sequence_column = csv.column("stop_sequence")?;
+
+    let mut by_trip: IndexMap<String, Vec<(u32, usize, Option<SimTime>, Option<SimTime>)>, _> =
+        IndexMap::with_hasher(RandomState::new());
+    let mut result = Loaded {
+        data: IndexMap::with_hasher(RandomState::new()),
+        warnings: Vec::new(),
+    };
+
+    for row in csv.rows() {
+        let stop_time = row.and_then(|row| {
+            let unknown = |column: crate::data::Column, id: &str| DataError::UnknownId {
+                file: row.file.clone(),
+                line: row.line,
+                column: column.name,
+                id: id.to_owned(),
+            };
+
+            let trip_id = row.get(trip_column)?;
+            if !trips.contains_key(trip_id) {
+                return Err(unknown(trip_column, trip_id));
+            }
+            let stop_id = row.get(stop_column)?;
+            let stop = stops
+                .get_index_of(stop_id)
+                .ok_or_else(|| unknown(stop_column, stop_id))?;
+
+            // Stops that aren't timepoints can leave the times out
+            let arrival = match row.get_optional(Some(arrival_column)) {
+                Some(_) => Some(row.parse(arrival_column)?),
+                None => None,
+            };
+            let departure = match row.get_optional(Some(departure_column)) {
+                Some(_) => Some(row.parse(departure_column)?),
+                None => None,
+            };
+
+            Ok((
+                trip_id.to_owned(),
+                (row.parse(sequence_column)?, stop, arrival, departure),
+            ))
+        });
+
+        match stop_time {
+            Ok((trip_id, stop_time)) => by_trip.entry(trip_id).or_default().push(stop_time),
+            Err(e) => result.reject(strictness, e)?,
+        }
+    }
+
+    for (trip_id, mut trip_stops) in by_trip {
+        trip_stops.sort_by_key(|(sequence, _, _, _)| *sequence);
+        let times = fill_times(
+            &trip_stops
+                .iter()
+                .map(|(_, _, arrival, departure)| (*arrival, *departure))
+                .collect::<Vec<_>>(),
+        );
+
+        if let Some(times) = times {
+            result.data.insert(
+                trip_id,
+                trip_stops
+                    .iter()
+                    .zip(times)
+                    .map(|((_, stop, _, _), (arrival, departure))| (*stop, arrival, departure))
+                    .collect(),
+            );
+        }
+    }
+
+    Ok(result)
+}
+
+/// Fill in the times of stops that don't have them, spacing them evenly between the stops
+/// before and after that do. None if the first or last stop has no time at all.
+fn fill_times(times: &[(Option<SimTime>, Option<SimTime>)]) -> Option<Vec<(SimTime, SimTime)>> {
+    let known: Vec<Option<(SimTime, SimTime)>> = times
+        .iter()
+        .map(|(arrival, departure)| match (arrival, departure) {
+            (Some(a), Some(d)) => Some((*a, *d)),
+            (Some(a), None) => Some((*a, *a)),
+            (None, Some(d)) => Some((*d, *d)),
+            (None, None) => None,
+        })
+        .collect();
+
+    let mut filled = Vec::with_capacity(known.len());
+    let mut previous: Option<(usize, SimTime)> = None;
+    for (i, time) in known.iter().enumerate() {
+        match time {
+            Some(time) => {
+                filled.push(*time);
+                previous = Some((i, time.1));
+            }
+            None => {
+                let (previous_index, previous_time) = previous?;
+                let (next_index, next_time) = known
+                    .iter()
+                    .enumerate()
+                    .skip(i + 1)
+                    .find_map(|(j, t)| t.map(|t| (j, t.0)))?;
+                let step = (next_time.0.saturating_sub(previous_time.0)) as u64
+                    * (i - previous_index) as u64
+                    / (next_index - previous_index) as u64;
+                let time = SimTime(previous_time.0 + step as u32);
+                filled.push((time, time));
+            }
+        }
+    }
+
+    Some(filled)
+}
+
+/// Shape IDs mapped to their points, in order
+fn load_shapes<R: Read>(
+    source: R,
+    name: &str,
+    strictness: Strictness,
+) -> Result<Loaded<ShapeMap>, DataError> {
+    let mut csv = CsvFile::new(source, name)?;
+    let id_column = csv.column("shape_id")?;
+    let lat_column = csv.column("shape_pt_lat")?;
+    let lon_column = csv.column("shape_pt_lon")?;
+    let sequence_column = csv.column("shape_pt_sequence")?;
+
+    let mut points: IndexMap<String, Vec<(u32, MapCoord)>, RandomState> =
+        IndexMap::with_hasher(RandomState::new());
+    let mut result: Loaded<ShapeMap> = Loaded {
+        data: IndexMap::with_hasher(RandomState::new()),
+        warnings: Vec::new(),
+    };
+
+    for row in csv.rows() {
+        let point = row.and_then(|row| {
+            Ok((
+                row.get(id_column)?.to_owned(),
+                row.parse(sequence_column)?,
+                MapCoord {
+                    long: Degree(row.parse(lon_column)?),
+                    lat: Degree(row.parse(lat_column)?),
+                },
+            ))
+        });
+
+        match point {
+            Ok((id, sequence, coord)) => points.entry(id).or_default().push((sequence, coord)),
+            Err(e) => result.reject(strictness, e)?,
+        }
+    }
+
+    for (id, mut shape_points) in points {
+        shape_points.sort_by_key(|(sequence, _)| *sequence);
+        result
+            .data
+            .insert(id, shape_points.into_iter().map(|(_, c)| c).collect());
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(h: u32, m: u32) -> Option<SimTime> {
+        Some(SimTime::from_hms(h, m, 0))
+    }
+
+    #[test]
+    fn missing_times_are_spaced_evenly() {
+        let filled = fill_times(&[
+            (time(8, 0), time(8, 1)),
+            (None, None),
+            (None, None),
+            (time(8, 10), None),
+            (None, time(8, 12)),
+            (None, None),
+            (time(8, 20), time(8, 20)),
+        ])
+        .unwrap();
+
+        let expected: Vec<(SimTime, SimTime)> = [
+            ((8, 0), (8, 1)),
+            ((8, 4), (8, 4)),
+            ((8, 7), (8, 7)),
+            ((8, 10), (8, 10)),
+            ((8, 12), (8, 12)),
+            ((8, 16), (8, 16)),
+            ((8, 20), (8, 20)),
+        ]
+        .iter()
+        .map(|(a, d)| (time(a.0, a.1).unwrap(), time(d.0, d.1).unwrap()))
+        .collect();
+        assert_eq!(filled, expected);
+    }
+
+    #[test]
+    fn trips_without_first_or_last_time_are_left_out() {
+        assert_eq!(fill_times(&[(None, None), (time(8, 0), time(8, 0))]), None);
+        assert_eq!(fill_times(&[(time(8, 0), time(8, 0)), (None, None)]), None);
+        assert_eq!(fill_times(&[]), Some(vec![]));
+    }
+
+    const STOPS: &str = "stop_id,stop_name,stop_lat,stop_lon,location_type,parent_station
+A,Alpha,35.0,139.0,0,
+B,Beta,35.1,139.1,0,
+";
+    const ROUTES: &str = "route_id,route_short_name,route_color\nR,Red,FF0000\n";
+    const TRIPS: &str = "route_id,trip_id\nR,T1\nR,T2\n";
+    const STOP_TIMES: &str = "trip_id,arrival_time,departure_time,stop_id,stop_sequence
+T1,08:00:00,08:00:00,A,1
+T1,08:05:00,08:05:00,B,2
+T2,09:00:00,09:00:00,A,1
+T2,09:05:00,09:05:00,C,2
+";
+
+    #[allow(clippy::type_complexity)]
+    fn stop_times(
+        strictness: Strictness,
+    ) -> Result<Loaded<IndexMap<String, Vec<(usize, SimTime, SimTime)>, RandomState>>, DataError>
+    {
+        let stops = load_stops(STOPS.as_bytes(), "stops.txt", Strictness::Strict)
+            .unwrap()
+            .data;
+        let agencies = IndexMap::with_hasher(RandomState::new());
+        let routes = load_routes(
+            ROUTES.as_bytes(),
+            "routes.txt",
+            &agencies,
+            Strictness::Strict,
+        )
+        .unwrap()
+        .data;
+        let trips = load_trips(TRIPS.as_bytes(), "trips.txt", &routes, Strictness::Strict)
+            .unwrap()
+            .data;
+        load_stop_times(
+            STOP_TIMES.as_bytes(),
+            "stop_times.txt",
+            &stops,
+            &trips,
+            strictness,
+        )
+    }
+
+    #[test]
+    fn unknown_stop_fails_strict_loading() {
+        let error = stop_times(Strictness::Strict).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "stop_times.txt:5: unknown `stop_id` \"C\""
+        );
+    }
+
+    #[test]
+    fn unknown_stop_is_skipped_by_lenient_loading() {
+        let loaded = stop_times(Strictness::Lenient).unwrap();
+        assert_eq!(loaded.warnings.len(), 1);
+        assert!(matches!(
+            loaded.warnings[0],
+            DataError::UnknownId {
+                line: 5,
+                column: "stop_id",
+                ..
+            }
+        ));
+
+        // T2 is left with a single stop
+        assert_eq!(loaded.data["T1"].len(), 2);
+        assert_eq!(loaded.data["T2"].len(), 1);
+    }
+
+    #[test]
+    fn duplicate_route_is_rejected() {
+        let routes = "route_id,route_short_name\nA,Alpha\nB,Beta\nA,Again\nC,Gamma\n";
+        let agencies = IndexMap::with_hasher(RandomState::new());
+
+        let error = load_routes(
+            routes.as_bytes(),
+            "routes.txt",
+            &agencies,
+            Strictness::Strict,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "routes.txt:4: `route_id` \"A\" is already taken"
+        );
+
+        // The first route with the ID is kept, and every route still gets its own line
+        let loaded = load_routes(
+            routes.as_bytes(),
+            "routes.txt",
+            &agencies,
+            Strictness::Lenient,
+        )
+        .unwrap();
+        assert_eq!(loaded.warnings.len(), 1);
+        let lines: Vec<(&str, u32, &str)> = loaded
+            .data
+            .iter()
+            .map(|(id, line)| (id.as_str(), line.id.0, line.name.as_str()))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![("A", 1, "Alpha"), ("B", 2, "Beta"), ("C", 3, "Gamma")]
+        );
+    }
+
+    #[test]
+    fn duplicate_stop_is_rejected() {
+        let stops = "stop_id,stop_name,stop_lat,stop_lon
+A,Alpha,35.0,139.0
+A,Again,35.2,139.2
+B,Beta,35.1,139.1
+";
+
+        let error = load_stops(stops.as_bytes(), "stops.txt", Strictness::Strict)
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "stops.txt:3: `stop_id` \"A\" is already taken"
+        );
+
+        let loaded = load_stops(stops.as_bytes(), "stops.txt", Strictness::Lenient).unwrap();
+        assert_eq!(loaded.warnings.len(), 1);
+        let names: Vec<&str> = loaded.data.values().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["Alpha", "Beta"]);
+    }
+
+    #[test]
+    fn duplicate_trip_is_rejected() {
+        let agencies = IndexMap::with_hasher(RandomState::new());
+        let routes = load_routes(
+            "route_id\nR\nS\n".as_bytes(),
+            "routes.txt",
+            &agencies,
+            Strictness::Strict,
+        )
+        .unwrap()
+        .data;
+        let trips = "route_id,trip_id\nR,T1\nS,T1\nS,T2\n";
+
+        let error = load_trips(trips.as_bytes(), "trips.txt", &routes, Strictness::Strict)
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "trips.txt:3: `trip_id` \"T1\" is already taken"
+        );
+
+        let loaded =
+            load_trips(trips.as_bytes(), "trips.txt", &routes, Strictness::Lenient).unwrap();
+        assert_eq!(loaded.warnings.len(), 1);
+        assert_eq!(loaded.data.len(), 2);
+        assert_eq!(loaded.data["T1"].route, 0);
+    }
+
+    #[test]
+    fn dates_are_parsed_with_or_without_dashes() {
+        let date = ServiceDate {
+            year: 2024,
+            month: 4,
+            day: 1,
+        };
+        assert_eq!("20240401".parse(), Ok(date));
+        assert_eq!("2024-04-01".parse(), Ok(date));
+        for invalid in ["2024041", "2024-4-01", "20241301", "2024040a", "2024/04/01"] {
+            assert_eq!(invalid.parse::<ServiceDate>(), Err(()), "{}", invalid);
+        }
+
+        // 1 April 2024 was a Monday, and 29 February 2024 a Thursday
+        assert_eq!(date.weekday(), 0);
+        assert_eq!("20240407".parse::<ServiceDate>().unwrap().weekday(), 6);
+        assert_eq!("20240229".parse::<ServiceDate>().unwrap().weekday(), 3);
+    }
+
+    const CALENDAR: &str =
+        "service_id,monday,tuesday,wednesday,thursday,friday,saturday,sunday,start_date,end_date
+WEEKDAY,1,1,1,1,1,0,0,20240101,20241231
+WEEKEND,0,0,0,0,0,1,1,20240101,20241231
+";
+    const CALENDAR_DATES: &str = "service_id,date,exception_type
+WEEKDAY,20240429,2
+WEEKEND,20240429,1
+";
+
+    /// The services chosen out of three: one for weekdays with two trips, one for weekends with
+    /// a single trip, and one that isn't in the calendar
+    fn choose(
+        choice: ServiceChoice,
+        strictness: Strictness,
+    ) -> Result<Loaded<Vec<String>>, DataError> {
+        let agencies = IndexMap::with_hasher(RandomState::new());
+        let routes = load_routes(
+            "route_id\nR\n".as_bytes(),
+            "routes.txt",
+            &agencies,
+            Strictness::Strict,
+        )
+        .unwrap()
+        .data;
+        let trips = load_trips(
+            "route_id,trip_id,service_id
+R,T1,WEEKEND
+R,T2,WEEKDAY
+R,T3,WEEKDAY
+R,T4,EXTRA
+"
+            .as_bytes(),
+            "trips.txt",
+            &routes,
+            Strictness::Strict,
+        )
+        .unwrap()
+        .data;
+        let stop_times = trips
+            .keys()
+            .map(|id| (id.clone(), Vec::new()))
+            .collect::<IndexMap<_, _, RandomState>>();
+        let calendar = load_calendar(CALENDAR.as_bytes(), "calendar.txt", Strictness::Strict)
+            .unwrap()
+            .data;
+        let exceptions = load_calendar_dates(
+            CALENDAR_DATES.as_bytes(),
+            "calendar_dates.txt",
+            Strictness::Strict,
+        )
+        .unwrap()
+        .data;
+
+        choose_services(
+            &choice,
+            "trips.txt",
+            &trips,
+            &stop_times,
+            &calendar,
+            &exceptions,
+            strictness,
+        )
+    }
+
+    fn services(choice: ServiceChoice) -> Vec<String> {
+        choose(choice, Strictness::Strict).unwrap().data
+    }
+
+    fn date(date: &str) -> ServiceChoice {
+        ServiceChoice::Date(date.parse().unwrap())
+    }
+
+    #[test]
+    fn services_are_chosen_by_id_or_date() {
+        assert_eq!(services(ServiceChoice::Busiest), ["WEEKDAY"]);
+        assert_eq!(services(ServiceChoice::Id("EXTRA".to_owned())), ["EXTRA"]);
+
+        assert_eq!(services(date("2024-04-26")), ["WEEKDAY"]);
+        assert_eq!(services(date("2024-04-27")), ["WEEKEND"]);
+
+        // A holiday on a Monday runs the weekend service instead
+        assert_eq!(services(date("2024-04-29")), ["WEEKEND"]);
+    }
+
+    #[test]
+    fn busiest_service_is_used_when_none_are_chosen() {
+        // After the end of the calendar
+        assert_eq!(services(date("2025-01-04")), ["WEEKDAY"]);
+    }
+
+    #[test]
+    fn unknown_service_is_reported() {
+        let unknown = || ServiceChoice::Id("WEEKDYA".to_owned());
+        let error = choose(unknown(), Strictness::Strict).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "trips.txt: no trips are on service \"WEEKDYA\""
+        );
+
+        // Lenient mode falls back to the busiest service, but says why
+        let loaded = choose(unknown(), Strictness::Lenient).unwrap();
+        assert_eq!(loaded.data, ["WEEKDAY"]);
+        assert_eq!(loaded.warnings.len(), 1);
+        assert_eq!(loaded.warnings[0].to_string(), error.to_string());
+    }
+
+    #[test]
+    fn invalid_calendar_days_are_reported() {
+        let calendar =
+            "service_id,monday,tuesday,wednesday,thursday,friday,saturday,sunday,start_date,end_date
+A,1,1,1,1,1,0,0,20240101,20241231
+B,1,1,1,1,1,yes,0,20240101,20241231
+";
+        let error = load_calendar(calendar.as_bytes(), "calendar.txt", Strictness::Strict)
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "calendar.txt:3: invalid `saturday` value \"yes\""
+        );
+
+        let dates = "service_id,date,exception_type\nA,20240429,3\n";
+        let error = load_calendar_dates(dates.as_bytes(), "calendar_dates.txt", Strictness::Strict)
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "calendar_dates.txt:2: invalid `exception_type` value \"3\""
+        );
+    }
+
+    /// A small feed: a red line from Central to North running three times, twice on weekdays and
+    /// once at the weekend, and a blue line from Central to East running once on weekdays.
+    /// Central has a platform for each line.
+    const FEED: [(&str, &str); 7] = [
+        ("agency.txt", "agency_id,agency_name\nA,Alpha Rail\n"),
+        (
+            "stops.txt",
+            "stop_id,stop_name,stop_lat,stop_lon,location_type,parent_station
+C,Central,35.0,139.0,1,
+C1,Central,35.0,139.0,0,C
+C2,Central,35.0002,139.0002,0,C
+N,North,35.1,139.0,0,
+E,East,35.0,139.1,0,
+",
+        ),
+        (
+            "routes.txt",
+            "route_id,agency_id,route_short_name,route_long_name,route_color
+R,A,R,Red Line,FF0000
+B,A,B,Blue Line,0000FF
+",
+        ),
+        (
+            "trips.txt",
+            "route_id,service_id,trip_id\nR,WEEKDAY,R1\nR,WEEKDAY,R2\nR,WEEKEND,R3\nB,WEEKDAY,B1\n",
+        ),
+        (
+            "stop_times.txt",
+            "trip_id,arrival_time,departure_time,stop_id,stop_sequence
+R1,08:00:00,08:00:00,C1,1
+R1,08:10:00,08:10:00,N,2
+R2,09:00:00,09:00:00,C1,1
+R2,09:10:00,09:10:00,N,2
+R3,10:00:00,10:00:00,C1,1
+R3,10:10:00,10:10:00,N,2
+B1,08:30:00,08:30:00,C2,1
+B1,08:45:00,08:45:00,E,2
+",
+        ),
+        ("calendar.txt", CALENDAR),
+        ("calendar_dates.txt", "service_id,date,exception_type\n"),
+    ];
+
+    /// A directory of its own for a test to write files into
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("the-sprawl-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn check_feed(path: &Path) {
+        let feed = load_gtfs(path, &ServiceChoice::Busiest, Strictness::Strict)
+            .unwrap()
+            .data;
+        let network = &feed.network;
+
+        // A station for each line at each stop, numbered by line and then by stop
+        let stations: Vec<(u32, &str, u32)> = network
+            .stations
+            .values()
+            .map(|s| (s.id.0, s.name.as_str(), s.line_id.0))
+            .collect();
+        assert_eq!(
+            stations,
+            [
+                (1, "Central", 1),
+                (2, "North", 1),
+                (3, "Central", 2),
+                (4, "East", 2)
+            ]
+        );
+        assert_eq!(network.groups.len(), 3);
+        assert_eq!(
+            network.group_of(StationId(3)).unwrap().members,
+            [StationId(1), StationId(3)]
+        );
+
+        assert!(network.connections[&StationId(1)].contains(&StationId(2)));
+        assert!(network.connections[&StationId(4)].contains(&StationId(3)));
+        assert!(!network.connections[&StationId(1)].contains(&StationId(3)));
+
+        let red = network.station_line(StationId(1)).unwrap();
+        assert_eq!(red.name, "Red Line");
+        assert_eq!(red.color, Some([0xFF, 0, 0]));
+        assert_eq!(network.line_company(red.id).unwrap().name, "Alpha Rail");
+
+        // Only the weekday trips run, since that service has the most of them
+        assert_eq!(feed.services, ["WEEKDAY"]);
+        let trips: Vec<(SimTime, Vec<StationId>)> = feed
+            .timetable
+            .trips()
+            .iter()
+            .map(|trip| {
+                let stops = &feed.timetable.pattern(trip.pattern).stops;
+                (trip.start, stops.iter().map(|s| s.station_id).collect())
+            })
+            .collect();
+        assert_eq!(
+            trips,
+            [
+                (SimTime::from_hms(8, 0, 0), vec![StationId(1), StationId(2)]),
+                (
+                    SimTime::from_hms(8, 30, 0),
+                    vec![StationId(3), StationId(4)]
+                ),
+                (SimTime::from_hms(9, 0, 0), vec![StationId(1), StationId(2)]),
+            ]
+        );
+
+        // 6 April 2024 was a Saturday
+        let weekend = ServiceChoice::Date("20240406".parse().unwrap());
+        let feed = load_gtfs(path, &weekend, Strictness::Strict).unwrap().data;
+        assert_eq!(feed.services, ["WEEKEND"]);
+        assert_eq!(feed.timetable.trips().len(), 1);
+        assert_eq!(feed.timetable.trips()[0].start, SimTime::from_hms(10, 0, 0));
+    }
+
+    #[test]
+    fn feed_is_loaded_from_a_directory() {
+        let dir = test_dir("gtfs-directory");
+        for (file, contents) in FEED.iter() {
+            std::fs::write(dir.join(file), contents).unwrap();
+        }
+
+        check_feed(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn feed_is_loaded_from_a_zip() {
+        use std::io::Write;
+        use zip::{write::FileOptions, ZipWriter};
+
+        let dir = test_dir("gtfs-zip");
+        let path = dir.join("feed.zip");
+        let mut zip = ZipWriter::new(File::create(&path).unwrap());
+        for (file, contents) in FEED.iter() {
+            // Some feeds keep their files in a folder inside the zip
+            zip.start_file(format!("feed/{}", file), FileOptions::default())
+                .unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+
+        check_feed(&path);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_route_color_is_reported() {
+        let routes = "route_id,route_short_name,route_color\nR,Red,FF0000\nG,Green,green\n";
+        let agencies = IndexMap::with_hasher(RandomState::new());
+
+        let error = load_routes(
+            routes.as_bytes(),
+            "routes.txt",
+            &agencies,
+            Strictness::Strict,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "routes.txt:3: invalid `route_color` value \"green\""
+        );
+
+        let loaded = load_routes(
+            routes.as_bytes(),
+            "routes.txt",
+            &agencies,
+            Strictness::Lenient,
+        )
+        .unwrap();
+        assert_eq!(loaded.data.len(), 1);
+        assert_eq!(loaded.warnings.len(), 1);
+    }
+}
@@ -0,0 +1,108 @@
+//! The panel with the details of the station that was clicked on
+
+use crate::{
+    data::{Network, StationGroup, StationGroupId},
+    effect::EffectManager,
+    fonts::FontManager,
+    map::{MapCoord, Viewport},
+    names::Language,
+    panel::{draw_panel, Corner, TEXT_COLOR, TITLE_COLOR},
+};
+
+/// Everything the panel shows about a station group
+#[derive(Debug, Clone)]
+pub struct StationInfo {
+    pub id: StationGroupId,
+    pub name: String,
+    pub coord: MapCoord,
+
+    /// The names of the lines stopping here, in station ID order
+    pub lines: Vec<String>,
+
+    /// The names of the stations connected directly to this one, in ID order
+    pub neighbors: Vec<String>,
+
+    /// How often trains have reached any of the stations in the group lately
+    pub popularity: u32,
+}
+
+impl StationInfo {
+    /// The details of a station group, with it and its neighbors named in `language`
+    pub fn new(
+        network: &Network,
+        effect_manager: &EffectManager,
+        group_id: StationGroupId,
+        language: Language,
+    ) -> Option<Self> {
+        let group = network.groups.get(&group_id)?;
+
+        // Lines are only named when a line file was loaded, otherwise they go by their ID
+        let mut lines: Vec<String> = Vec::new();
+        for line in group
+            .members
+            .iter()
+            .filter_map(|id| network.station_line(*id))
+        {
+            let name = if line.name.is_empty() {
+                line.id.0.to_string()
+            } else {
+                line.name.clone()
+            };
+            if !lines.contains(&name) {
+                lines.push(name);
+            }
+        }
+
+        // Transfers within the group are the same station as far as the panel is concerned
+        let mut neighbors: Vec<&StationGroup> = group
+            .members
+            .iter()
+            .filter_map(|id| network.connections.get(id))
+            .flatten()
+            .filter_map(|id| network.group_of(*id))
+            .filter(|neighbor| neighbor.id != group.id)
+            .collect();
+        neighbors.sort_by_key(|neighbor| neighbor.id);
+        neighbors.dedup_by_key(|neighbor| neighbor.id);
+
+        Some(Self {
+            id: group.id,
+            name: group.display_name(language).into_owned(),
+            coord: group.coord,
+            lines,
+            neighbors: neighbors
+                .into_iter()
+                .map(|neighbor| neighbor.display_name(language).into_owned())
+                .collect(),
+            popularity: group
+                .members
+                .iter()
+                .map(|id| effect_manager.station_popularity(*id))
+                .sum(),
+        })
+    }
+
+    /// The rows of text in the panel, starting with the title
+    pub fn rows(&self) -> Vec<String> {
+        vec![
+            self.name.clone(),
+            format!("ID: {}", self.id.0),
+            format!("{:.5}, {:.5}", self.coord.lat.0, self.coord.long.0),
+            format!("Lines: {}", self.lines.join(", ")),
+            format!("Next to: {}", self.neighbors.join(", ")),
+            format!("Popularity: {}", self.popularity),
+        ]
+    }
+
+    /// Draw the panel into the top left corner of `buffer`, an RGBA frame buffer the size of
+    /// `viewport`
+    pub fn draw(&self, buffer: &mut [u8], viewport: Viewport, font_manager: &FontManager) {
+        let rows: Vec<(String, [u8; 3])> = self
+            .rows()
+            .into_iter()
+            .enumerate()
+            .map(|(i, row)| (row, if i == 0 { TITLE_COLOR } else { TEXT_COLOR }))
+            .collect();
+        draw_panel(buffer, viewport, font_manager, &rows, Corner::TopLeft);
+    }
+}
@@ -0,0 +1,399 @@
+//! Deciding where station names go, so that they don't cover each other or other stations
+
+use crate::data::StationGroupId;
+use crate::tile::{Tile, TileCounts};
+use ahash::RandomState;
+use std::{cmp::Reverse, collections::HashMap};
+
+/// How many empty tiles to leave between a name and its station, and around each name
+const GAP: i32 = 1;
+
+/// The side length, in tiles, of the squares that taken boxes are bucketed by
+const BUCKET_SIZE: i32 = 64;
+
+/// Which side of its station a name goes on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Anchor {
+    North,
+    East,
+    South,
+    West,
+}
+
+impl Anchor {
+    /// Every anchor, in the order they're tried
+    pub const ALL: [Anchor; 4] = [Anchor::North, Anchor::East, Anchor::South, Anchor::West];
+}
+
+/// A box of tiles, including the tiles on its edges
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TileRect {
+    pub left: i32,
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+}
+
+impl TileRect {
+    /// The smallest box around all of the given tiles, or None if there aren't any
+    pub fn around<I: IntoIterator<Item = (i32, i32)>>(tiles: I) -> Option<Self> {
+        tiles.into_iter().fold(None, |rect: Option<Self>, (x, y)| {
+            Some(match rect {
+                Some(rect) => Self {
+                    left: rect.left.min(x),
+                    top: rect.top.min(y),
+                    right: rect.right.max(x),
+                    bottom: rect.bottom.max(y),
+                },
+                None => Self {
+                    left: x,
+                    top: y,
+                    right: x,
+                    bottom: y,
+                },
+            })
+        })
+    }
+
+    fn offset(self, x: i32, y: i32) -> Self {
+        Self {
+            left: self.left + x,
+            top: self.top + y,
+            right: self.right + x,
+            bottom: self.bottom + y,
+        }
+    }
+
+    fn padded(self, padding: i32) -> Self {
+        Self {
+            left: self.left - padding,
+            top: self.top - padding,
+            right: self.right + padding,
+            bottom: self.bottom + padding,
+        }
+    }
+
+    fn overlaps(&self, other: &TileRect) -> bool {
+        self.left <= other.right
+            && other.left <= self.right
+            && self.top <= other.bottom
+            && other.top <= self.bottom
+    }
+
+    /// The buckets this box is in
+    fn buckets(&self) -> impl Iterator<Item = (i32, i32)> {
+        let (left, right) = (
+            self.left.div_euclid(BUCKET_SIZE),
+            self.right.div_euclid(BUCKET_SIZE),
+        );
+        let (top, bottom) = (
+            self.top.div_euclid(BUCKET_SIZE),
+            self.bottom.div_euclid(BUCKET_SIZE),
+        );
+        (top..=bottom).flat_map(move |y| (left..=right).map(move |x| (x, y)))
+    }
+}
+
+/// A station name waiting to be placed
+#[derive(Clone, Copy, Debug)]
+pub struct Label {
+    /// The station's center tile
+    pub tile: Tile,
+    pub group_id: StationGroupId,
+
+    /// Names with a higher priority are placed first, and so are the last to be dropped
+    pub priority: usize,
+
+    /// Where the name's pixels are when it's centered on the station's tile
+    pub bounds: TileRect,
+}
+
+/// The boxes that names can't be placed over, bucketed so that only nearby ones are checked
+#[derive(Default)]
+struct TakenBoxes {
+    boxes: Vec<TileRect>,
+    buckets: HashMap<(i32, i32), Vec<usize>, RandomState>,
+}
+
+impl TakenBoxes {
+    fn insert(&mut self, rect: TileRect) {
+        let index = self.boxes.len();
+        self.boxes.push(rect);
+        for bucket in rect.buckets() {
+            self.buckets.entry(bucket).or_default().push(index);
+        }
+    }
+
+    fn is_free(&self, rect: &TileRect) -> bool {
+        rect.buckets().all(|bucket| {
+            self.buckets.get(&bucket).map_or(true, |indices| {
+                indices.iter().all(|i| !self.boxes[*i].overlaps(rect))
+            })
+        })
+    }
+}
+
+/// Places station names around their stations. Remembers where each name went, and tries that
+/// side first the next time, so that names don't jump around as the map is panned or zoomed. A
+/// side is forgotten once its station goes out of view.
+#[derive(Default)]
+pub struct LabelPlacer {
+    previous: HashMap<StationGroupId, Anchor, RandomState>,
+}
+
+impl LabelPlacer {
+    /// Place as many of the labels as fit without covering each other. Names also keep clear of
+    /// the stations centered on `station_tiles`, which are `station_width` tiles wide, and then
+    /// of the tracks counted in `track_tiles`, unless there's no other room for them. The most
+    /// important stations are placed first, and names that fit nowhere are left out. Returns the
+    /// index of each placed label and how far its name is moved from being centered on its
+    /// station.
+    pub fn place(
+        &mut self,
+        labels: &[Label],
+        station_tiles: &[Tile],
+        station_width: i32,
+        track_tiles: &TileCounts,
+    ) -> Vec<(usize, (i32, i32))> {
+        // The station box around a center tile, matching Tile::get_box
+        let near = (station_width - 1) / 2;
+        let far = station_width / 2;
+
+        let mut stations = TakenBoxes::default();
+        for tile in station_tiles {
+            stations.insert(TileRect {
+                left: tile.x.0 - near,
+                top: tile.y.0 - near,
+                right: tile.x.0 + far,
+                bottom: tile.y.0 + far,
+            });
+        }
+
+        // Ties go to the lower ID, rather than to whichever station comes first on the screen,
+        // so that panning doesn't change who wins
+        let mut order: Vec<usize> = (0..labels.len()).collect();
+        order.sort_by_key(|i| (Reverse(labels[*i].priority), labels[*i].group_id));
+
+        let mut names = TakenBoxes::default();
+        let mut placed = Vec::new();
+        let mut anchors = HashMap::default();
+        for i in order {
+            let label = &labels[i];
+            let bounds = label.bounds;
+            let center_x = -(bounds.left + bounds.right) / 2;
+            let center_y = -(bounds.top + bounds.bottom) / 2;
+
+            let previous = self.previous.get(&label.group_id).copied();
+            let candidates: Vec<(Anchor, (i32, i32), TileRect)> = previous
+                .into_iter()
+                .chain(Anchor::ALL.iter().copied().filter(|a| Some(*a) != previous))
+                .map(|anchor| {
+                    let offset = match anchor {
+                        Anchor::North => (center_x, -near - GAP - 1 - bounds.bottom),
+                        Anchor::East => (far + GAP + 1 - bounds.left, center_y),
+                        Anchor::South => (center_x, far + GAP + 1 - bounds.top),
+                        Anchor::West => (-near - GAP - 1 - bounds.right, center_y),
+                    };
+                    let rect = bounds
+                        .offset(label.tile.x.0 + offset.0, label.tile.y.0 + offset.1)
+                        .padded(GAP);
+                    (anchor, offset, rect)
+                })
+                .collect();
+
+            let covers_track = |rect: &TileRect| {
+                let upper_left = Tile {
+                    x: rect.left.into(),
+                    y: rect.top.into(),
+                };
+                let lower_right = Tile {
+                    x: rect.right.into(),
+                    y: rect.bottom.into(),
+                };
+                track_tiles.count(upper_left, lower_right) > 0
+            };
+            let best = candidates
+                .iter()
+                .filter(|(_, _, rect)| names.is_free(rect))
+                .min_by_key(|(_, _, rect)| (!stations.is_free(rect), covers_track(rect)));
+            if let Some((anchor, offset, rect)) = best {
+                names.insert(*rect);
+                anchors.insert(label.group_id, *anchor);
+                placed.push((i, *offset));
+            }
+        }
+
+        // Names that didn't fit this time keep their side for when there's room again, as long as
+        // their station is still in view
+        for label in labels {
+            if let Some(anchor) = self.previous.get(&label.group_id) {
+                anchors.entry(label.group_id).or_insert(*anchor);
+            }
+        }
+        self.previous = anchors;
+        placed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::MapFrame;
+    use crate::tile::TileRaster;
+
+    fn label(id: u32, x: i32, y: i32, priority: usize, half_size: i32) -> Label {
+        Label {
+            tile: Tile {
+                x: x.into(),
+                y: y.into(),
+            },
+            group_id: StationGroupId(id),
+            priority,
+            bounds: TileRect {
+                left: -half_size,
+                top: -half_size,
+                right: half_size,
+                bottom: half_size,
+            },
+        }
+    }
+
+    #[test]
+    fn sides_are_kept_while_the_station_is_in_view() {
+        let small = label(1, 0, 0, 1, 2);
+        // A name so big that wherever it goes, there's no room left around the small one
+        let big = label(2, 0, 1000, 2, 1000);
+        let no_tracks = TileCounts::default();
+
+        let mut placer = LabelPlacer::default();
+        placer.previous.insert(small.group_id, Anchor::West);
+
+        // Crowded out, but still in view
+        let placed = placer.place(&[small, big], &[], 1, &no_tracks);
+        assert_eq!(placed.iter().map(|(i, _)| *i).collect::<Vec<_>>(), vec![1]);
+        assert_eq!(placer.previous.get(&small.group_id), Some(&Anchor::West));
+
+        // Once there's room again, it goes back where it was
+        let placed = placer.place(&[small], &[], 1, &no_tracks);
+        assert_eq!(placed.len(), 1);
+        assert!((placed[0].1).0 < 0);
+
+        // Out of view, so it's forgotten
+        placer.place(&[big], &[], 1, &no_tracks);
+        assert_eq!(placer.previous.get(&small.group_id), None);
+    }
+
+    fn tile(x: i32, y: i32) -> Tile {
+        Tile {
+            x: x.into(),
+            y: y.into(),
+        }
+    }
+
+    /// Where the name of a placed label ends up
+    fn placed_rect(label: &Label, offset: (i32, i32)) -> TileRect {
+        label
+            .bounds
+            .offset(label.tile.x.0 + offset.0, label.tile.y.0 + offset.1)
+    }
+
+    /// Which side of its station a placed label's name is on
+    fn side(label: &Label, offset: (i32, i32)) -> Anchor {
+        let rect = placed_rect(label, offset);
+        if rect.bottom < label.tile.y.0 {
+            Anchor::North
+        } else if rect.top > label.tile.y.0 {
+            Anchor::South
+        } else if rect.left > label.tile.x.0 {
+            Anchor::East
+        } else {
+            Anchor::West
+        }
+    }
+
+    #[test]
+    fn names_never_share_space() {
+        // A crowd of stations close enough together that their names compete for room
+        let labels: Vec<Label> = (0..30)
+            .map(|i| label(i, (i as i32 % 6) * 7, (i as i32 / 6) * 5, i as usize % 4, 2))
+            .collect();
+        let placed = LabelPlacer::default().place(&labels, &[], 1, &TileCounts::default());
+
+        assert!(placed.len() > 1 && placed.len() < labels.len());
+        for (n, (i, offset)) in placed.iter().enumerate() {
+            for (j, other_offset) in &placed[n + 1..] {
+                assert!(
+                    !placed_rect(&labels[*i], *offset)
+                        .overlaps(&placed_rect(&labels[*j], *other_offset)),
+                    "{} and {} overlap",
+                    i,
+                    j
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn higher_priority_gets_the_first_choice() {
+        // Two names for stations in the same place both want to go north
+        for (first_priority, second_priority) in [(1, 2), (2, 1)] {
+            let labels = [
+                label(1, 0, 0, first_priority, 2),
+                label(2, 0, 0, second_priority, 2),
+            ];
+            let placed = LabelPlacer::default().place(&labels, &[], 1, &TileCounts::default());
+            assert_eq!(placed.len(), 2);
+
+            for (i, offset) in placed {
+                let expected = if labels[i].priority == 2 {
+                    Anchor::North
+                } else {
+                    Anchor::South
+                };
+                assert_eq!(side(&labels[i], offset), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn names_with_no_room_are_left_out() {
+        // Names so big that two of them at the same station take up all the room around it
+        let labels = [
+            label(1, 0, 0, 1, 1000),
+            label(2, 0, 0, 3, 1000),
+            label(3, 0, 0, 2, 1000),
+        ];
+        let placed = LabelPlacer::default().place(&labels, &[], 1, &TileCounts::default());
+
+        let mut indices: Vec<usize> = placed.iter().map(|(i, _)| *i).collect();
+        indices.sort_unstable();
+        assert_eq!(indices, [1, 2]);
+    }
+
+    #[test]
+    fn names_keep_off_stations_and_tracks_when_they_can() {
+        let name = label(1, 0, 0, 1, 2);
+        let no_tracks = TileCounts::default();
+        let mut placer = LabelPlacer::default();
+
+        // A station just north of this one
+        let placed = placer.place(&[name], &[tile(0, 0), tile(0, -4)], 1, &no_tracks);
+        assert_eq!(side(&name, placed[0].1), Anchor::East);
+
+        // A track just north of this one
+        let map_frame = MapFrame::default();
+        let origin = tile(map_frame.tile_columns()[20].0, map_frame.tile_rows()[20].0);
+        let name = label(1, origin.x.0, origin.y.0, 1, 2);
+        let mut tracks = TileRaster::new();
+        tracks.reset(&map_frame);
+        tracks.set(tile(origin.x.0, origin.y.0 - 4), true);
+        let placed = LabelPlacer::default().place(&[name], &[origin], 1, &tracks.counts(|t| t));
+        assert_eq!(side(&name, placed[0].1), Anchor::East);
+
+        // Stations on every side, so the name goes over one of them rather than nowhere
+        let crowded = [tile(0, 0), tile(0, -4), tile(5, 0), tile(0, 4), tile(-5, 0)];
+        let name = label(1, 0, 0, 1, 2);
+        let placed = LabelPlacer::default().place(&[name], &crowded, 1, &no_tracks);
+        assert_eq!(placed.len(), 1);
+    }
+}
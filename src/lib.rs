@@ -1,7 +1,22 @@
+pub mod camera;
+pub mod clock;
 pub mod constants;
 pub mod data;
 pub mod effect;
 pub mod fonts;
+pub mod geometry;
+pub mod glyph_cache;
+pub mod gtfs;
+pub mod inspector;
+pub mod labels;
 pub mod map;
+pub mod names;
+pub mod panel;
+pub mod projection;
+pub mod route;
+pub mod schedule;
+pub mod search;
+pub mod snapshot;
+pub mod spatial;
 pub mod tile;
 pub mod world;
@@ -1,9 +1,10 @@
 use crate::{
     constants::*,
+    projection::{Equirectangular, Projection},
     tile::{Tile, TileIterator, TilePos},
 };
 use derive_more::{Add, AddAssign, Div, From, FromStr, Mul, Sub, SubAssign};
-use std::fmt::Display;
+use std::{fmt::Display, rc::Rc};
 
 /// For longitude and latitude
 #[derive(
@@ -26,6 +27,33 @@ use std::fmt::Display;
 #[from(forward)]
 pub struct Degree(pub f32);
 
+/// For distances on the ground
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    From,
+    FromStr,
+    AddAssign,
+    SubAssign,
+    PartialOrd,
+    PartialEq,
+)]
+#[mul(forward)]
+#[div(forward)]
+#[from(forward)]
+pub struct Meters(pub f32);
+
+impl Meters {
+    pub fn kilometers(self) -> f32 {
+        self.0 / 1000.0
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct MapCoord {
     pub long: Degree,
@@ -33,6 +61,9 @@ pub struct MapCoord {
 }
 
 impl MapCoord {
+    /// Straight-line distance in raw degrees, as if longitude and latitude were the same size.
+    /// This matches distances on the (unprojected) screen, so it's what to use for things like
+    /// how close the mouse is to a station, but not for how far apart two places really are.
     pub fn distance_to(&self, other: &MapCoord) -> Degree {
         let long_dist: Degree = self.long - other.long;
         let lat_dist: Degree = self.lat - other.lat;
@@ -41,6 +72,17 @@ impl MapCoord {
 
         Degree(sum_of_squares.0.sqrt())
     }
+
+    /// Great-circle distance on the ground, using the haversine formula
+    pub fn meters_to(&self, other: &MapCoord) -> Meters {
+        let lat1 = (self.lat.0 as f64).to_radians();
+        let lat2 = (other.lat.0 as f64).to_radians();
+        let half_lat = (lat2 - lat1) / 2.0;
+        let half_long = ((other.long.0 - self.long.0) as f64).to_radians() / 2.0;
+
+        let a = half_lat.sin().powi(2) + lat1.cos() * lat2.cos() * half_long.sin().powi(2);
+        Meters((2.0 * EARTH_RADIUS.0 as f64 * a.sqrt().min(1.0).asin()) as f32)
+    }
 }
 
 impl Display for MapCoord {
@@ -49,38 +91,247 @@ impl Display for MapCoord {
     }
 }
 
-/// A rectangle view onto the map. Values are lat/long
-#[derive(Debug)]
+/// The size, in pixels, of the area that a MapFrame is drawn into
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Viewport {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Default for Viewport {
+    fn default() -> Self {
+        Self {
+            width: DEFAULT_SCREEN_WIDTH,
+            height: DEFAULT_SCREEN_HEIGHT,
+        }
+    }
+}
+
+/// A point on the plane that the map is projected onto. The units are degrees, but only for the
+/// equirectangular projection are they the same as longitude and latitude.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PlaneCoord {
+    pub x: Degree,
+    pub y: Degree,
+}
+
+/// A rectangle view onto the map. The corners are on the plane of `projection`, so a frame is
+/// always a rectangle on the screen, even where the lines of longitude and latitude aren't
+/// straight.
+#[derive(Clone, Debug)]
 pub struct MapFrame {
-    pub upper_left: MapCoord,
-    pub lower_right: MapCoord,
+    pub upper_left: PlaneCoord,
+    pub lower_right: PlaneCoord,
+
+    /// The size of the area this frame is drawn into
+    pub viewport: Viewport,
+
+    projection: Rc<dyn Projection>,
+
+    /// Where the center of Japan is projected to, which is the corner of the (0, 0) tile
+    origin: PlaneCoord,
 }
 
 impl MapFrame {
+    /// A frame between two corners given as longitude and latitude, in the equirectangular
+    /// projection
+    pub fn new(upper_left: MapCoord, lower_right: MapCoord, viewport: Viewport) -> Self {
+        let projection = Equirectangular;
+        Self {
+            upper_left: projection.project(upper_left),
+            lower_right: projection.project(lower_right),
+            viewport,
+            origin: projection.project(JAPAN_CENTER),
+            projection: Rc::new(projection),
+        }
+    }
+
+    /// The smallest frame that shows everything between the two corners, with a little room
+    /// around the edges. The map is stretched the same way as in the default frame, so that
+    /// places away from Japan keep a familiar shape.
+    pub fn around(upper_left: MapCoord, lower_right: MapCoord, viewport: Viewport) -> Self {
+        let aspect = Equirectangular.pixel_aspect();
+
+        // Leave a margin, and don't zoom in further than MIN_ZOOM allows, so that a single
+        // station still gets a usable frame
+        let width = (lower_right.long - upper_left.long).0 * 1.1;
+        let height = (upper_left.lat - lower_right.lat).0 * 1.1;
+        let degrees_per_pixel_y = (height / viewport.height as f32)
+            .max(width / (viewport.width as f32 * aspect))
+            .max(MIN_ZOOM.0 / (DEFAULT_SCREEN_WIDTH as f32 * aspect));
+        let degrees_per_pixel_x = degrees_per_pixel_y * aspect;
+        let half_width = Degree(degrees_per_pixel_x * viewport.width as f32 / 2.0);
+        let half_height = Degree(degrees_per_pixel_y * viewport.height as f32 / 2.0);
+
+        let center = MapCoord {
+            long: Degree((upper_left.long.0 + lower_right.long.0) / 2.0),
+            lat: Degree((upper_left.lat.0 + lower_right.lat.0) / 2.0),
+        };
+
+        Self::new(
+            MapCoord {
+                long: center.long - half_width,
+                lat: center.lat + half_height,
+            },
+            MapCoord {
+                long: center.long + half_width,
+                lat: center.lat - half_height,
+            },
+            viewport,
+        )
+    }
+
+    pub fn projection(&self) -> &dyn Projection {
+        self.projection.as_ref()
+    }
+
+    /// The same view in another projection: centered on the same place, and zoomed so that the
+    /// map is about the same height there
+    pub fn with_projection(&self, projection: Rc<dyn Projection>) -> Self {
+        let center = self.center();
+
+        // How much taller a small stretch of latitude around the center is in the new projection
+        let step = Degree(0.01);
+        let north = MapCoord {
+            long: center.long,
+            lat: center.lat + step,
+        };
+        let south = MapCoord {
+            long: center.long,
+            lat: center.lat - step,
+        };
+        let scale = (projection.project(north).y - projection.project(south).y).0
+            / (self.projection.project(north).y - self.projection.project(south).y).0;
+
+        // A frame that was stretched away from the usual pixel shape stays stretched the same way
+        let (degrees_per_pixel_x, degrees_per_pixel_y) = self.get_degrees_per_pixel();
+        let stretch =
+            (degrees_per_pixel_x.0 / degrees_per_pixel_y.0) / self.projection.pixel_aspect();
+
+        let degrees_per_pixel_y = Degree(degrees_per_pixel_y.0 * scale);
+        let degrees_per_pixel_x = degrees_per_pixel_y * Degree(projection.pixel_aspect() * stretch);
+
+        let frame = Self {
+            origin: projection.project(JAPAN_CENTER),
+            projection,
+            ..self.clone()
+        };
+        frame.around_point(
+            frame.project(center),
+            degrees_per_pixel_x,
+            degrees_per_pixel_y,
+        )
+    }
+
+    /// The same view moved to have `center` in the middle, and zoomed so that a viewport of the
+    /// default size would be `height` degrees tall there
+    pub fn centered_on(&self, center: MapCoord, height: Degree) -> Self {
+        let (degrees_per_pixel_x, degrees_per_pixel_y) = self.get_degrees_per_pixel();
+        let new_degrees_per_pixel_y = height / Degree(DEFAULT_SCREEN_HEIGHT as f32);
+        let new_degrees_per_pixel_x =
+            new_degrees_per_pixel_y * (degrees_per_pixel_x / degrees_per_pixel_y);
+
+        self.around_point(
+            self.project(center),
+            new_degrees_per_pixel_x,
+            new_degrees_per_pixel_y,
+        )
+    }
+
+    /// The view `t` of the way from this one to `other`, which should have the same projection
+    /// and viewport. The size changes by the same factor at every step, so zooming in from far
+    /// away doesn't all happen at the start, and the center moves along with the size, so that
+    /// zooming looks like it's centered on a single point of the map.
+    pub fn interpolate(&self, other: &MapFrame, t: f32) -> Self {
+        let center = self.plane_center();
+        let other_center = other.plane_center();
+        let (degrees_per_pixel_x, degrees_per_pixel_y) = self.get_degrees_per_pixel();
+        let (other_degrees_per_pixel_x, other_degrees_per_pixel_y) = other.get_degrees_per_pixel();
+        let between = |a: Degree, b: Degree| Degree(a.0.powf(1.0 - t) * b.0.powf(t));
+        let new_degrees_per_pixel_x = between(degrees_per_pixel_x, other_degrees_per_pixel_x);
+        let new_degrees_per_pixel_y = between(degrees_per_pixel_y, other_degrees_per_pixel_y);
+
+        // Without a change in size, move at an even pace instead
+        let size_change = (other_degrees_per_pixel_y - degrees_per_pixel_y).0;
+        let progress = if size_change.abs() > degrees_per_pixel_y.0 * 0.01 {
+            (new_degrees_per_pixel_y - degrees_per_pixel_y).0 / size_change
+        } else {
+            t
+        };
+
+        self.around_point(
+            PlaneCoord {
+                x: center.x + (other_center.x - center.x) * Degree(progress),
+                y: center.y + (other_center.y - center.y) * Degree(progress),
+            },
+            new_degrees_per_pixel_x,
+            new_degrees_per_pixel_y,
+        )
+    }
+
+    /// A frame of the same viewport and projection with `center` in the middle, at the given zoom
+    fn around_point(
+        &self,
+        center: PlaneCoord,
+        degrees_per_pixel_x: Degree,
+        degrees_per_pixel_y: Degree,
+    ) -> Self {
+        let half_width = degrees_per_pixel_x * Degree(self.viewport.width as f32 / 2.0);
+        let half_height = degrees_per_pixel_y * Degree(self.viewport.height as f32 / 2.0);
+
+        Self {
+            upper_left: PlaneCoord {
+                x: center.x - half_width,
+                y: center.y + half_height,
+            },
+            lower_right: PlaneCoord {
+                x: center.x + half_width,
+                y: center.y - half_height,
+            },
+            ..self.clone()
+        }
+    }
+
+    /// The point on the map in the middle of the view
+    pub fn center(&self) -> MapCoord {
+        self.projection.unproject(self.plane_center())
+    }
+
+    fn plane_center(&self) -> PlaneCoord {
+        PlaneCoord {
+            x: Degree((self.upper_left.x.0 + self.lower_right.x.0) / 2.0),
+            y: Degree((self.upper_left.y.0 + self.lower_right.y.0) / 2.0),
+        }
+    }
+
+    /// Where on this frame's plane a point on the map is
+    pub fn project(&self, coord: MapCoord) -> PlaneCoord {
+        self.projection.project(coord)
+    }
+
     pub fn width(&self) -> Degree {
-        self.lower_right.long - self.upper_left.long
+        self.lower_right.x - self.upper_left.x
     }
 
     pub fn height(&self) -> Degree {
-        self.upper_left.lat - self.lower_right.lat
+        self.upper_left.y - self.lower_right.y
     }
 
     /// Get the tile that contains the given map coordinate
     pub fn get_tile(&self, coord: MapCoord) -> Tile {
-        let degrees_from_center_x = coord.long - JAPAN_CENTER_LONG;
-        let degrees_from_center_y = JAPAN_CENTER_LAT - coord.lat;
+        self.plane_tile(self.project(coord))
+    }
 
-        // The number of degrees per tile depends on how far we're zoomed-in,
-        // i.e. the dimensions of the current MapFrame
-        let degrees_per_tile_x = self.width() / NUMBER_OF_TILES_X.into();
-        let degrees_per_tile_y = self.height() / NUMBER_OF_TILES_Y.into();
+    /// Get the tile that contains the given point on this frame's plane
+    pub fn plane_tile(&self, point: PlaneCoord) -> Tile {
+        let (tiles_from_center_x, tiles_from_center_y) = self.tiles_from_center(point);
 
         // There's no bounds-checking on panning, meaning that if you pan really far away from the
         // tile center (middle of Japan), it's possible that these offets could saturate at the
         // max/min values for i32...but everything will be offscreen anyway, so it shouldn't
         // matter.
-        let tile_offset_left: i32 = (degrees_from_center_x / degrees_per_tile_x).0 as i32;
-        let tile_offset_top: i32 = (degrees_from_center_y / degrees_per_tile_y).0 as i32;
+        let tile_offset_left: i32 = tiles_from_center_x as i32;
+        let tile_offset_top: i32 = tiles_from_center_y as i32;
 
         Tile {
             x: TilePos(tile_offset_left),
@@ -88,58 +339,220 @@ impl MapFrame {
         }
     }
 
+    /// Where the given point on this frame's plane is on the tile grid, measured so that the
+    /// center of each tile is at its whole-number position. Rounding this gives the same tile as
+    /// `plane_tile`, except right on the edge between two tiles.
+    pub fn plane_tile_position(&self, point: PlaneCoord) -> (f32, f32) {
+        let (tiles_from_center_x, tiles_from_center_y) = self.tiles_from_center(point);
+
+        // Tiles are found by rounding toward zero, which makes the tile at 0 twice as wide as the
+        // others, so points on either side of it are moved half a tile toward it
+        let centered = |tiles: f32| tiles - 0.5 * tiles.signum();
+        (centered(tiles_from_center_x), centered(tiles_from_center_y))
+    }
+
+    /// How many tiles right and down the given point on this frame's plane is from the tile
+    /// grid's origin
+    fn tiles_from_center(&self, point: PlaneCoord) -> (f32, f32) {
+        let degrees_from_center_x = point.x - self.origin.x;
+        let degrees_from_center_y = self.origin.y - point.y;
+
+        // The number of degrees per tile depends on how far we're zoomed-in,
+        // i.e. the dimensions of the current MapFrame
+        let degrees_per_tile_x = self.width() / Degree(self.number_of_tiles_x() as f32);
+        let degrees_per_tile_y = self.height() / Degree(self.number_of_tiles_y() as f32);
+
+        (
+            (degrees_from_center_x / degrees_per_tile_x).0,
+            (degrees_from_center_y / degrees_per_tile_y).0,
+        )
+    }
+
+    /// How many tiles fit across the viewport
+    pub fn number_of_tiles_x(&self) -> u32 {
+        self.viewport.width / TILE_SIZE
+    }
+
+    /// How many tiles fit down the viewport
+    pub fn number_of_tiles_y(&self) -> u32 {
+        self.viewport.height / TILE_SIZE
+    }
+
+    /// The tile column of each pixel column in the viewport, from left to right
+    pub fn tile_columns(&self) -> Vec<TilePos> {
+        (0..self.viewport.width as i32)
+            .map(|x| self.plane_tile(self.get_plane_coord(x, 0)).x)
+            .collect()
+    }
+
+    /// The tile row of each pixel row in the viewport, from top to bottom
+    pub fn tile_rows(&self) -> Vec<TilePos> {
+        (0..self.viewport.height as i32)
+            .map(|y| self.plane_tile(self.get_plane_coord(0, y)).y)
+            .collect()
+    }
+
     /// Get all visible tiles for this MapFrame
     pub fn visible_tiles(&self) -> TileIterator {
-        let upper_left = self.get_tile(self.upper_left);
-        let lower_right = self.get_tile(self.lower_right);
+        let upper_left = self.plane_tile(self.upper_left);
+        let lower_right = self.plane_tile(self.lower_right);
 
         TileIterator::new(upper_left, lower_right)
     }
 
     /// Get how many map degrees (long and lat) a single pixel in this frame currently represents
     pub fn get_degrees_per_pixel(&self) -> (Degree, Degree) {
-        let degrees_per_pixel_x = self.width() / SCREEN_WIDTH.into();
-        let degrees_per_pixel_y = self.height() / SCREEN_HEIGHT.into();
+        let degrees_per_pixel_x = self.width() / Degree(self.viewport.width as f32);
+        let degrees_per_pixel_y = self.height() / Degree(self.viewport.height as f32);
 
         (degrees_per_pixel_x, degrees_per_pixel_y)
     }
 
+    /// How many degrees of longitude and latitude a viewport of the default size would cover at
+    /// the current zoom level. The zoom-dependent sizes of stations, tracks and fonts were tuned
+    /// for the default size, so they're based on these rather than on `width()` and `height()`,
+    /// which keeps them looking the same at any viewport size.
+    pub fn reference_size(&self) -> (Degree, Degree) {
+        let (degrees_per_pixel_x, degrees_per_pixel_y) = self.get_degrees_per_pixel();
+
+        (
+            degrees_per_pixel_x * Degree(DEFAULT_SCREEN_WIDTH as f32),
+            degrees_per_pixel_y * Degree(DEFAULT_SCREEN_HEIGHT as f32),
+        )
+    }
+
+    /// The latitude half of `reference_size`
+    pub fn reference_height(&self) -> Degree {
+        self.reference_size().1
+    }
+
+    /// Change the viewport size while keeping the upper-left corner and the zoom level, so that a
+    /// bigger viewport shows more of the map rather than the same area stretched.
+    pub fn resize(&mut self, viewport: Viewport) {
+        let (degrees_per_pixel_x, degrees_per_pixel_y) = self.get_degrees_per_pixel();
+
+        self.lower_right.x =
+            self.upper_left.x + degrees_per_pixel_x * Degree(viewport.width as f32);
+        self.lower_right.y =
+            self.upper_left.y - degrees_per_pixel_y * Degree(viewport.height as f32);
+        self.viewport = viewport;
+    }
+
+    /// Change the viewport size while keeping the center and the zoom level, so that the view
+    /// widens or narrows around the same place instead of being stretched
+    pub fn resize_around_center(&mut self, viewport: Viewport) {
+        let (degrees_per_pixel_x, degrees_per_pixel_y) = self.get_degrees_per_pixel();
+        let center = self.plane_center();
+
+        self.viewport = viewport;
+        *self = self.around_point(center, degrees_per_pixel_x, degrees_per_pixel_y);
+    }
+
     /// Translate a (visible) screen pixel position to a map coordinate
-    pub fn get_map_coord(&self, pixel_x: i16, pixel_y: i16) -> MapCoord {
+    pub fn get_map_coord(&self, pixel_x: i32, pixel_y: i32) -> MapCoord {
+        self.projection
+            .unproject(self.get_plane_coord(pixel_x, pixel_y))
+    }
+
+    /// Translate a screen pixel position to a point on this frame's plane
+    pub fn get_plane_coord(&self, pixel_x: i32, pixel_y: i32) -> PlaneCoord {
         let (degrees_per_pixel_x, degrees_per_pixel_y) = self.get_degrees_per_pixel();
 
         // Get offsets from the top-left corner
-        let map_x: Degree = self.upper_left.long + degrees_per_pixel_x * Degree(pixel_x as f32);
-        let map_y: Degree = self.upper_left.lat - degrees_per_pixel_y * Degree(pixel_y as f32);
+        let map_x: Degree = self.upper_left.x + degrees_per_pixel_x * Degree(pixel_x as f32);
+        let map_y: Degree = self.upper_left.y - degrees_per_pixel_y * Degree(pixel_y as f32);
 
-        MapCoord {
-            long: map_x,
-            lat: map_y,
-        }
+        PlaneCoord { x: map_x, y: map_y }
     }
 
-    /// Check whether the given MapCoord is visible in this MapFrame
-    pub fn is_visible(&self, coord: MapCoord) -> bool {
-        // At high zoom levels, add a "margin" to the bounds we're checking, so that we can draw
-        // tracks and station names that originate from a station that's actually off-screen, to
-        // avoid pop-in.
-        let margin: Degree = if self.height().0 < 0.05 {
+    /// The upper-left and lower-right corners of the area that counts as visible, on this frame's
+    /// plane. At high zoom levels, this adds a "margin" around the frame, so that we can draw
+    /// tracks and station names that originate from a station that's actually off-screen, to
+    /// avoid pop-in.
+    pub fn visible_bounds(&self) -> (PlaneCoord, PlaneCoord) {
+        let (reference_width, reference_height) = self.reference_size();
+        let margin: Degree = if reference_height.0 < 0.05 {
             // Rough formula that seems to work well; start at margin of 10% and increase as we
             // zoom in more
-            (0.10 + (0.05 - self.height().0)).into()
+            (0.10 + (0.05 - reference_height.0)).into()
         } else {
             0.0.into()
         };
 
-        coord.long >= (self.upper_left.long - self.width() * margin)
-            && coord.long <= (self.lower_right.long + self.width() * margin)
-            && coord.lat <= (self.upper_left.lat + self.height() * margin)
-            && coord.lat >= (self.lower_right.lat - self.height() * margin)
+        (
+            PlaneCoord {
+                x: self.upper_left.x - reference_width * margin,
+                y: self.upper_left.y + reference_height * margin,
+            },
+            PlaneCoord {
+                x: self.lower_right.x + reference_width * margin,
+                y: self.lower_right.y - reference_height * margin,
+            },
+        )
+    }
+
+    /// The upper-left and lower-right corners, in longitude and latitude, of a box around
+    /// everything in `visible_bounds`. In projections other than the equirectangular one the
+    /// edges of the screen are curves on the map, so this is found by following them.
+    pub fn visible_area(&self) -> (MapCoord, MapCoord) {
+        const STEPS: i32 = 8;
+
+        let (upper_left, lower_right) = self.visible_bounds();
+        let mut min = self.projection.unproject(upper_left);
+        let mut max = min;
+        for step in 0..=STEPS {
+            let t = step as f32 / STEPS as f32;
+            let x = upper_left.x + (lower_right.x - upper_left.x) * Degree(t);
+            let y = upper_left.y + (lower_right.y - upper_left.y) * Degree(t);
+
+            for point in [
+                PlaneCoord { x, y: upper_left.y },
+                PlaneCoord {
+                    x,
+                    y: lower_right.y,
+                },
+                PlaneCoord { x: upper_left.x, y },
+                PlaneCoord {
+                    x: lower_right.x,
+                    y,
+                },
+            ]
+            .iter()
+            {
+                let coord = self.projection.unproject(*point);
+                min.long = Degree(min.long.0.min(coord.long.0));
+                min.lat = Degree(min.lat.0.min(coord.lat.0));
+                max.long = Degree(max.long.0.max(coord.long.0));
+                max.lat = Degree(max.lat.0.max(coord.lat.0));
+            }
+        }
+
+        (
+            MapCoord {
+                long: min.long,
+                lat: max.lat,
+            },
+            MapCoord {
+                long: max.long,
+                lat: min.lat,
+            },
+        )
+    }
+
+    /// Check whether the given MapCoord is visible in this MapFrame
+    pub fn is_visible(&self, coord: MapCoord) -> bool {
+        let (upper_left, lower_right) = self.visible_bounds();
+        let point = self.project(coord);
+
+        point.x >= upper_left.x
+            && point.x <= lower_right.x
+            && point.y <= upper_left.y
+            && point.y >= lower_right.y
     }
 
     /// How many tiles (on one side) to use to draw a station
     pub fn station_width(&self) -> i32 {
-        let height = self.height().0;
+        let height = self.reference_height().0;
 
         if height < 0.02 {
             5
@@ -156,7 +569,7 @@ impl MapFrame {
 
     /// How many tiles (on one side) to use to draw a track piece
     pub fn track_width(&self) -> i32 {
-        let height = self.height().0;
+        let height = self.reference_height().0;
 
         if height < 0.06 {
             2
@@ -168,7 +581,7 @@ impl MapFrame {
     /// How bright/emphasized (0-9) fonts should be at the current zoom level
     pub fn font_level(&self) -> usize {
         // Note: fonts don't even appear until height < 0.5
-        let height = self.height().0;
+        let height = self.reference_height().0;
 
         if height < 0.015 {
             9
@@ -197,16 +610,17 @@ impl MapFrame {
 impl Default for MapFrame {
     /// MapFrame that fits most of Japan
     fn default() -> Self {
-        Self {
-            upper_left: MapCoord {
+        Self::new(
+            MapCoord {
                 long: JAPAN_LEFT,
                 lat: JAPAN_TOP,
             },
-            lower_right: MapCoord {
+            MapCoord {
                 long: JAPAN_RIGHT,
                 lat: JAPAN_BOTTOM,
             },
-        }
+            Viewport::default(),
+        )
     }
 }
 
@@ -216,14 +630,8 @@ impl Default for MapFrame {
 /// of latitude, then we want to adjust it to show 10.1 degrees of longitude and
 /// 7.7 degrees of latitude
 pub fn zoom_ratio(scroll_diff: f32) -> f32 {
-    let mut clamped = scroll_diff;
-
     // scroll_diff seems to vary between 0.1 and around 30 for very forceful scrolling
-    if clamped > SCROLL_DIFF_MAX {
-        clamped = SCROLL_DIFF_MAX;
-    } else if clamped < -1.0 * SCROLL_DIFF_MAX {
-        clamped = -1.0 * SCROLL_DIFF_MAX;
-    }
+    let clamped = scroll_diff.clamp(-SCROLL_DIFF_MAX, SCROLL_DIFF_MAX);
 
     let sign = if clamped.is_sign_positive() {
         1.0
@@ -245,3 +653,48 @@ pub fn zoom_ratio(scroll_diff: f32) -> f32 {
 
     1.0 - (offset * sign)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distances_are_measured_on_the_ground() {
+        let tokyo = MapCoord {
+            long: Degree(139.7671),
+            lat: Degree(35.6812),
+        };
+        let shin_osaka = MapCoord {
+            long: Degree(135.5002),
+            lat: Degree(34.7334),
+        };
+
+        // About 403 km as the crow flies
+        let distance = tokyo.meters_to(&shin_osaka).kilometers();
+        assert!((distance - 403.0).abs() < 4.0, "{} km", distance);
+        assert_eq!(
+            shin_osaka.meters_to(&tokyo).0,
+            tokyo.meters_to(&shin_osaka).0
+        );
+        assert_eq!(tokyo.meters_to(&tokyo).0, 0.0);
+    }
+
+    #[test]
+    fn resizing_around_center_keeps_center_and_zoom() {
+        let mut frame = MapFrame::default();
+        let center = frame.plane_center();
+        let (degrees_per_pixel_x, degrees_per_pixel_y) = frame.get_degrees_per_pixel();
+
+        frame.resize_around_center(Viewport {
+            width: 300,
+            height: 100,
+        });
+
+        let new_center = frame.plane_center();
+        let (new_degrees_per_pixel_x, new_degrees_per_pixel_y) = frame.get_degrees_per_pixel();
+        assert!((new_center.x - center.x).0.abs() < 1e-4);
+        assert!((new_center.y - center.y).0.abs() < 1e-4);
+        assert!((new_degrees_per_pixel_x - degrees_per_pixel_x).0.abs() < 1e-6);
+        assert!((new_degrees_per_pixel_y - degrees_per_pixel_y).0.abs() < 1e-6);
+    }
+}
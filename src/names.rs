@@ -0,0 +1,354 @@
+//! Station names for people who don't read Japanese: writing kana readings in romaji, and which
+//! names to show
+
+use std::str::FromStr;
+
+/// Which names stations are shown by, on the map and in the panels
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Language {
+    /// The station's own name, as it's written in Japanese
+    #[default]
+    Japanese,
+
+    /// The name in romaji, for stations that have one, and in Japanese otherwise
+    Romaji,
+
+    /// The Japanese name followed by the romaji one
+    Both,
+}
+
+impl Language {
+    /// The language after this one, cycling back to the first
+    pub fn next(self) -> Self {
+        match self {
+            Language::Japanese => Language::Romaji,
+            Language::Romaji => Language::Both,
+            Language::Both => Language::Japanese,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Language::Japanese => "japanese",
+            Language::Romaji => "romaji",
+            Language::Both => "both",
+        }
+    }
+}
+
+impl FromStr for Language {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "japanese" => Ok(Language::Japanese),
+            "romaji" => Ok(Language::Romaji),
+            "both" => Ok(Language::Both),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Write a reading in kana (hiragana or katakana) in Hepburn romaji, the way station signs do:
+/// long vowels are written once (とうきょう is Tokyo, not Toukyou), and ん is an m before b, m and
+/// p (しんばし is Shimbashi). Half-width katakana is read like full-width. Anything that isn't
+/// kana is kept as it is.
+pub fn kana_to_romaji(kana: &str) -> String {
+    let mut romaji = String::new();
+
+    // Whether the last kana was っ, which doubles the next consonant
+    let mut doubled = false;
+
+    // Whether the last kana was ん
+    let mut after_n = false;
+
+    for c in full_width(kana).chars().map(to_hiragana) {
+        match c {
+            'っ' => {
+                doubled = true;
+                continue;
+            }
+            // The long vowel mark only lengthens the vowel before it
+            'ー' => continue,
+            // Small や, ゆ and よ replace the i of the kana before them: き and ゃ make kya, し
+            // and ゃ make sha
+            'ゃ' | 'ゅ' | 'ょ' => {
+                let vowel = match c {
+                    'ゃ' => 'a',
+                    'ゅ' => 'u',
+                    _ => 'o',
+                };
+                // て and で lose their e the same way: で and ゅ make dyu
+                let mut previous = romaji.chars().rev();
+                if let (Some('i' | 'e'), Some(consonant)) = (previous.next(), previous.next()) {
+                    if consonant.is_ascii_alphabetic() && !is_vowel(consonant) {
+                        romaji.pop();
+                    }
+                }
+                if !(romaji.ends_with("sh") || romaji.ends_with("ch") || romaji.ends_with('j')) {
+                    romaji.push('y');
+                }
+                romaji.push(vowel);
+                after_n = false;
+                continue;
+            }
+            // Small vowels replace the vowel of the kana before them: ふ and ぁ make fa, and
+            // on their own after う they make wa, wi, we and wo
+            'ぁ' | 'ぃ' | 'ぅ' | 'ぇ' | 'ぉ' => {
+                let vowel = match c {
+                    'ぁ' => 'a',
+                    'ぃ' => 'i',
+                    'ぅ' => 'u',
+                    'ぇ' => 'e',
+                    _ => 'o',
+                };
+                if romaji.ends_with(is_vowel) {
+                    romaji.pop();
+                    if !romaji.ends_with(|c: char| c.is_ascii_alphabetic() && !is_vowel(c)) {
+                        romaji.push('w');
+                    }
+                }
+                romaji.push(vowel);
+                after_n = false;
+                continue;
+            }
+            _ => {}
+        }
+
+        let syllable = match syllable(c) {
+            Some(syllable) => syllable,
+            None => {
+                romaji.push(c);
+                doubled = false;
+                after_n = false;
+                continue;
+            }
+        };
+
+        // Long o and u are written as a single vowel
+        let long_vowel = match syllable {
+            "u" => romaji.ends_with('o') || romaji.ends_with('u'),
+            "o" => romaji.ends_with('o'),
+            _ => false,
+        };
+        if long_vowel && !doubled && !after_n {
+            continue;
+        }
+
+        if after_n {
+            if syllable.starts_with(is_vowel) || syllable.starts_with('y') {
+                romaji.push('\'');
+            } else if syllable.starts_with(['b', 'm', 'p']) {
+                romaji.pop();
+                romaji.push('m');
+            }
+        }
+        if doubled {
+            if syllable.starts_with("ch") {
+                romaji.push('t');
+            } else if let Some(consonant) = syllable.chars().next().filter(|c| !is_vowel(*c)) {
+                romaji.push(consonant);
+            }
+        }
+
+        romaji.push_str(syllable);
+        doubled = false;
+        after_n = c == 'ん';
+    }
+
+    // Names start with a capital letter
+    let mut chars = romaji.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => romaji,
+    }
+}
+
+fn is_vowel(c: char) -> bool {
+    matches!(c, 'a' | 'i' | 'u' | 'e' | 'o')
+}
+
+/// The full-width katakana for ｦ to ﾝ, in the same order
+const HALF_WIDTH_KATAKANA: &str =
+    "ヲァィゥェォャュョッーアイウエオカキクケコサシスセソタチツテトナニヌネノハヒフヘホマミムメモヤユヨラリルレロワン";
+
+/// Half-width katakana written as full-width, with the separate voiced sound marks ﾞ and ﾟ
+/// joined to the kana before them
+fn full_width(kana: &str) -> String {
+    let mut full = String::with_capacity(kana.len());
+    for c in kana.chars() {
+        match c {
+            'ｦ'..='ﾝ' => full.extend(
+                HALF_WIDTH_KATAKANA
+                    .chars()
+                    .nth((c as u32 - 'ｦ' as u32) as usize),
+            ),
+            'ﾞ' | 'ﾟ' => {
+                let voiced = full
+                    .chars()
+                    .last()
+                    .and_then(|previous| match (previous, c) {
+                        ('ウ', 'ﾞ') => Some('ヴ'),
+                        // The voiced kana come right after the unvoiced ones, and the half-voiced
+                        // ones (パ, ピ, ...) right after those
+                        (_, 'ﾞ')
+                            if "カキクケコサシスセソタチツテトハヒフヘホ".contains(previous) =>
+                        {
+                            std::char::from_u32(previous as u32 + 1)
+                        }
+                        (_, 'ﾟ') if "ハヒフヘホ".contains(previous) => {
+                            std::char::from_u32(previous as u32 + 2)
+                        }
+                        _ => None,
+                    });
+                match voiced {
+                    Some(voiced) => {
+                        full.pop();
+                        full.push(voiced);
+                    }
+                    None => full.push(c),
+                }
+            }
+            _ => full.push(c),
+        }
+    }
+    full
+}
+
+/// Katakana written as the hiragana for the same sound
+fn to_hiragana(c: char) -> char {
+    match c {
+        // ァ to ヶ line up with ぁ to ゖ
+        'ァ'..='ヶ' => std::char::from_u32(c as u32 - 0x60).unwrap_or(c),
+        _ => c,
+    }
+}
+
+/// The romaji for a single full-size hiragana
+fn syllable(c: char) -> Option<&'static str> {
+    let syllable = match c {
+        'あ' => "a",
+        'い' => "i",
+        'う' => "u",
+        'え' => "e",
+        'お' => "o",
+        'か' | 'ゕ' => "ka",
+        'き' => "ki",
+        'く' => "ku",
+        'け' | 'ゖ' => "ke",
+        'こ' => "ko",
+        'が' => "ga",
+        'ぎ' => "gi",
+        'ぐ' => "gu",
+        'げ' => "ge",
+        'ご' => "go",
+        'さ' => "sa",
+        'し' => "shi",
+        'す' => "su",
+        'せ' => "se",
+        'そ' => "so",
+        'ざ' => "za",
+        'じ' | 'ぢ' => "ji",
+        'ず' | 'づ' => "zu",
+        'ぜ' => "ze",
+        'ぞ' => "zo",
+        'た' => "ta",
+        'ち' => "chi",
+        'つ' => "tsu",
+        'て' => "te",
+        'と' => "to",
+        'だ' => "da",
+        'で' => "de",
+        'ど' => "do",
+        'な' => "na",
+        'に' => "ni",
+        'ぬ' => "nu",
+        'ね' => "ne",
+        'の' => "no",
+        'は' => "ha",
+        'ひ' => "hi",
+        'ふ' => "fu",
+        'へ' => "he",
+        'ほ' => "ho",
+        'ば' => "ba",
+        'び' => "bi",
+        'ぶ' => "bu",
+        'べ' => "be",
+        'ぼ' => "bo",
+        'ぱ' => "pa",
+        'ぴ' => "pi",
+        'ぷ' => "pu",
+        'ぺ' => "pe",
+        'ぽ' => "po",
+        'ま' => "ma",
+        'み' => "mi",
+        'む' => "mu",
+        'め' => "me",
+        'も' => "mo",
+        'や' => "ya",
+        'ゆ' => "yu",
+        'よ' => "yo",
+        'ら' => "ra",
+        'り' => "ri",
+        'る' => "ru",
+        'れ' => "re",
+        'ろ' => "ro",
+        'わ' | 'ゎ' => "wa",
+        'ゐ' => "i",
+        'ゑ' => "e",
+        'を' => "o",
+        'ん' => "n",
+        'ゔ' => "vu",
+        _ => return None,
+    };
+    Some(syllable)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kana_is_written_in_romaji() {
+        for (kana, romaji) in [
+            ("とうきょう", "Tokyo"),
+            ("しんばし", "Shimbashi"),
+            ("しんじゅく", "Shinjuku"),
+            ("おおさか", "Osaka"),
+            ("はっちょうぼり", "Hatchobori"),
+            ("っち", "Tchi"),
+            ("じゃ", "Ja"),
+            ("ちゃ", "Cha"),
+            ("ふぁ", "Fa"),
+            ("でゅ", "Dyu"),
+            ("てぃ", "Ti"),
+            ("ディズニー", "Dizuni"),
+            ("きんえい", "Kin'ei"),
+            ("ケ", "Ke"),
+            ("ヶ", "Ke"),
+        ] {
+            assert_eq!(kana_to_romaji(kana), romaji, "{}", kana);
+        }
+    }
+
+    #[test]
+    fn half_width_katakana_is_read_like_full_width() {
+        for (kana, romaji) in [
+            ("ﾄｳｷｮｳ", "Tokyo"),
+            ("ｼﾝﾊﾞｼ", "Shimbashi"),
+            ("ｼﾝｼﾞｭｸ", "Shinjuku"),
+            ("ﾊｯﾁｮｳﾎﾞﾘ", "Hatchobori"),
+            ("ﾆｯﾎﾟﾘ", "Nippori"),
+            ("ｳﾞ", "Vu"),
+        ] {
+            assert_eq!(kana_to_romaji(kana), romaji, "{}", kana);
+        }
+    }
+
+    #[test]
+    fn anything_but_kana_is_kept() {
+        assert_eq!(kana_to_romaji("とうきょう2"), "Tokyo2");
+        assert_eq!(kana_to_romaji("ﾞ"), "ﾞ");
+        assert_eq!(kana_to_romaji(""), "");
+    }
+}
@@ -0,0 +1,170 @@
+//! Boxes of text drawn over the map, like the station details and the search box
+
+use crate::{fonts::FontManager, map::Viewport};
+
+/// Height of the text, in pixels
+const TEXT_HEIGHT: f32 = 9.0;
+
+/// Space between the rows of text, in pixels
+const ROW_SPACING: i32 = 1;
+
+/// Space between a panel and the edge of the screen, and between its border and its text, in
+/// pixels
+const MARGIN: i32 = 2;
+
+/// Put at the end of rows that were too long to fit on the screen
+const ELLIPSIS: &str = "…";
+
+const PANEL_COLOR: [u8; 3] = [0x22, 0x20, 0x2A];
+const BORDER_COLOR: [u8; 3] = [0xC4, 0x9D, 0xCF];
+
+/// For the first row of a panel, or the row that's picked out from the others
+pub const TITLE_COLOR: [u8; 3] = [0xF8, 0xFF, 0x7A];
+pub const TEXT_COLOR: [u8; 3] = [0xE0, 0xDD, 0xE8];
+
+/// Which corner of the screen a panel is drawn in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Corner {
+    TopLeft,
+    BottomLeft,
+}
+
+/// Draw a panel with the given rows of text and their colors into a corner of `buffer`, an RGBA
+/// frame buffer the size of `viewport`. Rows that are too long for the screen are cut short, and
+/// rows that don't fit below the others are left out.
+pub fn draw_panel(
+    buffer: &mut [u8],
+    viewport: Viewport,
+    font_manager: &FontManager,
+    rows: &[(String, [u8; 3])],
+    corner: Corner,
+) {
+    let row_height = TEXT_HEIGHT.ceil() as i32 + ROW_SPACING;
+    let inset = MARGIN + 1 + MARGIN;
+    let max_width = viewport.width as i32 - 2 * inset;
+    let max_rows = ((viewport.height as i32 - 2 * inset + ROW_SPACING) / row_height).max(0);
+    if max_width <= 0 || max_rows == 0 || rows.is_empty() {
+        return;
+    }
+
+    let rows: Vec<_> = rows
+        .iter()
+        .take(max_rows as usize)
+        .map(|(row, color)| {
+            let (pixels, width) =
+                font_manager.text_pixels(&fit(row, max_width, font_manager), TEXT_HEIGHT);
+            (pixels, width, color)
+        })
+        .collect();
+
+    let text_width = rows.iter().map(|(_, width, _)| *width).max().unwrap_or(0);
+    let text_height = rows.len() as i32 * row_height - ROW_SPACING;
+    let panel_height = text_height + 2 * (inset - MARGIN);
+    let left = MARGIN;
+    let right = inset + text_width + MARGIN;
+    let top = match corner {
+        Corner::TopLeft => MARGIN,
+        Corner::BottomLeft => viewport.height as i32 - MARGIN - panel_height,
+    };
+    let bottom = top + panel_height - 1;
+
+    let mut canvas = Canvas { buffer, viewport };
+    for y in top..=bottom {
+        for x in left..=right {
+            let on_border = x == left || x == right || y == top || y == bottom;
+            let color = if on_border {
+                &BORDER_COLOR
+            } else {
+                &PANEL_COLOR
+            };
+            canvas.set(x, y, color);
+        }
+    }
+
+    for (i, (pixels, _, color)) in rows.iter().enumerate() {
+        let row_top = top + inset - MARGIN + i as i32 * row_height;
+        for (x, y) in pixels {
+            // Glyphs can reach a little past the font's height, but not over the border
+            let (x, y) = (inset + x, row_top + y);
+            if x < right && y < bottom {
+                canvas.set(x, y, color);
+            }
+        }
+    }
+}
+
+/// `text` if it's at most `max_width` pixels wide, or as much of it as fits with an ellipsis on
+/// the end
+fn fit(text: &str, max_width: i32, font_manager: &FontManager) -> String {
+    if font_manager.text_width(text, TEXT_HEIGHT) <= max_width {
+        return text.to_owned();
+    }
+
+    let chars: Vec<char> = text.chars().collect();
+    let shortened =
+        |length: usize| format!("{}{}", chars[..length].iter().collect::<String>(), ELLIPSIS);
+    let fits =
+        |length: usize| font_manager.text_width(&shortened(length), TEXT_HEIGHT) <= max_width;
+
+    // More characters never make the text narrower, so binary search for the first length that
+    // doesn't fit. Everything shorter does.
+    let (mut low, mut high) = (0, chars.len());
+    while low < high {
+        let middle = (low + high) / 2;
+        if fits(middle) {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+
+    match low {
+        0 => String::new(),
+        length => shortened(length - 1),
+    }
+}
+
+/// An RGBA frame buffer, ignoring anything drawn outside of it
+struct Canvas<'a> {
+    buffer: &'a mut [u8],
+    viewport: Viewport,
+}
+
+impl Canvas<'_> {
+    fn set(&mut self, x: i32, y: i32, color: &[u8; 3]) {
+        if x < 0 || y < 0 || x >= self.viewport.width as i32 || y >= self.viewport.height as i32 {
+            return;
+        }
+
+        let i = (y as usize * self.viewport.width as usize + x as usize) * 4;
+        if let Some(pixel) = self.buffer.get_mut(i..i + 4) {
+            pixel.copy_from_slice(&[color[0], color[1], color[2], 0xFF]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_is_cut_to_the_longest_part_that_fits() {
+        let font_manager = FontManager::new().unwrap();
+        let text = "東京メトロ丸ノ内線 池袋 → 荻窪";
+        let width = |text: &str| font_manager.text_width(text, TEXT_HEIGHT);
+
+        assert_eq!(fit(text, width(text), &font_manager), text);
+
+        for max_width in [0, width(ELLIPSIS), 30, 60, width(text) - 1] {
+            let fitted = fit(text, max_width, &font_manager);
+            let kept = fitted.trim_end_matches(ELLIPSIS);
+            assert!(text.starts_with(kept));
+            assert!(width(&fitted) <= max_width);
+
+            // One more character wouldn't have fit
+            let longer: String = text.chars().take(kept.chars().count() + 1).collect();
+            assert!(width(&format!("{}{}", longer, ELLIPSIS)) > max_width);
+        }
+        assert_eq!(fit(text, 0, &font_manager), "");
+    }
+}
@@ -0,0 +1,282 @@
+//! Ways of flattening longitude and latitude onto the screen. Every projection maps onto a plane
+//! measured in degrees, so that zoom levels and the sizes of things on the map mean about the
+//! same in all of them.
+
+use crate::{
+    constants::*,
+    map::{Degree, MapCoord, PlaneCoord},
+};
+use std::{fmt, rc::Rc};
+
+/// Makes a projection given the meridian through the middle of the map
+type MakeProjection = fn(Degree) -> Rc<dyn Projection>;
+
+/// The available projections, in the order that `next_projection` cycles through, by name
+const PROJECTIONS: [(&str, MakeProjection); 3] = [
+    ("equirectangular", |_| Rc::new(Equirectangular)),
+    ("mercator", |_| Rc::new(WebMercator)),
+    ("transverse-mercator", |central_meridian| {
+        Rc::new(TransverseMercator { central_meridian })
+    }),
+];
+
+pub trait Projection: fmt::Debug {
+    /// Short name, as accepted by `projection_by_name`
+    fn name(&self) -> &'static str;
+
+    /// Where on the plane a point on the map ends up
+    fn project(&self, coord: MapCoord) -> PlaneCoord;
+
+    /// The point on the map that ends up at the given point on the plane
+    fn unproject(&self, point: PlaneCoord) -> MapCoord;
+
+    /// How many times wider than tall one pixel is, measured on the plane. Conformal projections
+    /// already keep shapes right, so their pixels are square.
+    fn pixel_aspect(&self) -> f32 {
+        1.0
+    }
+}
+
+/// Longitude and latitude used as they are. Shapes get stretched more the further they are from
+/// the equator, so pixels are made wider than they are tall to make up for it around the middle
+/// of Japan.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Equirectangular;
+
+impl Projection for Equirectangular {
+    fn name(&self) -> &'static str {
+        PROJECTIONS[0].0
+    }
+
+    fn project(&self, coord: MapCoord) -> PlaneCoord {
+        PlaneCoord {
+            x: coord.long,
+            y: coord.lat,
+        }
+    }
+
+    fn unproject(&self, point: PlaneCoord) -> MapCoord {
+        MapCoord {
+            long: point.x,
+            lat: point.y,
+        }
+    }
+
+    fn pixel_aspect(&self) -> f32 {
+        // The same as the default view of Japan, which this projection was the only one for
+        let width = (JAPAN_RIGHT - JAPAN_LEFT).0 / DEFAULT_SCREEN_WIDTH as f32;
+        let height = (JAPAN_TOP - JAPAN_BOTTOM).0 / DEFAULT_SCREEN_HEIGHT as f32;
+        width / height
+    }
+}
+
+/// The projection of most web maps. Conformal, but areas grow quickly towards the poles, so
+/// Hokkaido looks bigger than it is next to Kyushu.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WebMercator;
+
+impl Projection for WebMercator {
+    fn name(&self) -> &'static str {
+        PROJECTIONS[1].0
+    }
+
+    fn project(&self, coord: MapCoord) -> PlaneCoord {
+        let lat = (coord.lat.0 as f64).to_radians();
+        let y = (std::f64::consts::FRAC_PI_4 + lat / 2.0).tan().ln();
+
+        PlaneCoord {
+            x: coord.long,
+            y: Degree(y.to_degrees() as f32),
+        }
+    }
+
+    fn unproject(&self, point: PlaneCoord) -> MapCoord {
+        let y = (point.y.0 as f64).to_radians();
+        let lat = 2.0 * y.exp().atan() - std::f64::consts::FRAC_PI_2;
+
+        MapCoord {
+            long: point.x,
+            lat: Degree(lat.to_degrees() as f32),
+        }
+    }
+}
+
+/// Mercator turned on its side, so that it's true to scale along a meridian instead of the
+/// equator. Japan lies mostly north to south, so running the meridian through its middle keeps
+/// both shapes and sizes close to right all the way from Hokkaido to Okinawa.
+#[derive(Debug, Clone, Copy)]
+pub struct TransverseMercator {
+    pub central_meridian: Degree,
+}
+
+impl Default for TransverseMercator {
+    fn default() -> Self {
+        Self {
+            central_meridian: JAPAN_CENTER_LONG,
+        }
+    }
+}
+
+impl Projection for TransverseMercator {
+    fn name(&self) -> &'static str {
+        PROJECTIONS[2].0
+    }
+
+    fn project(&self, coord: MapCoord) -> PlaneCoord {
+        let lat = (coord.lat.0 as f64).to_radians();
+        let long = ((coord.long - self.central_meridian).0 as f64).to_radians();
+
+        // x is shifted by the central meridian so that it's close to the longitude around there
+        let x = (lat.cos() * long.sin()).atanh();
+        let y = lat.tan().atan2(long.cos());
+
+        PlaneCoord {
+            x: self.central_meridian + Degree(x.to_degrees() as f32),
+            y: Degree(y.to_degrees() as f32),
+        }
+    }
+
+    fn unproject(&self, point: PlaneCoord) -> MapCoord {
+        let x = ((point.x - self.central_meridian).0 as f64).to_radians();
+        let y = (point.y.0 as f64).to_radians();
+
+        let lat = (y.sin() / x.cosh()).asin();
+        let long = x.sinh().atan2(y.cos());
+
+        MapCoord {
+            long: self.central_meridian + Degree(long.to_degrees() as f32),
+            lat: Degree(lat.to_degrees() as f32),
+        }
+    }
+}
+
+/// The meridian through the middle of a map with the given bounds, for transverse Mercator to be
+/// true to scale along. Without any bounds, it's the one through the middle of Japan.
+pub fn central_meridian(bounds: Option<(MapCoord, MapCoord)>) -> Degree {
+    bounds.map_or(JAPAN_CENTER_LONG, |(upper_left, lower_right)| {
+        Degree((upper_left.long.0 + lower_right.long.0) / 2.0)
+    })
+}
+
+/// The names of the available projections, in the order that `next_projection` cycles through
+pub fn projection_names() -> impl Iterator<Item = &'static str> {
+    PROJECTIONS.iter().map(|(name, _)| *name)
+}
+
+/// Look up a projection by its name. Transverse Mercator is centered on `central_meridian`.
+pub fn projection_by_name(name: &str, central_meridian: Degree) -> Option<Rc<dyn Projection>> {
+    let (_, make) = PROJECTIONS.iter().find(|(n, _)| *n == name)?;
+    Some(make(central_meridian))
+}
+
+/// The projection after the given one in `PROJECTIONS`, going back to the first after the
+/// last. Transverse Mercator is centered on `central_meridian`.
+pub fn next_projection(
+    projection: &dyn Projection,
+    central_meridian: Degree,
+) -> Rc<dyn Projection> {
+    let index = PROJECTIONS
+        .iter()
+        .position(|(name, _)| *name == projection.name())
+        .map_or(0, |i| (i + 1) % PROJECTIONS.len());
+
+    (PROJECTIONS[index].1)(central_meridian)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn projections_cycle_in_order() {
+        let names: Vec<&str> = projection_names().collect();
+        let mut projection = projection_by_name(names[0], JAPAN_CENTER_LONG).unwrap();
+        assert_eq!(projection.name(), names[0]);
+        for name in names.iter().skip(1).chain(&names[..1]) {
+            projection = next_projection(projection.as_ref(), JAPAN_CENTER_LONG);
+            assert_eq!(projection.name(), *name);
+        }
+        assert!(projection_by_name("gnomonic", JAPAN_CENTER_LONG).is_none());
+    }
+
+    /// Points all over Japan, from Hokkaido to Okinawa
+    const JAPAN: [(f32, f32); 4] = [
+        (141.35, 43.06),
+        (139.77, 35.68),
+        (130.42, 33.59),
+        (127.68, 26.21),
+    ];
+
+    fn assert_round_trips(projection: &dyn Projection) {
+        for (long, lat) in JAPAN.iter() {
+            let coord = MapCoord {
+                long: Degree(*long),
+                lat: Degree(*lat),
+            };
+            let round_trip = projection.unproject(projection.project(coord));
+            assert!(
+                (round_trip.long - coord.long).0.abs() < 1e-3
+                    && (round_trip.lat - coord.lat).0.abs() < 1e-3,
+                "{} came back from {} as {}",
+                coord,
+                projection.name(),
+                round_trip
+            );
+        }
+    }
+
+    #[test]
+    fn equirectangular_round_trips() {
+        assert_round_trips(&Equirectangular);
+
+        // Longitude and latitude are used as they are
+        let point = Equirectangular.project(MapCoord {
+            long: Degree(139.77),
+            lat: Degree(35.68),
+        });
+        assert_eq!(point.x, Degree(139.77));
+        assert_eq!(point.y, Degree(35.68));
+    }
+
+    #[test]
+    fn web_mercator_round_trips() {
+        assert_round_trips(&WebMercator);
+
+        // Latitudes are spread further apart the further north they are
+        let y = |lat| {
+            WebMercator
+                .project(MapCoord {
+                    long: Degree(139.0),
+                    lat: Degree(lat),
+                })
+                .y
+                .0
+        };
+        assert!(y(0.0).abs() < 1e-6);
+        assert!(y(44.0) - y(43.0) > y(27.0) - y(26.0));
+        assert!(y(35.0) > 35.0);
+    }
+
+    #[test]
+    fn transverse_mercator_is_centered_on_the_given_meridian() {
+        // New York
+        let central_meridian = Degree(-74.0);
+        let projection = projection_by_name("transverse-mercator", central_meridian).unwrap();
+
+        let coord = MapCoord {
+            long: central_meridian,
+            lat: Degree(40.7),
+        };
+        let point = projection.project(coord);
+        assert!((point.x - central_meridian).0.abs() < 1e-4);
+        assert!((point.y - coord.lat).0.abs() < 1e-4);
+
+        let nearby = MapCoord {
+            long: Degree(-73.9),
+            lat: Degree(40.8),
+        };
+        let round_trip = projection.unproject(projection.project(nearby));
+        assert!((round_trip.long - nearby.long).0.abs() < 1e-3);
+        assert!((round_trip.lat - nearby.lat).0.abs() < 1e-3);
+    }
+}
@@ -0,0 +1,306 @@
+//! Planning routes between two stations
+
+use crate::data::{Network, StationGroup, StationGroupId, StationId};
+use crate::map::Meters;
+use pathfinding::directed::astar::astar;
+
+/// What the A* cost of traveling along one connection is when counting hops. Transfers only
+/// cost `TRANSFER_COST`, so that they only decide between routes with the same number of hops.
+const HOP_COST: u32 = 1000;
+
+/// Extra A* cost for changing lines at a transfer station, on top of whatever else the transfer
+/// costs. It keeps routes from switching back and forth between parallel lines for no reason.
+const TRANSFER_COST: u32 = 5;
+
+/// What a planned route is shortest by
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RouteMetric {
+    /// The fewest stations along the way
+    #[default]
+    Hops,
+
+    /// The shortest distance along the tracks
+    Distance,
+}
+
+impl RouteMetric {
+    /// The other metric
+    pub fn toggled(self) -> Self {
+        match self {
+            RouteMetric::Hops => RouteMetric::Distance,
+            RouteMetric::Distance => RouteMetric::Hops,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RouteMetric::Hops => "hops",
+            RouteMetric::Distance => "distance",
+        }
+    }
+}
+
+/// A way through the network from one station group to another
+#[derive(Debug, Clone)]
+pub struct Route {
+    /// Every station passed through, in order. Changing lines goes from one station to another
+    /// in the same group.
+    pub stations: Vec<StationId>,
+
+    /// How far it is along the tracks
+    pub length: Meters,
+}
+
+impl Route {
+    /// The station groups stopped at, in order, with each transfer counted once
+    pub fn stops<'a>(&self, network: &'a Network) -> Vec<&'a StationGroup> {
+        let mut stops: Vec<&StationGroup> = self
+            .stations
+            .iter()
+            .filter_map(|id| network.group_of(*id))
+            .collect();
+        stops.dedup_by_key(|group| group.id);
+        stops
+    }
+
+    /// How many connections the route travels along
+    pub fn hops(&self, network: &Network) -> usize {
+        self.stops(network).len().saturating_sub(1)
+    }
+}
+
+/// The shortest route between two station groups by the given metric, starting and ending on any
+/// of their lines. None if there's no way there, or if either group doesn't exist.
+pub fn find_route(
+    network: &Network,
+    origin: StationGroupId,
+    destination: StationGroupId,
+    metric: RouteMetric,
+) -> Option<Route> {
+    let origin = network.groups.get(&origin)?;
+    let destination = network.groups.get(&destination)?;
+
+    // Every station is drawn at its group's position, and the tracks are measured between those,
+    // so the straight line to the destination is never longer than the way there
+    let get_remaining = |id: &StationId| -> u32 {
+        match metric {
+            RouteMetric::Hops => 0,
+            RouteMetric::Distance => network
+                .draw_coord(*id)
+                .map_or(0, |coord| coord.meters_to(&destination.coord).0 as u32),
+        }
+    };
+
+    // Stations in a group are all drawn in the same place, so transfers have no length
+    let get_neighbors = |id: &StationId| -> Vec<(StationId, u32)> {
+        neighbors(
+            network,
+            *id,
+            |i| match metric {
+                RouteMetric::Hops => HOP_COST,
+                RouteMetric::Distance => network.track_length(*id, i).0.ceil() as u32,
+            },
+            |_| 0,
+        )
+    };
+
+    // The route can start on any line at the origin, which is the same as starting from its
+    // first station and transferring for free
+    let start = *origin.members.first()?;
+    let (mut stations, _) = astar(
+        &start,
+        |id| {
+            let mut neighbors = get_neighbors(id);
+            if *id == start {
+                for (i, cost) in neighbors.iter_mut() {
+                    if origin.members.contains(i) {
+                        *cost = 0;
+                    }
+                }
+            }
+            neighbors
+        },
+        get_remaining,
+        |id| destination.members.contains(id),
+    )?;
+
+    // Drop the free transfers at the start
+    let first_departure = stations
+        .iter()
+        .rposition(|id| origin.members.contains(id))
+        .unwrap_or(0);
+    stations.drain(..first_departure);
+
+    let length = stations
+        .windows(2)
+        .map(|pair| network.track_length(pair[0], pair[1]))
+        .fold(Meters(0.0), |total, length| total + length);
+
+    Some(Route { stations, length })
+}
+
+/// The stations one step on from `id` for A*, with what it costs to get to each: the stations
+/// it's connected to, costing `travel_cost`, and then the other stations in its group, costing
+/// `transfer_cost` plus `TRANSFER_COST`
+pub(crate) fn neighbors(
+    network: &Network,
+    id: StationId,
+    travel_cost: impl Fn(StationId) -> u32,
+    transfer_cost: impl Fn(StationId) -> u32,
+) -> Vec<(StationId, u32)> {
+    let mut neighbor_ids: Vec<StationId> = network
+        .connections
+        .get(&id)
+        .into_iter()
+        .flatten()
+        .copied()
+        .collect();
+
+    // The connections are in a HashSet, so put them in a fixed order to always break ties
+    // between equally good routes the same way
+    neighbor_ids.sort();
+
+    neighbor_ids
+        .into_iter()
+        .map(|i| (i, travel_cost(i)))
+        .chain(
+            network
+                .transfers(id)
+                .map(|i| (i, transfer_cost(i) + TRANSFER_COST)),
+        )
+        .collect()
+}
+
+/// A route being planned: the two ends that have been picked so far, and the route between them
+/// once both have
+#[derive(Debug, Clone, Default)]
+pub struct RoutePlan {
+    pub origin: Option<StationGroupId>,
+    pub destination: Option<StationGroupId>,
+    pub metric: RouteMetric,
+
+    /// None until both ends are picked, or if there's no way between them
+    pub route: Option<Route>,
+}
+
+impl RoutePlan {
+    /// Find the route again after one of the ends or the metric changed
+    pub fn update(&mut self, network: &Network) {
+        self.route = match (self.origin, self.destination) {
+            (Some(origin), Some(destination)) => {
+                find_route(network, origin, destination, self.metric)
+            }
+            _ => None,
+        };
+    }
+
+    /// Whether anything has been picked yet
+    pub fn is_empty(&self) -> bool {
+        self.origin.is_none() && self.destination.is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{load_connections_from_reader, load_stations_from_reader, Strictness};
+    use ahash::RandomState;
+    use indexmap::IndexMap;
+
+    /// Two lines from 一 to 四. Line 1 takes two hops, the long way around through 北, and line
+    /// 2 takes three along a straight line. 西 isn't connected to anything.
+    const STATIONS: &str = "station_cd,station_g_cd,station_name,lon,lat
+101,101,一,139.0,35.0
+102,102,北,139.05,35.5
+103,103,四,139.1,35.0
+201,101,一,139.0,35.0
+202,202,二,139.03,35.0
+203,203,三,139.07,35.0
+204,103,四,139.1,35.0
+301,301,西,140.0,36.0
+";
+
+    const CONNECTIONS: &str = "station_cd1,station_cd2
+101,102
+102,103
+201,202
+202,203
+203,204
+";
+
+    fn network() -> Network {
+        let stations =
+            load_stations_from_reader(STATIONS.as_bytes(), "stations.csv", Strictness::Strict)
+                .unwrap()
+                .data;
+        let connections = load_connections_from_reader(
+            CONNECTIONS.as_bytes(),
+            "join.csv",
+            &stations,
+            Strictness::Strict,
+        )
+        .unwrap()
+        .data;
+        Network::new(
+            stations,
+            connections.connections,
+            IndexMap::with_hasher(RandomState::new()),
+            IndexMap::with_hasher(RandomState::new()),
+        )
+    }
+
+    fn stations(route: &Route) -> Vec<u32> {
+        route.stations.iter().map(|id| id.0).collect()
+    }
+
+    #[test]
+    fn metrics_pick_different_routes() {
+        let network = network();
+        let (origin, destination) = (StationGroupId(101), StationGroupId(103));
+
+        let by_hops = find_route(&network, origin, destination, RouteMetric::Hops).unwrap();
+        assert_eq!(stations(&by_hops), [101, 102, 103]);
+        assert_eq!(by_hops.hops(&network), 2);
+
+        let by_distance = find_route(&network, origin, destination, RouteMetric::Distance).unwrap();
+        assert_eq!(by_distance.hops(&network), 3);
+        assert!(by_distance.length.0 < by_hops.length.0);
+    }
+
+    #[test]
+    fn route_starts_on_the_line_it_leaves_on() {
+        let network = network();
+        let route = find_route(
+            &network,
+            StationGroupId(101),
+            StationGroupId(103),
+            RouteMetric::Distance,
+        )
+        .unwrap();
+
+        // Starting on line 1 and transferring to line 2 before leaving isn't part of the route
+        assert_eq!(stations(&route), [201, 202, 203, 204]);
+        let stops: Vec<&str> = route
+            .stops(&network)
+            .iter()
+            .map(|group| group.name.as_str())
+            .collect();
+        assert_eq!(stops, ["一", "二", "三", "四"]);
+    }
+
+    #[test]
+    fn no_route_without_a_way_there() {
+        let network = network();
+        for metric in [RouteMetric::Hops, RouteMetric::Distance] {
+            assert!(
+                find_route(&network, StationGroupId(101), StationGroupId(301), metric).is_none()
+            );
+            assert!(
+                find_route(&network, StationGroupId(999), StationGroupId(103), metric).is_none()
+            );
+            assert!(
+                find_route(&network, StationGroupId(101), StationGroupId(999), metric).is_none()
+            );
+        }
+    }
+}
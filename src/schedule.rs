@@ -0,0 +1,592 @@
+use crate::{
+    data::{LineId, Network, StationId},
+    map::Meters,
+};
+use ahash::RandomState;
+use derive_more::{Add, AddAssign, Sub, SubAssign};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    str::FromStr,
+};
+
+const MILLIS_PER_SECOND: u32 = 1000;
+const MILLIS_PER_MINUTE: u32 = 60 * MILLIS_PER_SECOND;
+const MILLIS_PER_HOUR: u32 = 60 * MILLIS_PER_MINUTE;
+
+/// A time of day in the simulation, in milliseconds since midnight. Like in GTFS, the last trains
+/// of a service day run at times past 24:00:00 rather than wrapping around to 00:00:00.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Add,
+    Sub,
+    AddAssign,
+    SubAssign,
+)]
+pub struct SimTime(pub u32);
+
+impl SimTime {
+    pub const fn from_hms(hours: u32, minutes: u32, seconds: u32) -> Self {
+        SimTime(hours * MILLIS_PER_HOUR + minutes * MILLIS_PER_MINUTE + seconds * MILLIS_PER_SECOND)
+    }
+
+    pub const fn from_minutes(minutes: u32) -> Self {
+        SimTime(minutes * MILLIS_PER_MINUTE)
+    }
+
+    pub const fn from_seconds(seconds: u32) -> Self {
+        SimTime(seconds * MILLIS_PER_SECOND)
+    }
+
+    pub fn hours(self) -> u32 {
+        self.0 / MILLIS_PER_HOUR
+    }
+
+    pub fn minutes(self) -> u32 {
+        self.0 % MILLIS_PER_HOUR / MILLIS_PER_MINUTE
+    }
+
+    pub fn seconds(self) -> u32 {
+        self.0 % MILLIS_PER_MINUTE / MILLIS_PER_SECOND
+    }
+
+    /// The same time of day, moved into the service day (`SERVICE_DAY_START` up to 24 hours
+    /// later)
+    pub fn in_service_day(self) -> Self {
+        let day = ONE_DAY.0;
+        let start = SERVICE_DAY_START.0;
+        SimTime((self.0 % day + day - start) % day + start)
+    }
+}
+
+impl Display for SimTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}:{:02}",
+            self.hours(),
+            self.minutes(),
+            self.seconds()
+        )
+    }
+}
+
+/// Parse a time like `7:05`, `07:05:30` or `25:10:00`
+impl FromStr for SimTime {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts = s
+            .trim()
+            .split(':')
+            .map(|p| p.parse::<u32>().map_err(|_| ()))
+            .collect::<Result<Vec<u32>, ()>>()?;
+
+        match parts.as_slice() {
+            [h, m] if *m < 60 => Ok(SimTime::from_hms(*h, *m, 0)),
+            [h, m, s] if *m < 60 && *s < 60 => Ok(SimTime::from_hms(*h, *m, *s)),
+            _ => Err(()),
+        }
+    }
+}
+
+pub const ONE_DAY: SimTime = SimTime::from_hms(24, 0, 0);
+
+/// When the simulated day rolls over. Nothing runs at this time of night, so it's where the
+/// clock wraps around to the next day.
+pub const SERVICE_DAY_START: SimTime = SimTime::from_hms(4, 0, 0);
+
+/// First and last departures from a line's terminals in a generated timetable
+const FIRST_DEPARTURE: SimTime = SimTime::from_hms(5, 0, 0);
+const LAST_DEPARTURE: SimTime = SimTime::from_hms(24, 0, 0);
+
+/// How long trains stop at each station in a generated timetable
+const DWELL_TIME: SimTime = SimTime::from_seconds(30);
+
+/// Shortest time between two stations in a generated timetable
+const MIN_RUNNING_TIME: SimTime = SimTime::from_seconds(60);
+
+/// Average speed between stations in a generated timetable, in km/h
+const AVERAGE_SPEED: f32 = 45.0;
+
+/// When a trip gets to and leaves one of its stops, as given in a timetable file
+#[derive(Clone, Copy, Debug)]
+pub struct StopTime {
+    pub station_id: StationId,
+    pub arrival: SimTime,
+    pub departure: SimTime,
+}
+
+/// One stop of a `Pattern`. Times are relative to the trip's departure from its first stop.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PatternStop {
+    pub station_id: StationId,
+    pub arrival: SimTime,
+    pub departure: SimTime,
+}
+
+/// A sequence of stops along with the time it takes to get to each of them, shared by all the
+/// trips that run the same way
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Pattern {
+    pub line_id: LineId,
+    pub stops: Vec<PatternStop>,
+}
+
+impl Pattern {
+    /// How long a trip takes from its first departure to its last arrival
+    pub fn duration(&self) -> SimTime {
+        self.stops.last().map(|s| s.arrival).unwrap_or_default()
+    }
+}
+
+/// A single run of a train along a pattern
+#[derive(Clone, Copy, Debug)]
+pub struct Trip {
+    pub pattern: usize,
+
+    /// When the train leaves its first stop
+    pub start: SimTime,
+
+    /// When the train arrives at its last stop
+    pub end: SimTime,
+}
+
+/// All the trips run during a day, sorted by the time they start
+#[derive(Debug, Default)]
+pub struct Timetable {
+    patterns: Vec<Pattern>,
+    trips: Vec<Trip>,
+
+    /// How long the longest trip takes, so that the trips running at a given time can be found
+    /// among the ones that started at most this long before it
+    longest_trip: SimTime,
+}
+
+impl Timetable {
+    /// Put together a timetable from the stops of each trip, in the order they're visited. Trips
+    /// with fewer than two stops are left out, since they don't go anywhere.
+    pub fn from_stop_times(network: &Network, trips: Vec<Vec<StopTime>>) -> Self {
+        let mut timetable = Timetable::default();
+        let mut pattern_indices: HashMap<Pattern, usize, RandomState> =
+            HashMap::with_hasher(RandomState::new());
+
+        for stops in trips.into_iter().filter(|stops| stops.len() >= 2) {
+            // Times before the service day starts belong to its late night end
+            let first = stops[0];
+            let start = first.departure.in_service_day();
+            let offset = |time: SimTime| SimTime(time.0.saturating_sub(first.departure.0));
+
+            let pattern = Pattern {
+                line_id: network
+                    .stations
+                    .get(&first.station_id)
+                    .map_or(first.station_id.line_id(), |s| s.line_id),
+                stops: stops
+                    .iter()
+                    .map(|stop| PatternStop {
+                        station_id: stop.station_id,
+                        arrival: offset(stop.arrival),
+                        departure: offset(stop.departure.max(stop.arrival)),
+                    })
+                    .collect(),
+            };
+
+            let end = start + pattern.duration();
+            let index = match pattern_indices.get(&pattern) {
+                Some(index) => *index,
+                None => {
+                    let index = timetable.patterns.len();
+                    pattern_indices.insert(pattern.clone(), index);
+                    timetable.patterns.push(pattern);
+                    index
+                }
+            };
+            timetable.trips.push(Trip {
+                pattern: index,
+                start,
+                end,
+            });
+        }
+
+        timetable.sort();
+        timetable
+    }
+
+    /// Make up a timetable where every line runs all day in both directions, more often at rush
+    /// hour and on busy lines
+    pub fn generate(network: &Network) -> Self {
+        let mut timetable = Timetable::default();
+
+        for line_id in network.lines.keys() {
+            let routes = line_routes(network, *line_id);
+            if routes.is_empty() {
+                continue;
+            }
+
+            // Lines with a lot of transfer stations are in big cities, and run more often
+            let station_count = routes.iter().map(Vec::len).sum::<usize>();
+            let transfer_count = routes
+                .iter()
+                .flatten()
+                .filter(|id| network.transfers(**id).next().is_some())
+                .count();
+            let peak_headway = peak_headway(transfer_count as f32 / station_count as f32);
+
+            for route in routes {
+                let forward = route_pattern(network, *line_id, route.iter().copied());
+                let backward = route_pattern(network, *line_id, route.iter().rev().copied());
+
+                timetable.add_all_day(forward, peak_headway);
+                timetable.add_all_day(backward, peak_headway);
+            }
+        }
+
+        timetable.sort();
+        timetable
+    }
+
+    /// Add trips on the given pattern from the first to the last departure of the day
+    fn add_all_day(&mut self, pattern: Pattern, peak_headway: SimTime) {
+        let index = self.patterns.len();
+        let duration = pattern.duration();
+        self.patterns.push(pattern);
+
+        let mut start = FIRST_DEPARTURE;
+        while start <= LAST_DEPARTURE {
+            self.trips.push(Trip {
+                pattern: index,
+                start,
+                end: start + duration,
+            });
+            start += headway(peak_headway, start);
+        }
+    }
+
+    fn sort(&mut self) {
+        self.trips.sort_by_key(|t| (t.start, t.pattern));
+        self.longest_trip = self
+            .trips
+            .iter()
+            .map(|t| t.end - t.start)
+            .max()
+            .unwrap_or_default();
+    }
+
+    pub fn pattern(&self, index: usize) -> &Pattern {
+        &self.patterns[index]
+    }
+
+    pub fn trips(&self) -> &[Trip] {
+        &self.trips
+    }
+
+    /// The indices of the trips that leave their first stop after `from`, up to and including
+    /// `to`
+    pub fn trips_starting(&self, from: SimTime, to: SimTime) -> std::ops::Range<usize> {
+        let first = self.trips.partition_point(|t| t.start <= from);
+        let last = self.trips.partition_point(|t| t.start <= to);
+        first..last.max(first)
+    }
+
+    /// The indices of the trips that are on their way at `time`
+    pub fn trips_running(&self, time: SimTime) -> impl Iterator<Item = usize> + '_ {
+        // Trips that started before this have all finished by `time`
+        let earliest = SimTime(time.0.saturating_sub(self.longest_trip.0));
+        let first = self.trips.partition_point(|t| t.start < earliest);
+        let started = self.trips.partition_point(|t| t.start <= time);
+        (first..started.max(first)).filter(move |i| self.trips[*i].end > time)
+    }
+}
+
+/// The time between trains at rush hour, based on the share of a line's stations where you can
+/// transfer to another line
+fn peak_headway(transfer_share: f32) -> SimTime {
+    if transfer_share > 0.5 {
+        SimTime::from_minutes(3)
+    } else if transfer_share > 0.25 {
+        SimTime::from_minutes(6)
+    } else if transfer_share > 0.1 {
+        SimTime::from_minutes(15)
+    } else {
+        SimTime::from_minutes(30)
+    }
+}
+
+/// The time until the next train leaves after one that left at `time`
+fn headway(peak_headway: SimTime, time: SimTime) -> SimTime {
+    let morning_rush = SimTime::from_hms(7, 0, 0)..SimTime::from_hms(9, 30, 0);
+    let evening_rush = SimTime::from_hms(17, 0, 0)..SimTime::from_hms(19, 30, 0);
+    let late_night = SimTime::from_hms(21, 0, 0);
+
+    let multiplier = if morning_rush.contains(&time) || evening_rush.contains(&time) {
+        1
+    } else if time >= late_night {
+        3
+    } else {
+        2
+    };
+
+    SimTime(peak_headway.0 * multiplier)
+}
+
+/// The stops and times for a train running through the given stations in order
+fn route_pattern<I: Iterator<Item = StationId>>(
+    network: &Network,
+    line_id: LineId,
+    stations: I,
+) -> Pattern {
+    let mut stops: Vec<PatternStop> = Vec::new();
+    for station_id in stations {
+        let arrival = match stops.last() {
+            Some(previous) => {
+                let distance = network.track_length(previous.station_id, station_id);
+                previous.departure + running_time(distance)
+            }
+            None => SimTime(0),
+        };
+
+        stops.push(PatternStop {
+            station_id,
+            arrival,
+            departure: if stops.is_empty() {
+                arrival
+            } else {
+                arrival + DWELL_TIME
+            },
+        });
+    }
+
+    // The train doesn't wait at its last stop
+    if let Some(last) = stops.last_mut() {
+        last.departure = last.arrival;
+    }
+
+    Pattern { line_id, stops }
+}
+
+fn running_time(distance: Meters) -> SimTime {
+    let hours = distance.kilometers() / AVERAGE_SPEED;
+    SimTime((hours * MILLIS_PER_HOUR as f32) as u32).max(MIN_RUNNING_TIME)
+}
+
+/// Split a line into routes that trains can run along from end to end. A simple line is a single
+/// route. A line with branches is split at the junctions, and a loop line (with no ends) is one
+/// route that ends back where it started.
+fn line_routes(network: &Network, line_id: LineId) -> Vec<Vec<StationId>> {
+    // The stations on this line, and the connections between them that belong to it. join.csv
+    // can put a connection on a line that only one of its stations is on, so leave out the
+    // stations that aren't.
+    let mut neighbors: HashMap<StationId, Vec<StationId>, RandomState> =
+        HashMap::with_hasher(RandomState::new());
+    for station in network.stations.values().filter(|s| s.line_id == line_id) {
+        let mut on_line: Vec<StationId> = network
+            .connections
+            .get(&station.id)
+            .into_iter()
+            .flatten()
+            .copied()
+            .filter(|other| network.lines_between(station.id, *other).contains(&line_id))
+            .filter(|other| {
+                network
+                    .stations
+                    .get(other)
+                    .is_some_and(|s| s.line_id == line_id)
+            })
+            .collect();
+        on_line.sort();
+        neighbors.insert(station.id, on_line);
+    }
+
+    let mut stations: Vec<StationId> = neighbors.keys().copied().collect();
+    stations.sort();
+
+    let mut visited: HashSet<(StationId, StationId), RandomState> =
+        HashSet::with_hasher(RandomState::new());
+    let mut routes: Vec<Vec<StationId>> = Vec::new();
+
+    let walk = |start: StationId,
+                next: StationId,
+                visited: &mut HashSet<(StationId, StationId), RandomState>|
+     -> Vec<StationId> {
+        let mut route = vec![start];
+        let (mut previous, mut current) = (start, next);
+        visited.insert(edge(previous, current));
+        loop {
+            route.push(current);
+            let onward: Vec<StationId> = neighbors[&current]
+                .iter()
+                .copied()
+                .filter(|n| *n != previous && !visited.contains(&edge(current, *n)))
+                .collect();
+
+            // Stop at the end of the line and at junctions
+            if neighbors[&current].len() != 2 || onward.is_empty() {
+                return route;
+            }
+
+            previous = current;
+            current = onward[0];
+            visited.insert(edge(previous, current));
+        }
+    };
+
+    // Start from the ends of the line and its junctions first, then whatever is left over has to
+    // be part of a loop
+    let ends = stations
+        .iter()
+        .filter(|id| neighbors[id].len() != 2)
+        .chain(stations.iter());
+    for start in ends {
+        for next in neighbors[start].clone() {
+            if !visited.contains(&edge(*start, next)) {
+                routes.push(walk(*start, next, &mut visited));
+            }
+        }
+    }
+
+    routes
+}
+
+fn edge(station_id_1: StationId, station_id_2: StationId) -> (StationId, StationId) {
+    if station_id_1 <= station_id_2 {
+        (station_id_1, station_id_2)
+    } else {
+        (station_id_2, station_id_1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{load_connections_from_reader, load_stations_from_reader, Strictness};
+    use indexmap::IndexMap;
+
+    #[test]
+    fn times_past_midnight_parse() {
+        assert_eq!("7:05".parse(), Ok(SimTime::from_hms(7, 5, 0)));
+        assert_eq!("07:05:30".parse(), Ok(SimTime::from_hms(7, 5, 30)));
+
+        let late: SimTime = "25:10:00".parse().unwrap();
+        assert_eq!(late, SimTime::from_hms(25, 10, 0));
+        assert_eq!(late.to_string(), "25:10:00");
+
+        assert_eq!("7:60".parse::<SimTime>(), Err(()));
+        assert_eq!("7:05:60".parse::<SimTime>(), Err(()));
+        assert_eq!("7".parse::<SimTime>(), Err(()));
+        assert_eq!("seven".parse::<SimTime>(), Err(()));
+    }
+
+    #[test]
+    fn times_move_into_the_service_day() {
+        let in_service_day = |h, m| SimTime::from_hms(h, m, 0).in_service_day();
+        assert_eq!(in_service_day(4, 0), SimTime::from_hms(4, 0, 0));
+        assert_eq!(in_service_day(12, 30), SimTime::from_hms(12, 30, 0));
+        assert_eq!(in_service_day(0, 30), SimTime::from_hms(24, 30, 0));
+        assert_eq!(in_service_day(3, 59), SimTime::from_hms(27, 59, 0));
+        assert_eq!(in_service_day(25, 0), SimTime::from_hms(25, 0, 0));
+        assert_eq!(in_service_day(28, 0), SimTime::from_hms(4, 0, 0));
+        assert_eq!(in_service_day(36, 0), SimTime::from_hms(12, 0, 0));
+    }
+
+    fn trip(start: (u32, u32), end: (u32, u32)) -> Trip {
+        Trip {
+            pattern: 0,
+            start: SimTime::from_hms(start.0, start.1, 0),
+            end: SimTime::from_hms(end.0, end.1, 0),
+        }
+    }
+
+    #[test]
+    fn running_trips_are_found() {
+        let mut timetable = Timetable {
+            trips: vec![
+                trip((9, 0), (9, 30)),
+                trip((5, 0), (11, 0)),
+                trip((8, 0), (8, 20)),
+                trip((8, 50), (9, 10)),
+                trip((10, 0), (10, 5)),
+            ],
+            ..Timetable::default()
+        };
+        timetable.sort();
+
+        let running = |h, m| -> Vec<(SimTime, SimTime)> {
+            timetable
+                .trips_running(SimTime::from_hms(h, m, 0))
+                .map(|i| (timetable.trips()[i].start, timetable.trips()[i].end))
+                .collect()
+        };
+        let times = |t: Trip| (t.start, t.end);
+
+        assert_eq!(running(4, 0), vec![]);
+        assert_eq!(running(5, 0), vec![times(trip((5, 0), (11, 0)))]);
+        assert_eq!(
+            running(8, 10),
+            vec![times(trip((5, 0), (11, 0))), times(trip((8, 0), (8, 20)))]
+        );
+        assert_eq!(
+            running(9, 0),
+            vec![
+                times(trip((5, 0), (11, 0))),
+                times(trip((8, 50), (9, 10))),
+                times(trip((9, 0), (9, 30))),
+            ]
+        );
+        assert_eq!(running(10, 5), vec![times(trip((5, 0), (11, 0)))]);
+        assert_eq!(running(11, 0), vec![]);
+    }
+
+    #[test]
+    fn lines_only_run_through_their_own_stations() {
+        let stations = "station_cd,station_name,line_cd,lon,lat
+1,一,1,139.70,35.0
+2,二,2,139.71,35.0
+3,三,1,139.72,35.0
+";
+        let stations =
+            load_stations_from_reader(stations.as_bytes(), "stations.csv", Strictness::Strict)
+                .unwrap()
+                .data;
+
+        // 2 is on line 2, but join.csv puts its connection to 1 on line 1
+        let connections = "line_cd,station_cd1,station_cd2\n1,1,2\n1,1,3\n";
+        let connections = load_connections_from_reader(
+            connections.as_bytes(),
+            "join.csv",
+            &stations,
+            Strictness::Strict,
+        )
+        .unwrap()
+        .data;
+        let mut network = Network::new(
+            stations,
+            connections.connections,
+            IndexMap::with_hasher(RandomState::new()),
+            IndexMap::with_hasher(RandomState::new()),
+        );
+        network.add_connection_lines(connections.lines);
+
+        assert_eq!(
+            line_routes(&network, LineId(1)),
+            vec![vec![StationId(1), StationId(3)]]
+        );
+        assert!(line_routes(&network, LineId(2)).is_empty());
+
+        let timetable = Timetable::generate(&network);
+        assert!(!timetable.trips().is_empty());
+        for trip in timetable.trips() {
+            let pattern = timetable.pattern(trip.pattern);
+            assert_eq!(pattern.line_id, LineId(1));
+            assert!(pattern.stops.iter().all(|s| s.station_id != StationId(2)));
+        }
+    }
+}
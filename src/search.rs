@@ -0,0 +1,205 @@
+//! Finding stations by name
+
+use crate::data::{Network, StationGroup, StationGroupId};
+use std::cmp::Reverse;
+
+/// How closely a name matches a query. Better matches sort first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Match {
+    Exact,
+    Prefix,
+    Contains,
+}
+
+/// Make names and queries comparable regardless of how they were typed: katakana becomes
+/// hiragana, full-width letters and digits become ASCII, letters become lowercase, and spaces are
+/// dropped. The small ヶ in names like 市ヶ谷 is read け, and is matched by it.
+pub fn normalize(text: &str) -> String {
+    text.chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| match c {
+            // Before the rest of katakana, which would make ヶ into ゖ
+            'ヶ' | 'ゖ' => 'け',
+            // ァ to ヶ line up with ぁ to ゖ
+            'ァ'..='ヶ' => std::char::from_u32(c as u32 - 0x60).unwrap_or(c),
+            // ！ to ～ line up with ! to ~
+            '！'..='～' => std::char::from_u32(c as u32 - 0xFEE0).unwrap_or(c),
+            _ => c,
+        })
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// How well any of the group's names matches the (normalized) query
+fn match_group(group: &StationGroup, query: &str) -> Option<Match> {
+    std::iter::once(&group.name)
+        .chain(group.kana.as_ref())
+        .chain(group.romaji.as_ref())
+        .filter_map(|name| {
+            let name = normalize(name);
+            if name == query {
+                Some(Match::Exact)
+            } else if name.starts_with(query) {
+                Some(Match::Prefix)
+            } else if name.contains(query) {
+                Some(Match::Contains)
+            } else {
+                None
+            }
+        })
+        .min()
+}
+
+/// The station groups whose name, reading or name in romaji contains `query`, best matches first:
+/// exact matches, then names starting with the query, then the rest. Among equally good matches,
+/// stations on more lines come first.
+pub fn find_stations<'a>(network: &'a Network, query: &str) -> Vec<&'a StationGroup> {
+    let query = normalize(query);
+    if query.is_empty() {
+        return Vec::new();
+    }
+
+    let mut matches: Vec<(Match, &StationGroup)> = network
+        .groups
+        .values()
+        .filter_map(|group| match_group(group, &query).map(|m| (m, group)))
+        .collect();
+    matches.sort_by_key(|(m, group)| {
+        (
+            *m,
+            Reverse(group.members.len()),
+            group.name.chars().count(),
+            group.id,
+        )
+    });
+
+    matches.into_iter().map(|(_, group)| group).collect()
+}
+
+/// A search that's being typed in: the query so far, what it matches, and which of the matches
+/// would be picked
+#[derive(Debug, Clone, Default)]
+pub struct Search {
+    query: String,
+    matches: Vec<StationGroupId>,
+    choice: usize,
+}
+
+impl Search {
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    /// The matching station groups, best first
+    pub fn matches(&self) -> &[StationGroupId] {
+        &self.matches
+    }
+
+    /// Which of the matches would be picked
+    pub fn choice(&self) -> Option<StationGroupId> {
+        self.matches.get(self.choice).copied()
+    }
+
+    /// The index of the picked match in `matches`
+    pub fn choice_index(&self) -> usize {
+        self.choice
+    }
+
+    /// Change the query, finding the matches again and going back to picking the best one
+    pub fn set_query(&mut self, network: &Network, query: String) {
+        self.matches = find_stations(network, &query)
+            .into_iter()
+            .map(|group| group.id)
+            .collect();
+        self.query = query;
+        self.choice = 0;
+    }
+
+    /// Pick the match `step` places further down the list (or up, if negative), wrapping around
+    pub fn move_choice(&mut self, step: isize) {
+        if !self.matches.is_empty() {
+            let count = self.matches.len() as isize;
+            self.choice = (self.choice as isize + step).rem_euclid(count) as usize;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{load_stations_from_reader, Strictness};
+    use ahash::RandomState;
+    use indexmap::IndexMap;
+    use std::collections::HashMap;
+
+    /// 新宿 is on three lines and 南新宿 on two; the rest are on one
+    const STATIONS: &str = "station_cd,station_g_cd,station_name,station_name_k,lon,lat
+100,100,新宿,シンジュク,139.70,35.69
+101,100,新宿,シンジュク,139.70,35.69
+102,100,新宿,シンジュク,139.70,35.69
+200,200,新宿三丁目,シンジュクサンチョウメ,139.70,35.69
+300,300,西新宿,ニシシンジュク,139.69,35.69
+400,400,南新宿,ミナミシンジュク,139.70,35.68
+401,400,南新宿,ミナミシンジュク,139.70,35.68
+500,500,北新宿,キタシンジュク,139.69,35.70
+600,600,代々木,ヨヨギ,139.70,35.68
+";
+
+    fn network() -> Network {
+        let stations =
+            load_stations_from_reader(STATIONS.as_bytes(), "stations.csv", Strictness::Strict)
+                .unwrap()
+                .data;
+        Network::new(
+            stations,
+            HashMap::with_hasher(RandomState::new()),
+            IndexMap::with_hasher(RandomState::new()),
+            IndexMap::with_hasher(RandomState::new()),
+        )
+    }
+
+    fn names<'a>(network: &'a Network, query: &str) -> Vec<&'a str> {
+        find_stations(network, query)
+            .into_iter()
+            .map(|group| group.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn names_are_normalized() {
+        assert_eq!(normalize("シンジュク"), "しんじゅく");
+        assert_eq!(normalize("Ｓｈｉｎｊｕｋｕ 3"), "shinjuku3");
+        assert_eq!(normalize("市ヶ谷"), normalize("市ケ谷"));
+        assert_eq!(normalize("いちがや ゖ"), "いちがやけ");
+    }
+
+    #[test]
+    fn exact_matches_come_before_prefixes_and_prefixes_before_the_rest() {
+        let network = network();
+        // Among names that only contain the query, the one on more lines comes first, and then
+        // the one with the lower ID
+        assert_eq!(
+            names(&network, "新宿"),
+            ["新宿", "新宿三丁目", "南新宿", "西新宿", "北新宿"]
+        );
+        assert_eq!(names(&network, "新宿三"), ["新宿三丁目"]);
+        assert!(names(&network, "渋谷").is_empty());
+        assert!(names(&network, " ").is_empty());
+    }
+
+    #[test]
+    fn kana_and_romaji_find_the_same_station() {
+        let network = network();
+        for query in [
+            "新宿",
+            "しんじゅく",
+            "シンジュク",
+            "shinjuku",
+            "Ｓｈｉｎｊｕｋｕ",
+        ] {
+            assert_eq!(names(&network, query)[0], "新宿", "{}", query);
+        }
+        assert_eq!(names(&network, "よよぎ"), ["代々木"]);
+        assert_eq!(names(&network, "Yoyogi"), ["代々木"]);
+    }
+}
@@ -0,0 +1,32 @@
+use crate::{map::Viewport, world::World};
+use png::{BitDepth, ColorType, Encoder, EncodingError};
+use std::{fs::File, io::BufWriter, path::Path};
+
+/// Draw the world's current MapFrame into a new RGBA buffer of `width` x `height` pixels. The
+/// frame keeps its center and zoom level, and shows more or less of the map to fill the new size.
+/// This leaves the world's viewport at the new size.
+pub fn render(world: &mut World, width: u32, height: u32) -> Vec<u8> {
+    let mut map_frame = world.map_frame().clone();
+    map_frame.resize_around_center(Viewport { width, height });
+    world.set_map_frame(map_frame);
+
+    let mut pixels = vec![0; width as usize * height as usize * 4];
+    world.draw(&mut pixels);
+    pixels
+}
+
+/// Render the world's current MapFrame to a PNG file, without needing a window or a GPU
+pub fn save_png<P: AsRef<Path>>(
+    world: &mut World,
+    width: u32,
+    height: u32,
+    path: P,
+) -> Result<(), EncodingError> {
+    let pixels = render(world, width, height);
+
+    let file = File::create(path)?;
+    let mut encoder = Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(ColorType::RGBA);
+    encoder.set_depth(BitDepth::Eight);
+    encoder.write_header()?.write_image_data(&pixels)
+}
@@ -0,0 +1,500 @@
+use crate::{
+    data::{Network, StationId},
+    map::{Degree, MapCoord, PlaneCoord},
+};
+use std::ops::RangeInclusive;
+
+/// Size of one grid cell, in degrees of longitude and latitude. All of Japan fits in a few
+/// hundred cells on each side, and a cell around central Tokyo holds a few dozen stations.
+const CELL_SIZE: f32 = 0.05;
+
+/// Most cells a grid can have on each side. A station far from the rest, like one with a typo in
+/// its coordinates, stretches the grid's bounding box, so the cells get bigger instead of more
+/// numerous past this.
+const MAX_CELLS_PER_SIDE: usize = 2000;
+
+/// A uniform grid over the map, where each cell lists the items that touch it. The lists are
+/// stored back to back in a single Vec, so that scanning a large area is cheap.
+struct Grid {
+    /// The corner with the lowest longitude and latitude
+    origin: MapCoord,
+    cell_width: f32,
+    cell_height: f32,
+    columns: usize,
+    rows: usize,
+
+    /// `items[starts[cell]..starts[cell + 1]]` are the items in that cell
+    starts: Vec<usize>,
+    items: Vec<u32>,
+}
+
+impl Grid {
+    /// Build a grid covering the given bounding box, with each item added to every cell that its
+    /// own bounding box overlaps
+    fn new(min: MapCoord, max: MapCoord, boxes: &[(MapCoord, MapCoord)]) -> Self {
+        let cell_width = ((max.long - min.long).0 / MAX_CELLS_PER_SIDE as f32).max(CELL_SIZE);
+        let cell_height = ((max.lat - min.lat).0 / MAX_CELLS_PER_SIDE as f32).max(CELL_SIZE);
+        let columns = cell_count((max.long - min.long).0, cell_width);
+        let rows = cell_count((max.lat - min.lat).0, cell_height);
+
+        let mut grid = Self {
+            origin: min,
+            cell_width,
+            cell_height,
+            columns,
+            rows,
+            starts: Vec::new(),
+            items: Vec::new(),
+        };
+
+        let mut entries: Vec<(usize, u32)> = Vec::new();
+        for (item, (item_min, item_max)) in boxes.iter().enumerate() {
+            let (x_range, y_range) = grid.cell_ranges(*item_min, *item_max);
+            for y in y_range {
+                for x in x_range.clone() {
+                    entries.push((y * columns + x, item as u32));
+                }
+            }
+        }
+        entries.sort_unstable();
+
+        grid.starts = vec![0; columns * rows + 1];
+        for (cell, _) in &entries {
+            grid.starts[cell + 1] += 1;
+        }
+        for cell in 0..columns * rows {
+            grid.starts[cell + 1] += grid.starts[cell];
+        }
+        grid.items = entries.into_iter().map(|(_, item)| item).collect();
+
+        grid
+    }
+
+    fn cell_column(&self, long: Degree) -> usize {
+        (((long - self.origin.long).0 / self.cell_width).max(0.0) as usize).min(self.columns - 1)
+    }
+
+    fn cell_row(&self, lat: Degree) -> usize {
+        (((lat - self.origin.lat).0 / self.cell_height).max(0.0) as usize).min(self.rows - 1)
+    }
+
+    /// The columns and rows of the cells overlapping the box between `min` and `max`, clamped to
+    /// the grid
+    fn cell_ranges(
+        &self,
+        min: MapCoord,
+        max: MapCoord,
+    ) -> (RangeInclusive<usize>, RangeInclusive<usize>) {
+        (
+            self.cell_column(min.long)..=self.cell_column(max.long),
+            self.cell_row(min.lat)..=self.cell_row(max.lat),
+        )
+    }
+
+    fn cell(&self, x: usize, y: usize) -> &[u32] {
+        let cell = y * self.columns + x;
+        &self.items[self.starts[cell]..self.starts[cell + 1]]
+    }
+
+    /// All items in cells overlapping the box between `min` and `max`, sorted and without
+    /// duplicates. Items near the edges of the box may not actually be inside it.
+    fn query(&self, min: MapCoord, max: MapCoord) -> Vec<u32> {
+        let (x_range, y_range) = self.cell_ranges(min, max);
+        let mut found: Vec<u32> = Vec::new();
+        for y in y_range {
+            for x in x_range.clone() {
+                found.extend_from_slice(self.cell(x, y));
+            }
+        }
+        found.sort_unstable();
+        found.dedup();
+
+        found
+    }
+}
+
+/// How many cells of the given size it takes to cover `extent`, at least one and at most
+/// `MAX_CELLS_PER_SIDE`
+fn cell_count(extent: f32, cell_size: f32) -> usize {
+    // `as` turns NaN into 0 and infinity into usize::MAX, so this holds for any coordinates
+    ((extent / cell_size) as usize).min(MAX_CELLS_PER_SIDE - 1) + 1
+}
+
+/// A connection between two stations, as it's drawn on the map
+#[derive(Clone, Debug)]
+pub struct TrackSegment {
+    pub start_station_id: StationId,
+    pub end_station_id: StationId,
+
+    /// The path of the track, from the start station to the end station. Just the two stations'
+    /// positions, unless the track has its own geometry.
+    pub path: Vec<MapCoord>,
+}
+
+/// Grid buckets over the station groups and the track segments of a Network, for finding what's
+/// on screen without looking at every station.
+pub struct SpatialIndex {
+    groups: Grid,
+    segments: Vec<TrackSegment>,
+    segment_grid: Grid,
+}
+
+impl SpatialIndex {
+    pub fn new(network: &Network) -> Self {
+        // Each connection is stored in both directions, but only needs to be drawn once. Sort so
+        // that segments are always drawn in the same order.
+        let mut segments: Vec<TrackSegment> = Vec::new();
+        for (station_id, connected_stations) in &network.connections {
+            for other_station_id in connected_stations {
+                let reverse_exists = network
+                    .connections
+                    .get(other_station_id)
+                    .is_some_and(|c| c.contains(station_id));
+                if reverse_exists && other_station_id < station_id {
+                    continue;
+                }
+
+                // The loaders reject connections to unknown stations, but a Network can also be
+                // built from data that didn't go through them, so just skip those here.
+                if let Some(path) = network.track_path(*station_id, *other_station_id) {
+                    segments.push(TrackSegment {
+                        start_station_id: *station_id,
+                        end_station_id: *other_station_id,
+                        path: path.into_owned(),
+                    });
+                }
+            }
+        }
+        segments.sort_by_key(|s| (s.start_station_id, s.end_station_id));
+
+        let group_boxes: Vec<(MapCoord, MapCoord)> = network
+            .groups
+            .values()
+            .map(|g| (g.coord, g.coord))
+            .collect();
+        let segment_boxes: Vec<(MapCoord, MapCoord)> = segments
+            .iter()
+            .map(|s| path_bounding_box(&s.path))
+            .collect();
+
+        let (min, max) = group_boxes.iter().fold(
+            (
+                MapCoord {
+                    long: Degree(f32::MAX),
+                    lat: Degree(f32::MAX),
+                },
+                MapCoord {
+                    long: Degree(f32::MIN),
+                    lat: Degree(f32::MIN),
+                },
+            ),
+            |(min, max), (coord, _)| (min_coord(min, *coord), max_coord(max, *coord)),
+        );
+        let (min, max) = if min.long <= max.long {
+            (min, max)
+        } else {
+            // No stations at all; any non-empty area will do
+            let zero = MapCoord {
+                long: Degree(0.0),
+                lat: Degree(0.0),
+            };
+            (zero, zero)
+        };
+
+        Self {
+            groups: Grid::new(min, max, &group_boxes),
+            segments,
+            segment_grid: Grid::new(min, max, &segment_boxes),
+        }
+    }
+
+    /// The indices (into `Network::groups`) of the station groups between the two corners, in
+    /// order
+    pub fn groups_in(&self, upper_left: MapCoord, lower_right: MapCoord) -> Vec<usize> {
+        let (min, max) = bounding_box(upper_left, lower_right);
+        self.groups
+            .query(min, max)
+            .into_iter()
+            .map(|i| i as usize)
+            .collect()
+    }
+
+    /// The track segments that may pass between the two corners, always in the same order. Some
+    /// of them may only be close by.
+    pub fn segments_in(&self, upper_left: MapCoord, lower_right: MapCoord) -> Vec<&TrackSegment> {
+        let (min, max) = bounding_box(upper_left, lower_right);
+        self.segment_grid
+            .query(min, max)
+            .into_iter()
+            .map(|i| &self.segments[i as usize])
+            .collect()
+    }
+
+    /// The index (into `Network::groups`) of the station group closest to `coord`, if there's one
+    /// within `max_distance`
+    pub fn nearest_group(
+        &self,
+        network: &Network,
+        coord: MapCoord,
+        max_distance: Degree,
+    ) -> Option<usize> {
+        let min = MapCoord {
+            long: coord.long - max_distance,
+            lat: coord.lat - max_distance,
+        };
+        let max = MapCoord {
+            long: coord.long + max_distance,
+            lat: coord.lat + max_distance,
+        };
+
+        let (x_range, y_range) = self.groups.cell_ranges(min, max);
+        let mut nearest: Option<(usize, Degree)> = None;
+        for y in y_range {
+            for x in x_range.clone() {
+                for index in self.groups.cell(x, y) {
+                    let index = *index as usize;
+                    let distance = match network.groups.get_index(index) {
+                        Some((_, group)) => group.coord.distance_to(&coord),
+                        None => continue,
+                    };
+                    let closer = match nearest {
+                        Some((nearest_index, nearest_distance)) => {
+                            distance < nearest_distance
+                                || (distance == nearest_distance && index < nearest_index)
+                        }
+                        None => true,
+                    };
+                    if distance <= max_distance && closer {
+                        nearest = Some((index, distance));
+                    }
+                }
+            }
+        }
+
+        nearest.map(|(index, _)| index)
+    }
+}
+
+fn min_coord(a: MapCoord, b: MapCoord) -> MapCoord {
+    MapCoord {
+        long: Degree(a.long.0.min(b.long.0)),
+        lat: Degree(a.lat.0.min(b.lat.0)),
+    }
+}
+
+fn max_coord(a: MapCoord, b: MapCoord) -> MapCoord {
+    MapCoord {
+        long: Degree(a.long.0.max(b.long.0)),
+        lat: Degree(a.lat.0.max(b.lat.0)),
+    }
+}
+
+/// The corners with the lowest and highest longitude and latitude of the box around two points
+fn bounding_box(a: MapCoord, b: MapCoord) -> (MapCoord, MapCoord) {
+    (min_coord(a, b), max_coord(a, b))
+}
+
+/// The corners with the lowest and highest longitude and latitude of the box around a path
+fn path_bounding_box(path: &[MapCoord]) -> (MapCoord, MapCoord) {
+    path.iter().fold((path[0], path[0]), |(min, max), coord| {
+        (min_coord(min, *coord), max_coord(max, *coord))
+    })
+}
+
+/// Cut the segment from `start` to `end` down to the part inside the box between `min` and `max`,
+/// or None if it doesn't pass through the box at all
+pub fn clip_segment(
+    start: PlaneCoord,
+    end: PlaneCoord,
+    min: PlaneCoord,
+    max: PlaneCoord,
+) -> Option<(PlaneCoord, PlaneCoord)> {
+    // Liang–Barsky: find the range of `t` in `start + t * (end - start)` that's inside the box
+    let dx = (end.x - start.x).0;
+    let dy = (end.y - start.y).0;
+    let mut t_min: f32 = 0.0;
+    let mut t_max: f32 = 1.0;
+
+    for (p, q) in [
+        (-dx, (start.x - min.x).0),
+        (dx, (max.x - start.x).0),
+        (-dy, (start.y - min.y).0),
+        (dy, (max.y - start.y).0),
+    ]
+    .iter()
+    {
+        if *p == 0.0 {
+            // Parallel to this edge, so either entirely inside or entirely outside of it
+            if *q < 0.0 {
+                return None;
+            }
+        } else {
+            let t = q / p;
+            if *p < 0.0 {
+                t_min = t_min.max(t);
+            } else {
+                t_max = t_max.min(t);
+            }
+        }
+    }
+
+    if t_min > t_max {
+        return None;
+    }
+
+    let point = |t: f32| PlaneCoord {
+        x: Degree(start.x.0 + t * dx),
+        y: Degree(start.y.0 + t * dy),
+    };
+    Some((point(t_min), point(t_max)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{load_stations_from_reader, Strictness};
+    use crate::map::{MapFrame, Viewport};
+    use ahash::RandomState;
+    use indexmap::IndexMap;
+    use std::collections::HashMap;
+
+    /// Stations with names of their own, so that each is its own group. The grid starts at
+    /// 139.0, 35.0, so the first column of cells ends at 139.05.
+    const STATIONS: &str = "station_cd,station_name,lon,lat
+1,一,139.0,35.0
+2,二,139.045,35.0
+3,三,139.051,35.0
+4,四,139.12,35.07
+5,五,139.3,35.2
+6,六,139.21,35.13
+7,七,139.16,35.01
+";
+
+    fn network() -> Network {
+        let stations =
+            load_stations_from_reader(STATIONS.as_bytes(), "stations.csv", Strictness::Strict)
+                .unwrap()
+                .data;
+        Network::new(
+            stations,
+            HashMap::with_hasher(RandomState::new()),
+            IndexMap::with_hasher(RandomState::new()),
+            IndexMap::with_hasher(RandomState::new()),
+        )
+    }
+
+    fn coord(long: f32, lat: f32) -> MapCoord {
+        MapCoord {
+            long: Degree(long),
+            lat: Degree(lat),
+        }
+    }
+
+    fn point(x: f32, y: f32) -> PlaneCoord {
+        PlaneCoord {
+            x: Degree(x),
+            y: Degree(y),
+        }
+    }
+
+    #[test]
+    fn segments_are_clipped_to_the_box() {
+        let (min, max) = (point(0.0, 0.0), point(10.0, 10.0));
+
+        let inside = (point(1.0, 1.0), point(2.0, 3.0));
+        assert_eq!(clip_segment(inside.0, inside.1, min, max), Some(inside));
+
+        assert_eq!(
+            clip_segment(point(11.0, 0.0), point(12.0, 5.0), min, max),
+            None
+        );
+
+        assert_eq!(
+            clip_segment(point(5.0, 5.0), point(15.0, 5.0), min, max),
+            Some((point(5.0, 5.0), point(10.0, 5.0)))
+        );
+    }
+
+    #[test]
+    fn nearest_group_looks_across_cells() {
+        let network = network();
+        let index = SpatialIndex::new(&network);
+        let name = |i: Option<usize>| i.map(|i| network.groups[i].name.as_str());
+
+        // The point is in the first column of cells, but the closest station is in the second
+        let nearest = index.nearest_group(&network, coord(139.0498, 35.0), Degree(0.01));
+        assert_eq!(name(nearest), Some("三"));
+
+        let nearest = index.nearest_group(&network, coord(139.044, 35.0005), Degree(0.01));
+        assert_eq!(name(nearest), Some("二"));
+
+        assert_eq!(
+            index.nearest_group(&network, coord(139.25, 35.1), Degree(0.01)),
+            None
+        );
+    }
+
+    #[test]
+    fn far_off_stations_make_the_cells_bigger() {
+        let boxes = [
+            (coord(139.0, 35.0), coord(139.0, 35.0)),
+            (coord(139.7, 35.6), coord(139.7, 35.6)),
+            (coord(-139.7, -35.6), coord(-139.7, -35.6)),
+        ];
+        let grid = Grid::new(boxes[2].0, boxes[1].1, &boxes);
+        assert_eq!(grid.columns, MAX_CELLS_PER_SIDE);
+        // The grid spans half as many degrees of latitude, which fit without growing the cells
+        assert!(grid.rows < MAX_CELLS_PER_SIDE);
+        assert_eq!(grid.starts.len(), grid.columns * grid.rows + 1);
+        assert_eq!(grid.query(coord(139.6, 35.5), coord(139.8, 35.7)), vec![1]);
+        assert_eq!(
+            grid.query(coord(-140.0, -36.0), coord(-139.0, -35.0)),
+            vec![2]
+        );
+
+        // Coordinates that aren't finite still make a grid that can be searched
+        let nowhere = coord(f32::NAN, f32::INFINITY);
+        let grid = Grid::new(boxes[0].0, nowhere, &[(nowhere, nowhere)]);
+        assert!(grid.columns >= 1 && grid.columns <= MAX_CELLS_PER_SIDE);
+        assert!(grid.rows >= 1 && grid.rows <= MAX_CELLS_PER_SIDE);
+        assert_eq!(grid.query(boxes[0].0, boxes[0].1).len(), 1);
+    }
+
+    #[test]
+    fn visible_groups_match_a_scan_of_every_group() {
+        let network = network();
+        let index = SpatialIndex::new(&network);
+        let viewport = Viewport {
+            width: 200,
+            height: 150,
+        };
+
+        let mut visible_counts = Vec::new();
+        for (upper_left, lower_right) in [
+            (coord(139.04, 35.08), coord(139.13, 35.0)),
+            (coord(139.1, 35.15), coord(139.25, 35.0)),
+            (coord(138.9, 35.3), coord(139.4, 34.9)),
+            (coord(140.0, 36.0), coord(140.1, 35.9)),
+        ] {
+            let map_frame = MapFrame::new(upper_left, lower_right, viewport);
+            let (area_upper_left, area_lower_right) = map_frame.visible_area();
+            let indexed: Vec<usize> = index
+                .groups_in(area_upper_left, area_lower_right)
+                .into_iter()
+                .filter(|i| map_frame.is_visible(network.groups[*i].coord))
+                .collect();
+            let scanned: Vec<usize> = (0..network.groups.len())
+                .filter(|i| map_frame.is_visible(network.groups[*i].coord))
+                .collect();
+            assert_eq!(indexed, scanned);
+            visible_counts.push(scanned.len());
+        }
+
+        // Some of the frames see part of the network, one sees all of it, and one sees none of it
+        assert_eq!(visible_counts[2], network.groups.len());
+        assert_eq!(visible_counts[3], 0);
+        assert!(visible_counts[..2]
+            .iter()
+            .all(|count| *count > 0 && *count < network.groups.len()));
+    }
+}
@@ -1,4 +1,4 @@
-use crate::data::Station;
+use crate::map::MapFrame;
 use derive_more::{Add, AddAssign, Div, From, Mul, Sub};
 
 #[derive(
@@ -42,19 +42,39 @@ impl Tile {
 
         TileIterator::new(upper_left, lower_right)
     }
+
+    /// Get a TileIterator for the box with the given side length whose middle is as close as it
+    /// can be to `center`, a position on the tile grid as given by `MapFrame::plane_tile_position`.
+    /// Unlike `get_box`, a box with an even side length is centered on the point rather than
+    /// reaching one more tile right and down.
+    pub fn box_around(center: (f32, f32), side_length: i32) -> TileIterator {
+        // The first tile is the one whose center is closest to half the box before the middle
+        let start = |middle: f32| (middle - (side_length - 1) as f32 / 2.0 + 0.5).floor() as i32;
+        let upper_left = Tile {
+            x: start(center.0).into(),
+            y: start(center.1).into(),
+        };
+        let lower_right = Tile {
+            x: (upper_left.x.0 + side_length - 1).into(),
+            y: (upper_left.y.0 + side_length - 1).into(),
+        };
+
+        TileIterator::new(upper_left, lower_right)
+    }
 }
 
-/// The items that can be present in the world's "base map". An empty tile is represented by not
-/// being present in the HashMap.
+/// The items that can be present in the world's "base map". These are kept small and `Copy`, since
+/// there's one for every visible tile.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
 pub enum TileStatus {
-    /// This tile should be used for drawing the font (station name) layer. Contains the index for
-    /// which font color to use.
-    Font(usize),
+    #[default]
+    Empty,
 
-    /// For simplicity, only one station can be "present" in a tile at once, even if there are
-    /// actually multiple ones overlapping. It shouldn't affect the drawing in anyway, since the
-    /// tile is the smallest unit we can draw.
-    Station(Station),
+    /// For simplicity, only one station group can be "present" in a tile at once, even if there
+    /// are actually multiple ones overlapping. It shouldn't affect the drawing in anyway, since the
+    /// tile is the smallest unit we can draw. Contains the index of the group in the network's
+    /// `groups`.
+    Station(u32),
 
     /// When you zoom in, each station can take up more than one tile. We still want to only keep
     /// the center/main tile as the one that "actually" as the station, since that's used to
@@ -62,7 +82,188 @@ pub enum TileStatus {
     /// "shadow" of the station.
     StationShadow,
 
-    Track,
+    /// Contains the index of the set of lines running over this tile, in the World's list of
+    /// track line sets. More than one line means that several lines share a corridor, and the
+    /// track is drawn striped in their colors. Also contains how much of the tile the track
+    /// covers, from 1 to 255, for smoothing its edges.
+    Track(u32, u8),
+}
+
+/// How much of a tile a station name covers, from 0 (none of it, so there's no name here) to 255,
+/// and the index of the font color to draw the name in. Names are kept apart from the base map,
+/// since they're blended over the tracks rather than replacing them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LabelCoverage {
+    pub font_index: u8,
+    pub coverage: u8,
+}
+
+/// A dense grid with one value for every tile visible in a MapFrame, plus a lookup from screen
+/// pixels to grid cells, so that drawing is a straight scan over the pixels.
+pub struct TileRaster<T> {
+    /// The top-left tile of the grid
+    origin: Tile,
+    width: usize,
+    height: usize,
+    cells: Vec<T>,
+
+    /// For each pixel column on the screen, which grid column it's in
+    pixel_columns: Vec<usize>,
+
+    /// For each pixel row on the screen, the index of the start of its grid row in `cells`
+    pixel_rows: Vec<usize>,
+}
+
+impl<T: Copy + Default> TileRaster<T> {
+    pub fn new() -> Self {
+        Self {
+            origin: Tile {
+                x: TilePos(0),
+                y: TilePos(0),
+            },
+            width: 0,
+            height: 0,
+            cells: Vec::new(),
+            pixel_columns: Vec::new(),
+            pixel_rows: Vec::new(),
+        }
+    }
+
+    /// Resize the grid to cover the tiles visible in `map_frame`, and clear all cells to the
+    /// default value. Reuses the existing allocation where possible.
+    pub fn reset(&mut self, map_frame: &MapFrame) {
+        let columns = map_frame.tile_columns();
+        let rows = map_frame.tile_rows();
+
+        let first_column = columns.first().copied().unwrap_or(TilePos(0));
+        let first_row = rows.first().copied().unwrap_or(TilePos(0));
+        self.origin = Tile {
+            x: first_column,
+            y: first_row,
+        };
+
+        // Tiles only ever increase going right and down the screen
+        self.width = columns
+            .last()
+            .map_or(0, |last| (last.0 - first_column.0) as usize + 1);
+        self.height = rows
+            .last()
+            .map_or(0, |last| (last.0 - first_row.0) as usize + 1);
+
+        self.pixel_columns.clear();
+        self.pixel_columns
+            .extend(columns.iter().map(|c| (c.0 - first_column.0) as usize));
+        self.pixel_rows.clear();
+        let width = self.width;
+        self.pixel_rows
+            .extend(rows.iter().map(|r| (r.0 - first_row.0) as usize * width));
+
+        self.cells.clear();
+        self.cells.resize(self.width * self.height, T::default());
+    }
+
+    fn index(&self, tile: Tile) -> Option<usize> {
+        let x = tile.x.0.checked_sub(self.origin.x.0)?;
+        let y = tile.y.0.checked_sub(self.origin.y.0)?;
+
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            None
+        } else {
+            Some(y as usize * self.width + x as usize)
+        }
+    }
+
+    /// The value for the given tile, or None if it isn't visible
+    pub fn get(&self, tile: Tile) -> Option<T> {
+        self.index(tile).map(|i| self.cells[i])
+    }
+
+    /// Set the value for the given tile. Does nothing if it isn't visible.
+    pub fn set(&mut self, tile: Tile, value: T) {
+        if let Some(i) = self.index(tile) {
+            self.cells[i] = value;
+        }
+    }
+
+    /// The value for the tile that contains the given screen pixel
+    pub fn at_pixel(&self, x: usize, y: usize) -> T {
+        self.cells[self.pixel_rows[y] + self.pixel_columns[x]]
+    }
+
+    /// Count the tiles whose value matches `matches`, in a table that can then tell how many
+    /// there are in any box of tiles
+    pub fn counts<F: Fn(T) -> bool>(&self, matches: F) -> TileCounts {
+        // Each entry is how many tiles match above and to the left of it, so it has an extra row
+        // and column of zeroes at the top and left
+        let stride = self.width + 1;
+        let mut sums = vec![0; stride * (self.height + 1)];
+        for y in 0..self.height {
+            let mut row_sum = 0;
+            for x in 0..self.width {
+                row_sum += matches(self.cells[y * self.width + x]) as u32;
+                sums[(y + 1) * stride + x + 1] = sums[y * stride + x + 1] + row_sum;
+            }
+        }
+
+        TileCounts {
+            origin: self.origin,
+            width: self.width,
+            height: self.height,
+            sums,
+        }
+    }
+}
+
+/// How many tiles of a TileRaster matched something, by box of tiles
+pub struct TileCounts {
+    origin: Tile,
+    width: usize,
+    height: usize,
+
+    /// Running totals from the top left, with an extra row and column at the start
+    sums: Vec<u32>,
+}
+
+impl Default for TileCounts {
+    /// No tiles at all
+    fn default() -> Self {
+        Self {
+            origin: Tile {
+                x: TilePos(0),
+                y: TilePos(0),
+            },
+            width: 0,
+            height: 0,
+            sums: vec![0],
+        }
+    }
+}
+
+impl TileCounts {
+    /// How many matching tiles there are from `upper_left` to `lower_right`, including the tiles
+    /// on the edges. Tiles outside the raster don't count.
+    pub fn count(&self, upper_left: Tile, lower_right: Tile) -> u32 {
+        let clamp = |value: i32, origin: TilePos, size: usize| {
+            (value - origin.0).max(0).min(size as i32) as usize
+        };
+        let left = clamp(upper_left.x.0, self.origin.x, self.width);
+        let right = clamp(lower_right.x.0 + 1, self.origin.x, self.width);
+        let top = clamp(upper_left.y.0, self.origin.y, self.height);
+        let bottom = clamp(lower_right.y.0 + 1, self.origin.y, self.height);
+        if left >= right || top >= bottom {
+            return 0;
+        }
+
+        let stride = self.width + 1;
+        let sum = |x: usize, y: usize| self.sums[y * stride + x];
+        sum(right, bottom) + sum(left, top) - sum(left, bottom) - sum(right, top)
+    }
+}
+
+impl<T: Copy + Default> Default for TileRaster<T> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 pub struct TileIterator {
@@ -113,3 +314,57 @@ impl Iterator for TileIterator {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tile(x: i32, y: i32) -> Tile {
+        Tile {
+            x: TilePos(x),
+            y: TilePos(y),
+        }
+    }
+
+    #[test]
+    fn boxes_around_a_point_are_centered_on_it() {
+        let corners = |center: (f32, f32), side_length: i32| {
+            let tiles: Vec<Tile> = Tile::box_around(center, side_length).collect();
+            assert_eq!(tiles.len(), (side_length * side_length) as usize);
+            (tiles[0], tiles[tiles.len() - 1])
+        };
+
+        assert_eq!(corners((5.0, -2.0), 1), (tile(5, -2), tile(5, -2)));
+        assert_eq!(corners((5.4, -2.4), 3), (tile(4, -3), tile(6, -1)));
+
+        // An even box goes whichever way puts its middle closer to the point
+        assert_eq!(corners((5.2, 5.8), 2), (tile(5, 5), tile(6, 6)));
+        assert_eq!(corners((4.8, 5.4), 4), (tile(3, 4), tile(6, 7)));
+        assert_eq!(corners((4.2, 5.6), 4), (tile(3, 4), tile(6, 7)));
+    }
+
+    #[test]
+    fn counts_cover_boxes_clipped_to_the_raster() {
+        // A 4x3 raster starting at (10, 20), with tiles set along a diagonal
+        let mut raster = TileRaster {
+            origin: tile(10, 20),
+            width: 4,
+            height: 3,
+            cells: vec![false; 12],
+            pixel_columns: Vec::new(),
+            pixel_rows: Vec::new(),
+        };
+        for (x, y) in [(10, 20), (11, 21), (12, 22), (13, 22)] {
+            raster.set(tile(x, y), true);
+        }
+        let counts = raster.counts(|set| set);
+
+        assert_eq!(counts.count(tile(10, 20), tile(13, 22)), 4);
+        assert_eq!(counts.count(tile(11, 21), tile(11, 21)), 1);
+        assert_eq!(counts.count(tile(11, 20), tile(13, 21)), 1);
+        assert_eq!(counts.count(tile(12, 20), tile(13, 21)), 0);
+        assert_eq!(counts.count(tile(0, 0), tile(11, 100)), 2);
+        assert_eq!(counts.count(tile(14, 20), tile(20, 30)), 0);
+        assert_eq!(TileCounts::default().count(tile(0, 0), tile(5, 5)), 0);
+    }
+}
@@ -1,24 +1,63 @@
 use crate::constants::*;
 use crate::{
-    data::{Station, StationId},
-    effect::EffectManager,
-    fonts::FontManager,
-    map::{zoom_ratio, Degree, MapFrame},
-    tile::{Tile, TileStatus},
+    camera::{Animation, Camera, CameraMove},
+    clock::SimClock,
+    data::{LineId, Network, StationGroup, StationGroupId, StationId},
+    effect::{Effect, EffectManager, Routing},
+    fonts::{FontManager, StationLabel},
+    inspector::StationInfo,
+    labels::TileRect,
+    map::{zoom_ratio, Degree, MapCoord, MapFrame, PlaneCoord, Viewport},
+    names::Language,
+    panel::{draw_panel, Corner, TEXT_COLOR, TITLE_COLOR},
+    projection::{self, Projection},
+    route::{RouteMetric, RoutePlan},
+    schedule::Timetable,
+    search::{self, Search},
+    spatial::{clip_segment, SpatialIndex},
+    tile::{LabelCoverage, Tile, TileRaster, TileStatus},
 };
 use ahash::RandomState;
-use indexmap::IndexMap;
 use line_drawing::Supercover;
-use std::{
-    collections::{HashMap, HashSet},
-    rc::Rc,
-    time::Duration,
-};
+use std::{collections::HashMap, rc::Rc, time::Duration};
 
+/// Used for tracks on lines that don't have an official color
 const TRACK_COLOR: [u8; 3] = [0x4F, 0x61, 0x6B];
+
+/// Where several lines share a track tile, each line's color is drawn as a diagonal stripe this
+/// many tiles wide, cycling through the lines in ID order. Stripes are anchored to tile positions,
+/// so they stay put while panning.
+const TRACK_STRIPE_WIDTH: i32 = 2;
+
+/// How far away from a station, in pixels, the mouse can be and still point at it
+const NEAREST_STATION_PIXELS: i32 = 3;
+
+/// How tall a viewport of the default size is, in degrees, after flying to a station that was
+/// searched for. Close enough for the names of the stations around it to show.
+const SEARCH_RESULT_HEIGHT: Degree = Degree(0.04);
+
+/// How many matches the search box lists at most
+const SEARCH_RESULTS_SHOWN: usize = 5;
+
+/// How many of a planned route's stops are listed at most
+const ROUTE_STOPS_SHOWN: usize = 8;
+
 const STATION_COLOR: [u8; 3] = [0xC4, 0x9D, 0xCF];
+/// The station under the mouse and the selected station, along with their tracks
+const HIGHLIGHT_COLOR: [u8; 3] = [0xF4, 0xF1, 0xFF];
+/// The planned route and the stations at either end of it
+const ROUTE_COLOR: [u8; 3] = [0xFF, 0x8A, 0x3D];
 const BACKGROUND_COLOR: [u8; 3] = [0x32, 0x2F, 0x3D];
 
+/// How opaque station names are where they're drawn over a track, so that the track still shows
+/// through them
+const LABEL_OVER_TRACK_OPACITY: f32 = 0.7;
+
+/// How much two lines' tracks have to cover a tile, from 0 to 255, for it to be striped in the
+/// colors of both. Where one only covers the edge of the other, the one covering more of the tile
+/// keeps it.
+const SHARED_TRACK_COVERAGE: u8 = 128;
+
 const FONT_COLORS: [[[u8; 3]; 10]; 3] = [
     // yellow
     [
@@ -61,26 +100,352 @@ const FONT_COLORS: [[[u8; 3]; 10]; 3] = [
     ],
 ];
 
+/// The distinct sets of lines running over track tiles. Tiles refer to a set by its index, so that
+/// the base map can stay compact, and each set's colors are looked up once instead of per pixel.
+#[derive(Default)]
+struct TrackLineSets {
+    /// Each set of lines, sorted by ID, along with the colors to draw its stripes in
+    sets: Vec<(Vec<LineId>, Vec<[u8; 3]>)>,
+    indices: HashMap<Vec<LineId>, u32, RandomState>,
+}
+
+impl TrackLineSets {
+    fn clear(&mut self) {
+        self.sets.clear();
+        self.indices.clear();
+    }
+
+    /// Get the index for the given set of lines, adding it if it's new
+    fn intern(&mut self, lines: &[LineId], network: &Network) -> u32 {
+        if let Some(index) = self.indices.get(lines) {
+            return *index;
+        }
+
+        let mut lines = lines.to_vec();
+        lines.sort();
+        lines.dedup();
+        if let Some(index) = self.indices.get(&lines) {
+            return *index;
+        }
+
+        let colors = if lines.is_empty() {
+            vec![TRACK_COLOR]
+        } else {
+            lines
+                .iter()
+                .map(|id| {
+                    network
+                        .lines
+                        .get(id)
+                        .and_then(|line| line.color)
+                        .unwrap_or(TRACK_COLOR)
+                })
+                .collect()
+        };
+
+        let index = self.sets.len() as u32;
+        self.sets.push((lines.clone(), colors));
+        self.indices.insert(lines, index);
+        index
+    }
+
+    /// Get the index for the union of two sets
+    fn merge(&mut self, index_1: u32, index_2: u32, network: &Network) -> u32 {
+        let mut lines = self.sets[index_1 as usize].0.clone();
+        lines.extend_from_slice(&self.sets[index_2 as usize].0);
+        self.intern(&lines, network)
+    }
+
+    /// The color to draw a track tile with. `diagonal` is the sum of the tile's x and y
+    /// positions, which picks the stripe.
+    fn color(&self, index: u32, diagonal: i32) -> &[u8; 3] {
+        let colors = &self.sets[index as usize].1;
+        let stripe_count = colors.len() as i32;
+        let stripe = diagonal.rem_euclid(stripe_count * TRACK_STRIPE_WIDTH) / TRACK_STRIPE_WIDTH;
+
+        &colors[stripe as usize]
+    }
+}
+
+/// Finds the tiles that straight pieces of track pass through. Pieces that are many screens long
+/// (only when zoomed in very far) are cut down to the area around the screen first, instead of
+/// walking every one of their tiles.
+struct TrackTiles {
+    max_tiles: i32,
+    clip_min: PlaneCoord,
+    clip_max: PlaneCoord,
+
+    /// The tiles on the screen
+    visible: TileRect,
+}
+
+impl TrackTiles {
+    fn new(map_frame: &MapFrame) -> Self {
+        let (upper_left, lower_right) = map_frame.visible_bounds();
+        let columns = map_frame.tile_columns();
+        let rows = map_frame.tile_rows();
+        let visible = TileRect {
+            left: columns.first().map_or(0, |c| c.0),
+            top: rows.first().map_or(0, |r| r.0),
+            right: columns.last().map_or(-1, |c| c.0),
+            bottom: rows.last().map_or(-1, |r| r.0),
+        };
+        Self {
+            max_tiles: 4 * (map_frame.viewport.width + map_frame.viewport.height) as i32,
+            clip_min: PlaneCoord {
+                x: upper_left.x - map_frame.width(),
+                y: lower_right.y - map_frame.height(),
+            },
+            clip_max: PlaneCoord {
+                x: lower_right.x + map_frame.width(),
+                y: upper_left.y + map_frame.height(),
+            },
+            visible,
+        }
+    }
+
+    /// The tiles along the piece of track from `start` to `end`, or None if it's nowhere near the
+    /// screen
+    fn piece(
+        &self,
+        map_frame: &MapFrame,
+        start: MapCoord,
+        end: MapCoord,
+    ) -> Option<Supercover<i32>> {
+        let start = map_frame.project(start);
+        let end = map_frame.project(end);
+        let mut tile1 = map_frame.plane_tile(start);
+        let mut tile2 = map_frame.plane_tile(end);
+        if (tile1.x.0 - tile2.x.0)
+            .abs()
+            .max((tile1.y.0 - tile2.y.0).abs())
+            > self.max_tiles
+        {
+            let (start, end) = clip_segment(start, end, self.clip_min, self.clip_max)?;
+            tile1 = map_frame.plane_tile(start);
+            tile2 = map_frame.plane_tile(end);
+        }
+
+        Some(Supercover::new(
+            (tile1.x.0, tile1.y.0),
+            (tile2.x.0, tile2.y.0),
+        ))
+    }
+
+    /// The tiles on the screen that a piece of track `width` tiles wide from `start` to `end`
+    /// covers at least part of, with how much of each it covers from 1 to 255, or None if it's
+    /// nowhere near the screen
+    fn smooth_piece(
+        &self,
+        map_frame: &MapFrame,
+        start: MapCoord,
+        end: MapCoord,
+        width: i32,
+    ) -> Option<impl Iterator<Item = (Tile, u8)>> {
+        let mut start = map_frame.project(start);
+        let mut end = map_frame.project(end);
+        let tile1 = map_frame.plane_tile(start);
+        let tile2 = map_frame.plane_tile(end);
+        if (tile1.x.0 - tile2.x.0)
+            .abs()
+            .max((tile1.y.0 - tile2.y.0).abs())
+            > self.max_tiles
+        {
+            let (clipped_start, clipped_end) =
+                clip_segment(start, end, self.clip_min, self.clip_max)?;
+            start = clipped_start;
+            end = clipped_end;
+        }
+
+        let start = map_frame.plane_tile_position(start);
+        let end = map_frame.plane_tile_position(end);
+
+        // A tile is covered entirely when its center is within half the width of the track, and
+        // fades out over the next tile
+        let half_width = width as f32 / 2.0;
+        let reach = half_width + 0.5;
+        let visible = self.visible;
+
+        // Go through the rows near the piece, and along each only as far as the piece could reach
+        let top = ((start.1.min(end.1) - reach).floor() as i32).max(visible.top);
+        let bottom = ((start.1.max(end.1) + reach).ceil() as i32).min(visible.bottom);
+        Some(
+            (top..=bottom)
+                .flat_map(move |y| {
+                    let (left, right) = row_extent(start, end, y as f32, reach);
+                    let left = (left.floor() as i32).max(visible.left);
+                    let right = (right.ceil() as i32).min(visible.right);
+                    (left..=right).map(move |x| (x, y))
+                })
+                .filter_map(move |(x, y)| {
+                    let distance = distance_to_segment((x as f32, y as f32), start, end);
+                    let coverage = ((reach - distance).min(1.0) * 255.0).round();
+                    if coverage < 1.0 {
+                        return None;
+                    }
+
+                    let tile = Tile {
+                        x: x.into(),
+                        y: y.into(),
+                    };
+                    Some((tile, coverage as u8))
+                }),
+        )
+    }
+}
+
+/// How far left and right the points within `reach` of the line segment between `start` and
+/// `end` can be, on the row at `y`. Empty, with the left after the right, if none are.
+fn row_extent(start: (f32, f32), end: (f32, f32), y: f32, reach: f32) -> (f32, f32) {
+    // The part of the segment that's within `reach` of the row, as fractions of the way along it
+    let dy = end.1 - start.1;
+    let (from, to) = if dy == 0.0 {
+        if (start.1 - y).abs() <= reach {
+            (0.0, 1.0)
+        } else {
+            (1.0, 0.0)
+        }
+    } else {
+        let t1 = (y - reach - start.1) / dy;
+        let t2 = (y + reach - start.1) / dy;
+        (t1.min(t2).max(0.0), t1.max(t2).min(1.0))
+    };
+    if from > to {
+        return (1.0, 0.0);
+    }
+
+    let x1 = start.0 + from * (end.0 - start.0);
+    let x2 = start.0 + to * (end.0 - start.0);
+    (x1.min(x2) - reach, x1.max(x2) + reach)
+}
+
+/// How far `point` is from the line segment between `start` and `end`
+fn distance_to_segment(point: (f32, f32), start: (f32, f32), end: (f32, f32)) -> f32 {
+    let (dx, dy) = (end.0 - start.0, end.1 - start.1);
+    let length_squared = dx * dx + dy * dy;
+    let t = if length_squared > 0.0 {
+        (((point.0 - start.0) * dx + (point.1 - start.1) * dy) / length_squared).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+
+    (point.0 - (start.0 + t * dx)).hypot(point.1 - (start.1 + t * dy))
+}
+
+/// Put a track carrying the lines in `line_set` on a tile of the base map, covering `coverage`
+/// of it from 1 to 255. Stations have priority over tracks, so nothing happens if there's already
+/// a station here.
+fn add_track_tile(
+    base_map: &mut TileRaster<TileStatus>,
+    track_line_sets: &mut TrackLineSets,
+    network: &Network,
+    tile: Tile,
+    line_set: u32,
+    coverage: u8,
+) {
+    let status = match base_map.get(tile) {
+        None | Some(TileStatus::Station(_)) | Some(TileStatus::StationShadow) => return,
+        // Another connection already passes through here, so if both of them run through most of
+        // the tile, it's shared by the lines of both
+        Some(TileStatus::Track(existing, existing_coverage)) if existing != line_set => {
+            let line_set = if existing_coverage.min(coverage) >= SHARED_TRACK_COVERAGE {
+                track_line_sets.merge(existing, line_set, network)
+            } else if existing_coverage >= coverage {
+                existing
+            } else {
+                line_set
+            };
+            TileStatus::Track(line_set, existing_coverage.max(coverage))
+        }
+        Some(TileStatus::Track(_, existing_coverage)) => {
+            TileStatus::Track(line_set, existing_coverage.max(coverage))
+        }
+        Some(TileStatus::Empty) => TileStatus::Track(line_set, coverage),
+    };
+    base_map.set(tile, status);
+}
+
+/// `over` drawn on top of `under`, `alpha` of the way from 0 (not at all) to 255 (covering it)
+fn blend(under: &[u8; 3], over: &[u8; 3], alpha: u8) -> [u8; 3] {
+    match alpha {
+        0 => *under,
+        255 => *over,
+        _ => {
+            let alpha = alpha as u32;
+            let mix = |under: u8, over: u8| {
+                ((over as u32 * alpha + under as u32 * (255 - alpha) + 127) / 255) as u8
+            };
+            [
+                mix(under[0], over[0]),
+                mix(under[1], over[1]),
+                mix(under[2], over[2]),
+            ]
+        }
+    }
+}
+
+/// How opaque a name covering `label_coverage` of a tile is, from 0 to 255, where a track covers
+/// `track_coverage` of it. Over the middle of a track it's only LABEL_OVER_TRACK_OPACITY as
+/// opaque, and less so towards the track's edge.
+fn label_over_track(label_coverage: u8, track_coverage: u8) -> u8 {
+    let opacity = 1.0 - (1.0 - LABEL_OVER_TRACK_OPACITY) * (track_coverage as f32 / 255.0);
+    (label_coverage as f32 * opacity).round() as u8
+}
+
 /// Representation of the application state.
 pub struct World {
-    /// Just a collection of all Stations in Japan. Loaded once and never changes.
-    /// key: station ID
-    stations: Rc<IndexMap<StationId, Station, RandomState>>,
-
-    /// Static collection of all station connections. Loaded once and never changes.
-    /// key: station ID
-    /// value: set of station IDs connected to the key station
-    connections: Rc<HashMap<StationId, HashSet<StationId, RandomState>, RandomState>>,
+    /// All stations in Japan, how they're connected, and the lines running on them. Loaded once
+    /// and never changes.
+    network: Rc<Network>,
 
     /// The area the user is currently looking at
     map_frame: MapFrame,
 
     effect_manager: EffectManager,
 
+    /// The simulated time of day
+    clock: SimClock,
+
     font_manager: FontManager,
 
+    /// Grid buckets over the network's stations and tracks, for finding the visible ones
+    spatial_index: SpatialIndex,
+
     /// Which tiles have stations/tracks on them. Recalculated on zoom/pan.
-    base_map: HashMap<Tile, TileStatus, RandomState>,
+    base_map: TileRaster<TileStatus>,
+
+    /// Which tiles station names are drawn over, blended with whatever's in the base map.
+    /// Recalculated along with it.
+    label_map: TileRaster<LabelCoverage>,
+
+    /// Whether the map frame changed since the base map was last calculated. The base map is only
+    /// calculated again when it's drawn, so moving the view several times in one frame, like when
+    /// dragging the map while it's still zooming, doesn't calculate it more than once.
+    base_map_stale: bool,
+
+    /// The sets of lines that the track tiles in `base_map` refer to
+    track_line_sets: TrackLineSets,
+
+    /// The colors effects are drawing over the base map. Recalculated every frame.
+    effect_map: TileRaster<Option<[u8; 3]>>,
+
+    /// The station group under the mouse
+    hovered: Option<StationGroupId>,
+
+    /// The station group whose details are shown
+    selected: Option<StationGroupId>,
+
+    /// The search being typed in, if the search box is open
+    search: Option<Search>,
+
+    /// Moves the map frame on its own, when it's animated or flung
+    camera: Camera,
+
+    /// The route being planned between two stations
+    route_plan: RoutePlan,
+
+    /// Which names stations are shown by
+    language: Language,
 
     /// The Duration that elapsed between calls to `update`. Used to determine how many steps
     /// should be processed per `update` call.
@@ -88,38 +453,251 @@ pub struct World {
 }
 
 impl World {
-    pub fn new(
-        stations: IndexMap<StationId, Station, RandomState>,
-        connections: HashMap<StationId, HashSet<StationId, RandomState>, RandomState>,
-    ) -> Self {
-        let stations = Rc::new(stations);
-        let connections = Rc::new(connections);
-        Self {
-            stations: stations.clone(),
-            connections: connections.clone(),
+    /// Set up the world for the given network, drawing text with the built-in font. All
+    /// randomness in the simulation comes from `seed`, so two worlds with the same seed that are
+    /// stepped the same number of frames end up in the same state.
+    pub fn new(network: Network, seed: u64) -> Self {
+        let font_manager = FontManager::new().expect("the built-in font should always load");
+        Self::with_font_manager(network, seed, font_manager)
+    }
+
+    /// Set up the world like `new`, but drawing text with the fonts of `font_manager`
+    pub fn with_font_manager(network: Network, seed: u64, font_manager: FontManager) -> Self {
+        let network = Rc::new(network);
+        let mut world = Self {
+            network: network.clone(),
             map_frame: MapFrame::default(),
-            effect_manager: EffectManager::new(stations, connections),
-            font_manager: FontManager::new(),
-            base_map: HashMap::with_hasher(RandomState::new()),
+            spatial_index: SpatialIndex::new(&network),
+            effect_manager: EffectManager::new(network, seed),
+            clock: SimClock::default(),
+            font_manager,
+            base_map: TileRaster::new(),
+            label_map: TileRaster::new(),
+            base_map_stale: true,
+            track_line_sets: TrackLineSets::default(),
+            effect_map: TileRaster::new(),
+            hovered: None,
+            selected: None,
+            search: None,
+            camera: Camera::default(),
+            route_plan: RoutePlan::default(),
+            language: Language::default(),
             dt: Duration::default(),
+        };
+
+        // The base map always has to match the map frame
+        world.update_base_map();
+        world
+    }
+
+    /// The area the user is currently looking at
+    pub fn map_frame(&self) -> &MapFrame {
+        &self.map_frame
+    }
+
+    /// The simulated time of day, and how fast it's passing
+    pub fn clock(&self) -> &SimClock {
+        &self.clock
+    }
+
+    /// Change the speed of the clock, pause it, or jump to another time of day. When running to a
+    /// timetable, the trains catch up with a new time on the next step.
+    pub fn clock_mut(&mut self) -> &mut SimClock {
+        &mut self.clock
+    }
+
+    /// Run trains according to the given timetable from now on
+    pub fn set_timetable(&mut self, timetable: Timetable) {
+        self.effect_manager
+            .set_timetable(Rc::new(timetable), self.clock.time());
+    }
+
+    /// Change how trains that aren't running to a timetable pick their routes
+    pub fn set_routing(&mut self, routing: Routing) {
+        self.effect_manager.set_routing(routing);
+    }
+
+    /// The effects currently running, oldest first
+    pub fn effects(&self) -> &[Box<dyn Effect>] {
+        &self.effect_manager.effects
+    }
+
+    /// Jump straight to looking at the given area
+    pub fn set_map_frame(&mut self, map_frame: MapFrame) {
+        self.camera.stop();
+        self.map_frame = map_frame;
+        self.base_map_stale = true;
+    }
+
+    /// How the map is currently flattened onto the screen
+    pub fn projection(&self) -> &dyn Projection {
+        self.map_frame.projection()
+    }
+
+    /// Switch to another projection, keeping the same place in the middle of the screen
+    pub fn set_projection(&mut self, projection: Rc<dyn Projection>) {
+        self.camera.stop();
+        self.map_frame = self.map_frame.with_projection(projection);
+        self.base_map_stale = true;
+    }
+
+    /// Switch to the next of the available projections
+    pub fn next_projection(&mut self) {
+        let central_meridian = projection::central_meridian(self.network.bounds());
+        self.set_projection(projection::next_projection(
+            self.projection(),
+            central_meridian,
+        ));
+    }
+
+    /// Which names stations are shown by
+    pub fn language(&self) -> Language {
+        self.language
+    }
+
+    /// Show stations by their names in `language`, on the map and in the panels
+    pub fn set_language(&mut self, language: Language) {
+        self.language = language;
+
+        // The station names on the map have to be placed again
+        self.base_map_stale = true;
+    }
+
+    /// Move the view over the next moment to have `center` in the middle, zoomed so that a
+    /// viewport of the default size would be `height` degrees tall. Zooming or panning on the way
+    /// stops it.
+    pub fn fly_to(&mut self, center: MapCoord, height: Degree) {
+        self.camera.animate(
+            Animation::Flight,
+            self.map_frame.clone(),
+            self.map_frame.centered_on(center, height),
+        );
+    }
+
+    /// Whether the view is still moving on its own, after `fly_to`, zooming or flinging the map
+    pub fn is_moving(&self) -> bool {
+        self.camera.is_moving()
+    }
+
+    /// Move the view along with the camera
+    fn update_camera(&mut self, dt: &Duration) {
+        match self.camera.update(dt) {
+            Some(CameraMove::To(map_frame)) => {
+                self.map_frame = map_frame;
+                self.base_map_stale = true;
+            }
+            Some(CameraMove::Pan(diff_x, diff_y)) => self.shift(diff_x, diff_y),
+            None => {}
         }
     }
 
-    pub fn init(&mut self) {
-        self.update_base_map();
+    /// The station groups with names or readings containing `query`, best matches first
+    pub fn find_stations(&self, query: &str) -> Vec<&StationGroup> {
+        search::find_stations(&self.network, query)
+    }
+
+    /// Open the search box, empty
+    pub fn open_search(&mut self) {
+        self.search = Some(Search::default());
+    }
+
+    pub fn close_search(&mut self) {
+        self.search = None;
+    }
+
+    /// The search being typed in, if the search box is open
+    pub fn search(&self) -> Option<&Search> {
+        self.search.as_ref()
+    }
+
+    /// Change what's typed into the search box
+    pub fn set_search_query(&mut self, query: String) {
+        if let Some(search) = &mut self.search {
+            search.set_query(&self.network, query);
+        }
+    }
+
+    /// Pick another of the search's matches, `step` places down the list (or up, if negative)
+    pub fn move_search_choice(&mut self, step: isize) {
+        if let Some(search) = &mut self.search {
+            search.move_choice(step);
+        }
+    }
+
+    /// Close the search box, then select the picked match and fly to it
+    pub fn confirm_search(&mut self) {
+        let choice = self.search.take().and_then(|search| search.choice());
+        if let Some(group) = choice.and_then(|id| self.network.groups.get(&id)) {
+            let coord = group.coord;
+            self.selected = Some(group.id);
+            self.fly_to(coord, SEARCH_RESULT_HEIGHT);
+        }
     }
 
+    /// The rows of the search box: what's been typed, and the first few matches
+    fn search_rows(&self, search: &Search) -> Vec<(String, [u8; 3])> {
+        let mut rows = vec![(format!("Search: {}_", search.query()), TEXT_COLOR)];
+
+        // Keep the picked match in the list, even when it's far down
+        let first = (search.choice_index() + 1).saturating_sub(SEARCH_RESULTS_SHOWN);
+        for (i, id) in search
+            .matches()
+            .iter()
+            .enumerate()
+            .skip(first)
+            .take(SEARCH_RESULTS_SHOWN)
+        {
+            if let Some(group) = self.network.groups.get(id) {
+                // The reading is only of use to those reading the Japanese name
+                let name = group.display_name(self.language);
+                let row = match &group.kana {
+                    Some(kana) if self.language != Language::Romaji => {
+                        format!("{} ({})", name, kana)
+                    }
+                    _ => name.into_owned(),
+                };
+                let color = if i == search.choice_index() {
+                    TITLE_COLOR
+                } else {
+                    TEXT_COLOR
+                };
+                rows.push((row, color));
+            }
+        }
+        if !search.query().is_empty() && search.matches().is_empty() {
+            rows.push(("No matches".to_owned(), TEXT_COLOR));
+        }
+
+        rows
+    }
+
+    /// Change the size, in pixels, of the area the world is drawn into. The zoom level stays the
+    /// same, so a bigger viewport shows more of the map.
+    pub fn resize(&mut self, viewport: Viewport) {
+        self.camera.stop();
+        self.map_frame.resize(viewport);
+        self.base_map_stale = true;
+    }
+
+    /// Zoom in or out around the given screen pixel, by an amount depending on how far the mouse
+    /// wheel was turned. The view eases into the new zoom level over a moment, and turning the
+    /// wheel again on the way zooms further from where it was headed.
     pub fn zoom(&mut self, mouse_cell: (isize, isize), scroll_diff: f32) {
+        let mut target = match self.camera.target(Animation::Zoom) {
+            Some(target) => target.clone(),
+            None => self.map_frame.clone(),
+        };
         let (mouse_x, mouse_y) = mouse_cell;
 
         // How far right and down, proportionally, the user is zooming in/out at
-        let x_factor: f32 = mouse_x as f32 / SCREEN_WIDTH as f32;
-        let y_factor: f32 = mouse_y as f32 / SCREEN_HEIGHT as f32;
+        let viewport = target.viewport;
+        let x_factor: f32 = mouse_x as f32 / viewport.width as f32;
+        let y_factor: f32 = mouse_y as f32 / viewport.height as f32;
 
         let ratio = zoom_ratio(scroll_diff);
 
-        let current_x_size = self.map_frame.width();
-        let current_y_size = self.map_frame.height();
+        let current_x_size = target.width();
+        let current_y_size = target.height();
 
         let target_x_size = current_x_size * ratio.into();
         let target_y_size = current_y_size * ratio.into();
@@ -133,13 +711,16 @@ impl World {
         let amount_to_change_x: Degree = target_x_size - current_x_size;
 
         // For the left side, a positive change is zooming in, so we have to multiply by -1 here
-        let left_change: Degree = amount_to_change_x * (x_factor * -1.0).into();
+        let left_change: Degree = amount_to_change_x * (-x_factor).into();
         let right_change: Degree = amount_to_change_x * (1.0 - x_factor).into();
 
-        let new_left: Degree = self.map_frame.upper_left.long + left_change;
-        let new_right: Degree = self.map_frame.lower_right.long + right_change;
+        let new_left: Degree = target.upper_left.x + left_change;
+        let new_right: Degree = target.lower_right.x + right_change;
 
-        let new_length: Degree = new_right - new_left;
+        // Compare the width the default viewport would have, so that the zoom limits are the same
+        // at any viewport size
+        let new_length: Degree =
+            (new_right - new_left) * Degree(DEFAULT_SCREEN_WIDTH as f32 / viewport.width as f32);
         if new_length > MAX_ZOOM || new_length < MIN_ZOOM {
             return;
         }
@@ -148,175 +729,484 @@ impl World {
         let top_change = amount_to_change_y * y_factor.into();
 
         // For the bottom side, a positive change is zooming in
-        let bottom_change = amount_to_change_y * ((1.0 - y_factor) * -1.0).into();
+        let bottom_change = amount_to_change_y * (y_factor - 1.0).into();
 
-        self.map_frame.upper_left.long += left_change;
-        self.map_frame.lower_right.long += right_change;
+        target.upper_left.x += left_change;
+        target.lower_right.x += right_change;
 
-        self.map_frame.upper_left.lat += top_change;
-        self.map_frame.lower_right.lat += bottom_change;
+        target.upper_left.y += top_change;
+        target.lower_right.y += bottom_change;
 
-        // Zooming requires updating static positions of stations, tracks, fonts
-        self.update_base_map();
+        self.camera
+            .animate(Animation::Zoom, self.map_frame.clone(), target);
     }
 
-    /// Pan the current MapFrame by the specified amount in pixels
+    /// Drag the map by the specified amount in pixels. This stops the view moving on its own.
     pub fn pan(&mut self, diff_x: isize, diff_y: isize) {
+        self.camera.drag(diff_x, diff_y);
+        self.shift(diff_x, diff_y);
+    }
+
+    /// Let go of the map after dragging it. If it was moving fast enough, it drifts on and slows
+    /// to a stop.
+    pub fn end_pan(&mut self) {
+        self.camera.release();
+    }
+
+    /// Move the current MapFrame by the specified amount in pixels
+    fn shift(&mut self, diff_x: isize, diff_y: isize) {
+        if diff_x == 0 && diff_y == 0 {
+            return;
+        }
+
         // diff_x and diff_y are the number of pixels to move, but we have to translate this to the
         // number of degrees to move
         let (degrees_per_pixel_x, degrees_per_pixel_y) = self.map_frame.get_degrees_per_pixel();
 
-        self.map_frame.upper_left.long -= Degree(diff_x as f32) * degrees_per_pixel_x;
-        self.map_frame.lower_right.long -= Degree(diff_x as f32) * degrees_per_pixel_x;
-        self.map_frame.upper_left.lat += Degree(diff_y as f32) * degrees_per_pixel_y;
-        self.map_frame.lower_right.lat += Degree(diff_y as f32) * degrees_per_pixel_y;
+        self.map_frame.upper_left.x -= Degree(diff_x as f32) * degrees_per_pixel_x;
+        self.map_frame.lower_right.x -= Degree(diff_x as f32) * degrees_per_pixel_x;
+        self.map_frame.upper_left.y += Degree(diff_y as f32) * degrees_per_pixel_y;
+        self.map_frame.lower_right.y += Degree(diff_y as f32) * degrees_per_pixel_y;
 
         // Panning requires updating static positions of stations, tracks, fonts
-        self.update_base_map();
+        self.base_map_stale = true;
     }
 
     /// Update all visible tiles in regards to whether they contain stations/tracks.
     fn update_base_map(&mut self) {
-        self.base_map.clear();
+        self.base_map_stale = false;
+        self.base_map.reset(&self.map_frame);
+        self.label_map.reset(&self.map_frame);
+        self.track_line_sets.clear();
         let station_width = self.map_frame.station_width();
         let track_width = self.map_frame.track_width();
 
         let map_frame = &self.map_frame;
+        let network = &self.network;
+        let language = self.language;
 
-        // Only look at visible stations, all others would be wasted computation
-        for station in self
-            .stations
-            .values()
-            .filter(|s| map_frame.is_visible(s.coord))
-        {
-            let station_tile = self.map_frame.get_tile(station.coord);
+        // The station groups drawn, along with their center tile, for labelling afterwards
+        let mut drawn_groups: Vec<(Tile, usize)> = Vec::new();
 
-            for tile in Tile::get_box(station_tile, station_width) {
-                let status = if tile == station_tile {
-                    TileStatus::Station((*station).clone())
+        // Only look at visible stations, all others would be wasted computation. Stations are
+        // drawn per group, so that a transfer station is a single box with a single name.
+        let (upper_left, lower_right) = map_frame.visible_area();
+        for group_index in self.spatial_index.groups_in(upper_left, lower_right) {
+            let group = match network.groups.get_index(group_index) {
+                Some((_, group)) if map_frame.is_visible(group.coord) => group,
+                _ => continue,
+            };
+
+            let group_tile = map_frame.get_tile(group.coord);
+            drawn_groups.push((group_tile, group_index));
+
+            for tile in Tile::get_box(group_tile, station_width) {
+                let status = if tile == group_tile {
+                    TileStatus::Station(group_index as u32)
                 } else {
                     TileStatus::StationShadow
                 };
-                self.base_map.insert(tile, status);
+                self.base_map.set(tile, status);
             }
-            if let Some(connected_stations) = self.connections.get(&station.id) {
-                for other_station_id in connected_stations {
-                    let other_station = self.stations.get(other_station_id).unwrap();
-
-                    let tile1 = self.map_frame.get_tile(station.coord);
-                    let tile2 = self.map_frame.get_tile(other_station.coord);
-
-                    for (inner_x, inner_y) in
-                        Supercover::new((tile1.x.0, tile1.y.0), (tile2.x.0, tile2.y.0))
-                    {
-                        let inner_tile = Tile {
-                            x: inner_x.into(),
-                            y: inner_y.into(),
-                        };
-
-                        for tile in Tile::get_box(inner_tile, track_width) {
-                            match self.base_map.get(&tile) {
-                                // Stations have priority over tracks, so don't do anything if a
-                                // station was already present.
-                                Some(TileStatus::Station(_)) | Some(TileStatus::StationShadow) => {}
-                                _ => {
-                                    self.base_map.insert(tile, TileStatus::Track);
-                                }
-                            };
-                        }
+        }
+
+        let track_tiles = TrackTiles::new(map_frame);
+
+        for segment in self.spatial_index.segments_in(upper_left, lower_right) {
+            let lines = network.lines_between(segment.start_station_id, segment.end_station_id);
+            let line_set = self.track_line_sets.intern(lines, network);
+
+            // Tracks with their own geometry are drawn one straight piece at a time. Zoomed out,
+            // tracks are a single tile wide and drawn crisp; zoomed in, their edges are smoothed.
+            for piece in segment.path.windows(2) {
+                if track_width > 1 {
+                    let piece_tiles =
+                        track_tiles.smooth_piece(map_frame, piece[0], piece[1], track_width);
+                    for (tile, coverage) in piece_tiles.into_iter().flatten() {
+                        add_track_tile(
+                            &mut self.base_map,
+                            &mut self.track_line_sets,
+                            network,
+                            tile,
+                            line_set,
+                            coverage,
+                        );
+                    }
+                    continue;
+                }
+
+                let piece_tiles = match track_tiles.piece(map_frame, piece[0], piece[1]) {
+                    Some(piece_tiles) => piece_tiles,
+                    None => continue,
+                };
+                for (inner_x, inner_y) in piece_tiles {
+                    let inner_tile = Tile {
+                        x: inner_x.into(),
+                        y: inner_y.into(),
+                    };
+                    for tile in Tile::get_box(inner_tile, track_width) {
+                        add_track_tile(
+                            &mut self.base_map,
+                            &mut self.track_line_sets,
+                            network,
+                            tile,
+                            line_set,
+                            u8::MAX,
+                        );
                     }
                 }
             }
         }
 
         // We've just calculated which tiles have a station, so pass this info to the FontManager
-        // to get the tiles to draw station names on.
-        let mut tiles_with_station: Vec<(&Tile, &String, &StationId)> = self
-            .base_map
+        // to get the tiles to draw station names on. Groups whose center tile was covered by
+        // another group don't get a name, but groups just off the edge of the screen do, since
+        // their names can reach into view. Stations on more lines are more important, so their
+        // names are placed first.
+        let base_map = &self.base_map;
+        let mut stations: Vec<StationLabel> = drawn_groups
             .iter()
-            .filter_map(|(tile, status)| match status {
-                TileStatus::Station(station) => {
-                    if self.map_frame.is_visible(station.coord) {
-                        Some((tile, &station.name, &station.id))
-                    } else {
-                        None
-                    }
-                }
-                _ => None,
+            .filter(|(tile, index)| {
+                base_map
+                    .get(*tile)
+                    .map_or(true, |status| status == TileStatus::Station(*index as u32))
+            })
+            .filter_map(|(tile, index)| {
+                network
+                    .groups
+                    .get_index(*index)
+                    .map(|(_, group)| StationLabel {
+                        tile: *tile,
+                        name: group.display_name(language),
+                        group_id: group.id,
+                        priority: group.members.len(),
+                    })
             })
             .collect();
 
-        // Sort by tile for a consistent order so that station names don't overlap each other
-        // randomly as you zoom in
-        tiles_with_station.sort_by(|(t1, _, _), (t2, _, _)| (**t1).cmp(&t2));
-
         // Eliminate duplicate names on the same tile; these will just create visual noise
-        tiles_with_station.dedup_by(|(t1, name1, _), (t2, name2, _)| t1 == t2 && name1 == name2);
+        stations.sort_by(|s1, s2| (s1.tile, &s1.name).cmp(&(s2.tile, &s2.name)));
+        stations.dedup_by(|s1, s2| s1.tile == s2.tile && s1.name == s2.name);
 
-        for (tile, font_index) in self
-            .font_manager
-            .get_font_tiles(&self.map_frame, tiles_with_station)
-        {
-            // Fonts have a lower priority than stations and tracks
-            match self.base_map.get(&tile) {
-                Some(TileStatus::Station(_))
-                | Some(TileStatus::StationShadow)
-                | Some(TileStatus::Track) => {}
-                _ => {
-                    self.base_map.insert(tile, TileStatus::Font(font_index));
-                }
+        let station_tiles: Vec<Tile> = drawn_groups.iter().map(|(tile, _)| *tile).collect();
+        let track_tiles = base_map.counts(|status| matches!(status, TileStatus::Track(..)));
+        for (tile, font_index, coverage) in self.font_manager.get_font_tiles(
+            &self.map_frame,
+            &stations,
+            &station_tiles,
+            &track_tiles,
+        ) {
+            let coverage = (coverage * 255.0).round() as u8;
+            if self
+                .label_map
+                .get(tile)
+                .is_some_and(|label| label.coverage < coverage)
+            {
+                let label = LabelCoverage {
+                    font_index: font_index as u8,
+                    coverage,
+                };
+                self.label_map.set(tile, label);
             }
         }
     }
 
-    pub fn inspect(&self, mouse_cell: (isize, isize)) {
+    /// The station group closest to the given screen pixel, if there's one close enough to be what
+    /// the mouse is pointing at
+    pub fn nearest_station(&self, mouse_cell: (isize, isize)) -> Option<&StationGroup> {
         let (mx, my) = mouse_cell;
-        let coord = self.map_frame.get_map_coord(mx as i16, my as i16);
-        let tile = self.map_frame.get_tile(coord);
-        if let Some(TileStatus::Station(station)) = self.base_map.get(&tile) {
-            println!("{}", station.name);
+        let coord = self.map_frame.get_map_coord(mx as i32, my as i32);
+
+        // Stations can be hard to hit exactly, so allow for being a few pixels off, or as far as
+        // the edge of the station's box when zoomed in. A pixel doesn't cover the same number of
+        // degrees across as it does down, so go by whichever is more.
+        let (degrees_per_pixel_x, degrees_per_pixel_y) = self.map_frame.get_degrees_per_pixel();
+        let degrees_per_pixel = Degree(degrees_per_pixel_x.0.max(degrees_per_pixel_y.0));
+        let pixels = NEAREST_STATION_PIXELS.max(self.map_frame.station_width());
+        let max_distance = degrees_per_pixel * Degree(pixels as f32);
+
+        self.spatial_index
+            .nearest_group(&self.network, coord, max_distance)
+            .and_then(|index| self.network.groups.get_index(index))
+            .map(|(_, group)| group)
+    }
+
+    /// Highlight the station group at the given screen pixel, if there is one
+    pub fn hover(&mut self, mouse_cell: (isize, isize)) {
+        self.hovered = self.nearest_station(mouse_cell).map(|group| group.id);
+    }
+
+    /// Show the details of the station group at the given screen pixel, or hide them if there's no
+    /// station there
+    pub fn select(&mut self, mouse_cell: (isize, isize)) {
+        self.selected = self.nearest_station(mouse_cell).map(|group| group.id);
+    }
+
+    /// Show the details of the given station group, or hide them
+    pub fn select_station(&mut self, group_id: Option<StationGroupId>) {
+        self.selected = group_id;
+    }
+
+    /// The station group whose details are shown
+    pub fn selected_station(&self) -> Option<StationGroupId> {
+        self.selected
+    }
+
+    /// What the details panel shows about the given station group
+    pub fn station_info(&self, group_id: StationGroupId) -> Option<StationInfo> {
+        StationInfo::new(&self.network, &self.effect_manager, group_id, self.language)
+    }
+
+    /// The tiles of the given station group's box and of the tracks leading away from it
+    fn station_highlight(&self, group_id: StationGroupId) -> Vec<Tile> {
+        let map_frame = &self.map_frame;
+        let network = &self.network;
+        let group = match network.groups.get(&group_id) {
+            Some(group) => group,
+            None => return Vec::new(),
+        };
+
+        let track_tiles = TrackTiles::new(map_frame);
+        let mut tiles = Vec::new();
+        for station_id in &group.members {
+            for neighbor_id in network.connections.get(station_id).into_iter().flatten() {
+                self.track_highlight(&track_tiles, *station_id, *neighbor_id, &mut tiles);
+            }
+        }
+
+        // The station goes on top of its tracks
+        tiles.extend(Tile::get_box(
+            map_frame.get_tile(group.coord),
+            map_frame.station_width(),
+        ));
+        tiles
+    }
+
+    /// The tiles of the planned route's tracks and of the stations at either end
+    fn route_highlight(&self) -> Vec<Tile> {
+        let map_frame = &self.map_frame;
+        let track_tiles = TrackTiles::new(map_frame);
+        let mut tiles = Vec::new();
+        if let Some(route) = &self.route_plan.route {
+            for pair in route.stations.windows(2) {
+                self.track_highlight(&track_tiles, pair[0], pair[1], &mut tiles);
+            }
+        }
+
+        let ends = [self.route_plan.origin, self.route_plan.destination];
+        for group in ends
+            .iter()
+            .flatten()
+            .filter_map(|id| self.network.groups.get(id))
+        {
+            tiles.extend(Tile::get_box(
+                map_frame.get_tile(group.coord),
+                map_frame.station_width(),
+            ));
+        }
+        tiles
+    }
+
+    /// Add the tiles of the track between two stations to `tiles`
+    fn track_highlight(
+        &self,
+        track_tiles: &TrackTiles,
+        start_station_id: StationId,
+        end_station_id: StationId,
+        tiles: &mut Vec<Tile>,
+    ) {
+        let map_frame = &self.map_frame;
+        let track_width = map_frame.track_width();
+        let path = match self.network.track_path(start_station_id, end_station_id) {
+            Some(path) => path,
+            None => return,
+        };
+
+        for piece in path.windows(2) {
+            // Zoomed in, the highlight covers the same tiles as the smoothed track
+            if track_width > 1 {
+                let piece_tiles =
+                    track_tiles.smooth_piece(map_frame, piece[0], piece[1], track_width);
+                tiles.extend(piece_tiles.into_iter().flatten().map(|(tile, _)| tile));
+                continue;
+            }
+
+            for (x, y) in track_tiles
+                .piece(map_frame, piece[0], piece[1])
+                .into_iter()
+                .flatten()
+            {
+                let inner_tile = Tile {
+                    x: x.into(),
+                    y: y.into(),
+                };
+                tiles.extend(Tile::get_box(inner_tile, track_width));
+            }
         }
     }
 
-    /// Draw the `World` state to the frame buffer.
+    /// Where the route planner is at
+    pub fn route_plan(&self) -> &RoutePlan {
+        &self.route_plan
+    }
+
+    /// Plan a route starting from the given station group, or from nowhere yet
+    pub fn set_route_origin(&mut self, group_id: Option<StationGroupId>) {
+        self.route_plan.origin = group_id;
+        self.route_plan.update(&self.network);
+    }
+
+    /// Plan a route ending at the given station group, or at nowhere yet
+    pub fn set_route_destination(&mut self, group_id: Option<StationGroupId>) {
+        self.route_plan.destination = group_id;
+        self.route_plan.update(&self.network);
+    }
+
+    /// Change what the planned route is shortest by
+    pub fn set_route_metric(&mut self, metric: RouteMetric) {
+        self.route_plan.metric = metric;
+        self.route_plan.update(&self.network);
+    }
+
+    /// Forget the planned route and both of its ends
+    pub fn clear_route(&mut self) {
+        self.route_plan = RoutePlan {
+            metric: self.route_plan.metric,
+            ..RoutePlan::default()
+        };
+    }
+
+    /// Send a train along the planned route. Returns false if there's no route yet, or no room
+    /// for another train.
+    pub fn send_train_along_route(&mut self) -> bool {
+        match &self.route_plan.route {
+            Some(route) => self.effect_manager.spawn_train(&route.stations),
+            None => false,
+        }
+    }
+
+    /// The rows of the route panel: the route's ends, size and stops, with the stops in the
+    /// middle left out if there are too many to list
+    fn route_rows(&self) -> Vec<(String, [u8; 3])> {
+        let plan = &self.route_plan;
+        let name = |id: Option<StationGroupId>| {
+            id.and_then(|id| self.network.groups.get(&id))
+                .map_or("?".into(), |group| group.display_name(self.language))
+        };
+        let mut rows = vec![(
+            format!("{} → {}", name(plan.origin), name(plan.destination)),
+            TITLE_COLOR,
+        )];
+
+        match &plan.route {
+            Some(route) => {
+                let stops: Vec<String> = route
+                    .stops(&self.network)
+                    .iter()
+                    .map(|group| group.display_name(self.language).into_owned())
+                    .collect();
+                rows.push((
+                    format!(
+                        "{} stops, {:.1} km (by {})",
+                        stops.len().saturating_sub(1),
+                        route.length.kilometers(),
+                        plan.metric.name()
+                    ),
+                    TEXT_COLOR,
+                ));
+
+                if stops.len() <= ROUTE_STOPS_SHOWN {
+                    rows.extend(stops.into_iter().map(|name| (name, TEXT_COLOR)));
+                } else {
+                    let half = ROUTE_STOPS_SHOWN / 2;
+                    let left_out = stops.len() - 2 * half;
+                    rows.extend(stops[..half].iter().map(|name| (name.clone(), TEXT_COLOR)));
+                    rows.push((format!("({} more)", left_out), TEXT_COLOR));
+                    rows.extend(
+                        stops[stops.len() - half..]
+                            .iter()
+                            .map(|name| (name.clone(), TEXT_COLOR)),
+                    );
+                }
+            }
+            None if plan.origin.is_some() && plan.destination.is_some() => {
+                rows.push(("No route found".to_owned(), TEXT_COLOR));
+            }
+            None => {}
+        }
+
+        rows
+    }
+
+    /// Draw the `World` state to the frame buffer, which should be the size of the current
+    /// viewport.
     pub fn draw(&mut self, buffer: &mut [u8]) {
-        let mut effect_tile_map: HashMap<Tile, &[u8; 3]> = HashMap::new();
+        if self.base_map_stale {
+            self.update_base_map();
+        }
+        self.effect_map.reset(&self.map_frame);
+
+        // Highlights go under the effects, so trains can still be seen passing through
+        for tile in self.route_highlight() {
+            self.effect_map.set(tile, Some(ROUTE_COLOR));
+        }
+        let mut highlighted: Vec<StationGroupId> = self.hovered.into_iter().collect();
+        highlighted.extend(self.selected.filter(|id| Some(*id) != self.hovered));
+        for group_id in highlighted {
+            for tile in self.station_highlight(group_id) {
+                self.effect_map.set(tile, Some(HIGHLIGHT_COLOR));
+            }
+        }
 
         // Process lower priority effects first so their colors will be overwritten with higher
-        // priority effects if necessary
-        self.effect_manager.effects.sort_by_key(|e| e.priority());
-        for effect in &self.effect_manager.effects {
+        // priority effects if necessary. Sort a separate list, since the order of the effects
+        // themselves is part of the simulation state, and drawing shouldn't change it.
+        let mut effects: Vec<&dyn Effect> = self
+            .effect_manager
+            .effects
+            .iter()
+            .map(|e| e.as_ref())
+            .collect();
+        effects.sort_by_key(|e| e.priority());
+        for effect in effects {
             for (tile, color) in effect.get_colors(&self.map_frame) {
-                effect_tile_map.insert(tile, color);
+                self.effect_map.set(tile, Some(*color));
             }
         }
 
         let font_level = self.map_frame.font_level();
+        let width = self.map_frame.viewport.width as usize;
+
+        // Track stripes depend on the tile position
+        let columns = self.map_frame.tile_columns();
+        let rows = self.map_frame.tile_rows();
 
         for (i, pixel) in buffer.chunks_exact_mut(4).enumerate() {
             // x and y are the coordinates of the screen pixel in question
-            let x = (i % SCREEN_WIDTH as usize) as i16;
-            let y = (i / SCREEN_WIDTH as usize) as i16;
-
-            // Translate the pixel position to a map coordinate
-            let coord = self.map_frame.get_map_coord(x, y);
+            let x = i % width;
+            let y = i / width;
 
-            // Look up the tile that that map coordinate is in
-            let tile = self.map_frame.get_tile(coord);
-
-            // Determine the color for the tile, starting with the highest priority
-            let color: &[u8; 3] = {
-                if let Some(effect_color) = effect_tile_map.get(&tile) {
-                    *effect_color
-                } else {
-                    match self.base_map.get(&tile) {
-                        Some(TileStatus::Font(font_index)) => &FONT_COLORS[*font_index][font_level],
-                        Some(TileStatus::Station(_)) | Some(TileStatus::StationShadow) => {
-                            &STATION_COLOR
+            // Determine the color for the pixel's tile, starting with the highest priority.
+            // Station names go over the background and tracks, but under stations and effects.
+            let color: [u8; 3] = match self.effect_map.at_pixel(x, y) {
+                Some(effect_color) => effect_color,
+                None => {
+                    let label = self.label_map.at_pixel(x, y);
+                    let (color, label_alpha) = match self.base_map.at_pixel(x, y) {
+                        TileStatus::Station(_) | TileStatus::StationShadow => (STATION_COLOR, 0),
+                        TileStatus::Track(line_set, coverage) => {
+                            let track_color = self
+                                .track_line_sets
+                                .color(line_set, columns[x].0 + rows[y].0);
+                            (
+                                blend(&BACKGROUND_COLOR, track_color, coverage),
+                                label_over_track(label.coverage, coverage),
+                            )
                         }
-                        Some(TileStatus::Track) => &TRACK_COLOR,
-                        None => &BACKGROUND_COLOR,
-                    }
+                        TileStatus::Empty => (BACKGROUND_COLOR, label.coverage),
+                    };
+                    let label_color = &FONT_COLORS[label.font_index as usize][font_level];
+                    blend(&color, label_color, label_alpha)
                 }
             };
 
@@ -324,11 +1214,37 @@ impl World {
 
             pixel.copy_from_slice(&with_alpha);
         }
+
+        let viewport = self.map_frame.viewport;
+        if let Some(info) = self.selected.and_then(|id| self.station_info(id)) {
+            info.draw(buffer, viewport, &self.font_manager);
+        }
+        // The search box goes where the route is listed, since it's often used to plan one
+        let rows = match &self.search {
+            Some(search) => self.search_rows(search),
+            None if !self.route_plan.is_empty() => self.route_rows(),
+            None => Vec::new(),
+        };
+        draw_panel(
+            buffer,
+            viewport,
+            &self.font_manager,
+            &rows,
+            Corner::BottomLeft,
+        );
     }
 
     /// Run one step of the world's evolution for every frame (1/60 of a second) that has elapsed
-    /// since the last call to this function
+    /// since the last call to this function. Nothing happens while the clock is paused.
     pub fn update(&mut self, dt: &Duration) {
+        // The view keeps moving while the clock is paused
+        self.update_camera(dt);
+
+        if self.clock.is_paused() {
+            self.dt = Duration::default();
+            return;
+        }
+
         let one_frame = Duration::new(0, 16_666_667);
         self.dt += *dt;
 
@@ -338,7 +1254,244 @@ impl World {
         }
     }
 
+    /// Run the given number of steps right away, regardless of how much time has passed or
+    /// whether the clock is paused
+    pub fn step_frames(&mut self, frames: u32) {
+        for _ in 0..frames {
+            self.step();
+        }
+    }
+
     fn step(&mut self) {
-        self.effect_manager.update();
+        self.clock.tick();
+        self.effect_manager.update(&self.clock);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        data::{load_network, Line, Strictness},
+        snapshot,
+    };
+    use indexmap::IndexMap;
+
+    /// The effects and the pixels of the whole map and of central Tokyo, after running a new world
+    /// with the given seed for a while
+    fn run(seed: u64) -> (String, Vec<u8>, Vec<u8>) {
+        let network = load_network(None, Strictness::Strict).unwrap().data;
+        let mut world = World::new(network, seed);
+        world.step_frames(300);
+
+        let effects = format!("{:?}", world.effects());
+        let japan = snapshot::render(&mut world, 400, 300);
+        world.set_map_frame(MapFrame::new(
+            MapCoord {
+                long: 139.68.into(),
+                lat: 35.72.into(),
+            },
+            MapCoord {
+                long: 139.8.into(),
+                lat: 35.64.into(),
+            },
+            Viewport {
+                width: 400,
+                height: 300,
+            },
+        ));
+        let tokyo = snapshot::render(&mut world, 400, 300);
+
+        (effects, japan, tokyo)
+    }
+
+    const RED: [u8; 3] = [0xFF, 0, 0];
+    const BLUE: [u8; 3] = [0, 0, 0xFF];
+
+    /// A network with just a red line 1, a blue line 2, and line 3 without a color
+    fn lines_network() -> Network {
+        let mut lines = IndexMap::with_hasher(RandomState::new());
+        for (id, color) in [(1, Some(RED)), (2, Some(BLUE)), (3, None)] {
+            lines.insert(
+                LineId(id),
+                Line {
+                    id: LineId(id),
+                    name: id.to_string(),
+                    company_id: None,
+                    color,
+                },
+            );
+        }
+        Network::new(
+            IndexMap::with_hasher(RandomState::new()),
+            HashMap::with_hasher(RandomState::new()),
+            lines,
+            IndexMap::with_hasher(RandomState::new()),
+        )
+    }
+
+    #[test]
+    fn line_sets_are_the_same_in_any_order() {
+        let network = lines_network();
+        let mut sets = TrackLineSets::default();
+
+        let both = sets.intern(&[LineId(2), LineId(1)], &network);
+        assert_eq!(sets.intern(&[LineId(1), LineId(2)], &network), both);
+        assert_eq!(
+            sets.intern(&[LineId(1), LineId(2), LineId(1)], &network),
+            both
+        );
+
+        let red = sets.intern(&[LineId(1)], &network);
+        let blue = sets.intern(&[LineId(2)], &network);
+        assert_ne!(red, both);
+        assert_eq!(sets.merge(blue, red, &network), both);
+        assert_eq!(sets.merge(red, red, &network), red);
+    }
+
+    #[test]
+    fn stripes_go_through_the_lines_by_id() {
+        let network = lines_network();
+        let mut sets = TrackLineSets::default();
+        let both = sets.intern(&[LineId(2), LineId(1)], &network);
+
+        // Each stripe is TRACK_STRIPE_WIDTH tiles wide, starting with the lowest line ID
+        let colors: Vec<[u8; 3]> = (-2..6).map(|d| *sets.color(both, d)).collect();
+        assert_eq!(colors, vec![BLUE, BLUE, RED, RED, BLUE, BLUE, RED, RED]);
+
+        let red = sets.intern(&[LineId(1)], &network);
+        assert!((0..4).all(|d| *sets.color(red, d) == RED));
+    }
+
+    #[test]
+    fn lines_without_a_color_are_drawn_in_the_track_color() {
+        let network = lines_network();
+        let mut sets = TrackLineSets::default();
+
+        let uncolored = sets.intern(&[LineId(3)], &network);
+        assert_eq!(*sets.color(uncolored, 0), TRACK_COLOR);
+
+        // Lines that aren't in the network at all, and tracks with no lines
+        let unknown = sets.intern(&[LineId(99)], &network);
+        assert_eq!(*sets.color(unknown, 0), TRACK_COLOR);
+        let none = sets.intern(&[], &network);
+        assert_eq!(*sets.color(none, 0), TRACK_COLOR);
+
+        let mixed = sets.intern(&[LineId(3), LineId(1)], &network);
+        assert_eq!(*sets.color(mixed, 0), RED);
+        assert_eq!(*sets.color(mixed, TRACK_STRIPE_WIDTH), TRACK_COLOR);
+    }
+
+    #[test]
+    fn tiles_mostly_covered_by_two_tracks_are_shared() {
+        let network = lines_network();
+        let mut sets = TrackLineSets::default();
+        let red = sets.intern(&[LineId(1)], &network);
+        let blue = sets.intern(&[LineId(2)], &network);
+        let both = sets.intern(&[LineId(1), LineId(2)], &network);
+
+        let map_frame = MapFrame::default();
+        let mut base_map = TileRaster::new();
+        base_map.reset(&map_frame);
+        let tile = |i: usize| Tile {
+            x: map_frame.tile_columns()[i],
+            y: map_frame.tile_rows()[0],
+        };
+
+        // Both run through most of the tile
+        add_track_tile(&mut base_map, &mut sets, &network, tile(0), red, 200);
+        add_track_tile(&mut base_map, &mut sets, &network, tile(0), blue, 150);
+        assert_eq!(base_map.get(tile(0)), Some(TileStatus::Track(both, 200)));
+
+        // One only clips the tile, which keeps the line that covers more of it
+        add_track_tile(&mut base_map, &mut sets, &network, tile(1), red, 200);
+        add_track_tile(&mut base_map, &mut sets, &network, tile(1), blue, 40);
+        assert_eq!(base_map.get(tile(1)), Some(TileStatus::Track(red, 200)));
+        add_track_tile(&mut base_map, &mut sets, &network, tile(2), red, 40);
+        add_track_tile(&mut base_map, &mut sets, &network, tile(2), blue, 200);
+        assert_eq!(base_map.get(tile(2)), Some(TileStatus::Track(blue, 200)));
+
+        // Stations stay on top
+        base_map.set(tile(3), TileStatus::StationShadow);
+        add_track_tile(&mut base_map, &mut sets, &network, tile(3), red, 255);
+        assert_eq!(base_map.get(tile(3)), Some(TileStatus::StationShadow));
+    }
+
+    #[test]
+    fn colors_are_blended_by_alpha() {
+        let under = [0x10, 0x80, 0xF0];
+        let over = [0xF0, 0x00, 0x10];
+        assert_eq!(blend(&under, &over, 0), under);
+        assert_eq!(blend(&under, &over, 255), over);
+        assert_eq!(blend(&under, &over, 128), [0x80, 0x40, 0x80]);
+        assert_eq!(blend(&[0; 3], &[0xFF; 3], 128), [128; 3]);
+    }
+
+    #[test]
+    fn track_coverage_falls_off_across_its_edge() {
+        let map_frame = MapFrame::default();
+        let track_tiles = TrackTiles::new(&map_frame);
+        let start = MapCoord {
+            long: 137.0.into(),
+            lat: 36.0.into(),
+        };
+        let end = MapCoord {
+            long: 139.0.into(),
+            lat: 36.0.into(),
+        };
+        let width = 3;
+
+        // Look across the track halfway along it
+        let (start_x, y) = map_frame.plane_tile_position(map_frame.project(start));
+        let (end_x, _) = map_frame.plane_tile_position(map_frame.project(end));
+        let x = ((start_x + end_x) / 2.0).round() as i32;
+        let mut across: Vec<(f32, u8)> = track_tiles
+            .smooth_piece(&map_frame, start, end, width)
+            .unwrap()
+            .filter(|(tile, _)| tile.x.0 == x)
+            .map(|(tile, coverage)| ((tile.y.0 as f32 - y).abs(), coverage))
+            .collect();
+        across.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        // Fully covered in the middle, partly at the edge, and not at all past it
+        assert!(across.len() >= width as usize);
+        assert_eq!(across[0].1, 255);
+        assert!(across.windows(2).all(|pair| pair[0].1 >= pair[1].1));
+        assert!(across.iter().any(|(_, coverage)| *coverage < 255));
+        assert!(across
+            .iter()
+            .all(|(distance, _)| *distance < width as f32 / 2.0 + 0.5));
+    }
+
+    #[test]
+    fn names_over_tracks_let_the_track_show_through() {
+        let over_track = (255.0 * LABEL_OVER_TRACK_OPACITY).round() as u8;
+        assert_eq!(label_over_track(255, 255), over_track);
+        assert_eq!(label_over_track(255, 0), 255);
+
+        // Over the edge of a track, a name is somewhere in between
+        let edge = label_over_track(255, 128);
+        assert!(over_track < edge && edge < 255);
+
+        // Where the name only covers part of the tile, it's fainter still
+        assert!(label_over_track(128, 255) < over_track);
+        assert_eq!(label_over_track(0, 255), 0);
+    }
+
+    #[test]
+    fn same_seed_runs_the_same() {
+        let (effects, japan, tokyo) = run(1);
+        let (other_effects, other_japan, other_tokyo) = run(1);
+        assert_eq!(effects, other_effects);
+        assert!(japan == other_japan);
+        assert!(tokyo == other_tokyo);
+    }
+
+    #[test]
+    fn different_seeds_run_differently() {
+        let (effects, japan, _) = run(1);
+        let (other_effects, other_japan, _) = run(2);
+        assert_ne!(effects, other_effects);
+        assert!(japan != other_japan);
     }
 }